@@ -0,0 +1,98 @@
+use std::path::Path;
+use std::rc::Rc;
+
+use crate::demo::play_headless;
+use crate::field::{developer_record, BrickKind, GameState};
+use crate::loader::Loader;
+use crate::replay::{Action, ReplayEngine};
+use crate::sim::BoardSim;
+use crate::solver::{min_throws, solve, MAX_BOARDS};
+
+// levels per line of the records table
+const RECORDS_PER_LINE: usize = 10;
+
+// Prints the difficulty curve of a level pack as CSV: the optimal number of throws
+// for every level and the level sizes. Empty `optimal_throws` means that the solver
+// has not found a solution
+pub fn analyze_pack(path: &Path) -> Result<(), String> {
+    let loader = Loader::from_file(path).map_err(|e| e.to_string())?;
+    println!("level,optimal_throws,bricks,puzzle_width,puzzle_height,corner_lines");
+    for idx in 0..loader.level_count() {
+        let lvl = loader.level(idx);
+        let bricks = lvl.puzzle.iter().flatten().filter(|b| **b != BrickKind::None).count();
+        let width = lvl.puzzle.iter().map(|l| l.len()).max().unwrap_or(0);
+        let optimal = min_throws(&lvl, MAX_BOARDS).map_or(String::new(), |n| n.to_string());
+        println!("{},{},{},{},{},{}", idx, optimal, bricks, width, lvl.puzzle.len(), lvl.corner.len());
+    }
+    Ok(())
+}
+
+// Prints the `RECORDS` table for the built-in levels with the optimal number of throws found by
+// the solver. Every solution is checked by playing it from the start of the level. If the solver
+// cannot solve a level, the current record is kept and a warning is printed to stderr
+pub fn print_records() {
+    let loader = Loader::new();
+    let counts: Vec<u32> = (0..loader.level_count())
+        .map(|idx| {
+            let lvl = loader.level(idx);
+            let solution = solve(BoardSim::new(&lvl), MAX_BOARDS).filter(|acts| {
+                let mut sim = BoardSim::new(&lvl);
+                for act in acts.iter() {
+                    sim.apply(*act);
+                }
+                sim.state == GameState::Winner
+            });
+            match solution {
+                Some(acts) => acts.iter().filter(|a| matches!(a, Action::Throw)).count() as u32,
+                None => {
+                    eprintln!("Level {}: no solution found, the current record is kept", idx);
+                    developer_record(idx).unwrap_or(0)
+                }
+            }
+        })
+        .collect();
+
+    println!("static RECORDS: &[u32] = &[");
+    if let Some(demo) = counts.first() {
+        println!("    {}, // demo level", demo);
+    }
+    let levels = counts.len().saturating_sub(1);
+    for (line, chunk) in counts[1.min(counts.len())..].chunks(RECORDS_PER_LINE).enumerate() {
+        let first = line * RECORDS_PER_LINE + 1;
+        let nums: Vec<String> = chunk.iter().map(|n| n.to_string()).collect();
+        println!("    {}, // {}-{}", nums.join(", "), first, (first + RECORDS_PER_LINE - 1).min(levels));
+    }
+    println!("];");
+}
+
+// Checks that all built-in levels are valid, then plays every saved replay of the built-in
+// levels on a headless game field, frame by frame in the same way the demo shows it, and
+// prints whether it still solves its level. Problems are reported to stderr. Returns false
+// if anything fails
+pub fn verify_replays() -> bool {
+    let loader = match Loader::std_levels() {
+        Ok(ld) => ld,
+        Err(e) => {
+            eprintln!("Invalid built-in levels: {}", e);
+            return false;
+        }
+    };
+    println!("All {} built-in levels are valid", loader.level_count());
+    let loader = Rc::new(loader);
+    let mut failed = 0;
+    for idx in 0..loader.level_count() {
+        let mut replay = ReplayEngine::new();
+        replay.load(idx);
+        if !replay.is_loaded() {
+            continue;
+        }
+        let field = play_headless(&mut replay, loader.clone(), idx);
+        if field.state == GameState::Winner || field.state == GameState::Completed {
+            println!("Level {}: solved in {} throws", idx, field.score);
+        } else {
+            eprintln!("Level {}: the replay ends with '{}' after {} throws", idx, field.state, field.score);
+            failed += 1;
+        }
+    }
+    failed == 0
+}
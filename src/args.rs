@@ -0,0 +1,104 @@
+use std::env;
+
+// Command line options
+#[derive(Default)]
+pub struct Args {
+    pub serve: Option<u16>,                     // stream the game to spectators on this port
+    pub watch: Option<String>,                  // HOST:PORT of a game to watch
+    pub restore_replay: Option<usize>,          // restore the replay of the level from its backup and exit
+    pub read_only: bool,                        // never write progress, replays and settings to disk
+    pub diagnose: bool,                         // print information about the game environment and exit
+    pub import_replay: Option<String>,          // save a replay file for the level with the same board and exit
+    pub export_replay: Option<(usize, String)>, // write the replay of the level as text and exit
+    pub export_csv: Option<String>,             // write level statistics to a CSV file and exit
+    pub export_json: Option<String>,            // write level statistics to a JSON file and exit
+    pub analyze_pack: Option<String>,           // print the difficulty curve of a level pack and exit
+    pub solve_records: bool,                    // print optimal results of the built-in levels and exit
+    pub verify_replays: bool,                   // check that saved replays solve their levels and exit
+    pub endless: Option<u32>,                   // start endless mode with the seed
+    pub level: Option<usize>,                   // start playing the level at once
+    pub portable: bool,                         // portable mode even without `config.toml` beside the binary
+    pub replay: Option<String>,                 // watch a replay file
+    pub windowed: Option<(i32, i32)>,           // window size, overrides the settings and fullscreen
+}
+
+impl Args {
+    pub fn parse() -> Result<Args, String> {
+        let mut args = Args::default();
+        let mut it = env::args().skip(1);
+        while let Some(arg) = it.next() {
+            match arg.as_str() {
+                "--serve" => {
+                    let port = it.next().ok_or("--serve requires a port")?;
+                    args.serve = Some(port.parse().map_err(|_| format!("invalid port: {}", port))?);
+                }
+                "--watch" => {
+                    args.watch = Some(it.next().ok_or("--watch requires HOST:PORT")?);
+                }
+                "--restore-replay" => {
+                    let lvl = it.next().ok_or("--restore-replay requires a level number")?;
+                    args.restore_replay = Some(lvl.parse().map_err(|_| format!("invalid level number: {}", lvl))?);
+                }
+                "--diagnose" => args.diagnose = true,
+                "--solve-records" => args.solve_records = true,
+                "--verify-replays" => args.verify_replays = true,
+                "--read-only" => args.read_only = true,
+                "--export-csv" => {
+                    args.export_csv = Some(it.next().ok_or("--export-csv requires a file name")?);
+                }
+                "--export-json" => {
+                    args.export_json = Some(it.next().ok_or("--export-json requires a file name")?);
+                }
+                "--analyze-pack" => {
+                    args.analyze_pack = Some(it.next().ok_or("--analyze-pack requires a file name")?);
+                }
+                "--endless" => {
+                    let seed = it.next().ok_or("--endless requires a seed")?;
+                    args.endless = Some(seed.parse().map_err(|_| format!("invalid seed: {}", seed))?);
+                }
+                "--level" => {
+                    let lvl = it.next().ok_or("--level requires a level number")?;
+                    args.level = match lvl.parse() {
+                        Ok(n) if n > 0 => Some(n),
+                        _ => return Err(format!("invalid level number: {}", lvl)),
+                    };
+                }
+                "--portable" => args.portable = true,
+                "--replay" => {
+                    args.replay = Some(it.next().ok_or("--replay requires a file name")?);
+                }
+                "--windowed" => {
+                    let size = it.next().ok_or("--windowed requires a size WIDTHxHEIGHT")?;
+                    args.windowed = Some(parse_size(&size).ok_or_else(|| format!("invalid window size: {}", size))?);
+                }
+                "--export-replay" => {
+                    let lvl = it.next().ok_or("--export-replay requires a level number")?;
+                    let lvl = lvl.parse().map_err(|_| format!("invalid level number: {}", lvl))?;
+                    let path = it.next().ok_or("--export-replay requires a file name")?;
+                    args.export_replay = Some((lvl, path));
+                }
+                "--import-replay" => {
+                    args.import_replay = Some(it.next().ok_or("--import-replay requires a file name")?);
+                }
+                _ => return Err(format!("unknown option: {}", arg)),
+            }
+        }
+        let starts = [args.endless.is_some(), args.watch.is_some(), args.level.is_some(), args.replay.is_some()];
+        if starts.iter().filter(|s| **s).count() > 1 {
+            return Err("--endless, --watch, --level, and --replay cannot be used together".to_string());
+        }
+        if args.serve.is_some() && args.watch.is_some() {
+            return Err("--serve and --watch cannot be used together".to_string());
+        }
+        Ok(args)
+    }
+}
+
+// parses window size in format `WIDTHxHEIGHT`, e.g. `1024x768`
+fn parse_size(s: &str) -> Option<(i32, i32)> {
+    let (w, h) = s.split_once(['x', 'X'])?;
+    match (w.parse(), h.parse()) {
+        (Ok(w), Ok(h)) if w > 0 && h > 0 => Some((w, h)),
+        _ => None,
+    }
+}
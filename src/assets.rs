@@ -0,0 +1,93 @@
+use log::{error, warn};
+use tetra::graphics::mesh::Mesh;
+use tetra::graphics::Texture;
+use tetra::Context;
+
+use crate::audio::{device_available, Sfx};
+use crate::common::theme_path;
+use crate::consts::BRICK_SIZE;
+use crate::stars::star_mesh;
+
+// A theme image of blocks is a column of BRICK_SIZE x BRICK_SIZE cells in the same order as the
+// built-in one: a wall, blocks of kinds 1 to 6, a joker, and blocks of kinds 7 to 9
+// (see `brick2shift`)
+const THEME_BRICK_CELLS: i32 = 11;
+
+// All images and sounds embedded into the binary. Images are decoded once at start and shared by
+// all scenes.
+// `Texture` is a reference-counted handle, so a scene can clone any texture cheaply
+pub struct Assets {
+    pub arrows: Texture,     // animated arrow that shows where player's block hits
+    pub bricks: Texture,     // walls and all kinds of blocks
+    pub background: Texture, // default background tile of the play area
+    pub numbers: Texture,    // digits 0..9 for `TextNumber`
+    pub font: Texture,       // letters, digits and punctuation for `BitmapText`
+    pub level_no: Texture,
+    pub throws: Texture,
+    pub attempts: Texture,
+    pub solved: Texture,
+    pub plates: Texture, // game state plates (level solved, no moves etc)
+    pub progress: Texture,
+    pub rules: Texture,
+    pub menu_arrow: Texture,
+    pub menu_items: Texture,
+    pub star: Mesh,  // level rating star
+    pub audio: bool, // sounds can be played. false - no sound device, the game is silent
+    pub sfx: Sfx,
+    pub theme: String, // the theme the images are loaded from. Empty - built-in images only
+}
+
+impl Assets {
+    // `theme` is the name of a directory with images that replace the built-in blocks and
+    // background. Empty - built-in images only
+    pub fn new(ctx: &mut Context, theme: &str) -> tetra::Result<Assets> {
+        let audio = device_available(ctx);
+        let mut assets = Assets {
+            arrows: Texture::from_encoded(ctx, include_bytes!("../assets/arrows.png"))?,
+            bricks: Texture::from_encoded(ctx, include_bytes!("../assets/bricks.png"))?,
+            background: Texture::from_encoded(ctx, include_bytes!("../assets/background.png"))?,
+            numbers: Texture::from_encoded(ctx, include_bytes!("../assets/numbers.png"))?,
+            font: Texture::from_encoded(ctx, include_bytes!("../assets/font.png"))?,
+            level_no: Texture::from_encoded(ctx, include_bytes!("../assets/level_no.png"))?,
+            throws: Texture::from_encoded(ctx, include_bytes!("../assets/throws.png"))?,
+            attempts: Texture::from_encoded(ctx, include_bytes!("../assets/attempts.png"))?,
+            solved: Texture::from_encoded(ctx, include_bytes!("../assets/solved.png"))?,
+            plates: Texture::from_encoded(ctx, include_bytes!("../assets/all_plates.png"))?,
+            progress: Texture::from_encoded(ctx, include_bytes!("../assets/progress.png"))?,
+            rules: Texture::from_encoded(ctx, include_bytes!("../assets/rules.png"))?,
+            menu_arrow: Texture::from_encoded(ctx, include_bytes!("../assets/menu_arrow.png"))?,
+            menu_items: Texture::from_encoded(ctx, include_bytes!("../assets/menu_items.png"))?,
+            star: star_mesh(ctx)?,
+            audio,
+            sfx: Sfx::new(audio),
+            theme: theme.to_string(),
+        };
+        if !theme.is_empty() {
+            assets.load_theme(ctx, theme);
+        }
+        Ok(assets)
+    }
+
+    // Replaces blocks and background with images from the theme directory. A missing or
+    // invalid image is reported to stderr and the built-in one is kept
+    fn load_theme(&mut self, ctx: &mut Context, theme: &str) {
+        let dir = theme_path(theme);
+        let path = dir.join("bricks.png");
+        match Texture::new(ctx, &path) {
+            Ok(tx) => {
+                let (w, h) = (BRICK_SIZE as i32, BRICK_SIZE as i32 * THEME_BRICK_CELLS);
+                if tx.width() == w && tx.height() == h {
+                    self.bricks = tx;
+                } else {
+                    warn!("Invalid theme image {:?}: its size must be {}x{}", path, w, h);
+                }
+            }
+            Err(e) => error!("Failed to load theme image {:?}: {:?}", path, e),
+        }
+        let path = dir.join("background.png");
+        match Texture::new(ctx, &path) {
+            Ok(tx) => self.background = tx,
+            Err(e) => error!("Failed to load theme image {:?}: {:?}", path, e),
+        }
+    }
+}
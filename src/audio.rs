@@ -0,0 +1,269 @@
+use std::cell::Cell;
+use std::rc::Rc;
+
+use log::{error, warn};
+use tetra::audio::{Sound, SoundInstance};
+use tetra::Context;
+
+use crate::consts::FPS;
+use crate::field::BrickKind;
+
+const THROW_WAV: &[u8] = include_bytes!("../assets/sfx_throw.wav");
+
+// pitch of the sound of a cleared block for every kind in the order of `BrickKind` values: the
+// notes of a major pentatonic scale. A wall and a joker are never cleared
+const MATCH_PITCH: [f32; 11] = [1.0, 1.0, 1.125, 1.25, 1.5, 1.667, 2.0, 1.0, 2.25, 2.5, 3.0];
+
+// duration of the crossfade between music tracks
+const MUSIC_FADE_FRAMES: u32 = FPS;
+
+// Game events that have a sound effect
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SfxKind {
+    Throw,            // player's block is launched
+    Match(BrickKind), // player's block annihilates a matching block of the kind
+    Bounce,           // player's block flies back
+    Win,              // a level is solved
+    Lose,             // no moves left
+    Complete,         // the last level is solved
+}
+
+impl SfxKind {
+    // the index of the sample in `Sfx`
+    fn sample(self) -> usize {
+        match self {
+            SfxKind::Throw => 0,
+            SfxKind::Match(_) => 1,
+            SfxKind::Bounce => 2,
+            SfxKind::Win => 3,
+            SfxKind::Lose => 4,
+            SfxKind::Complete => 5,
+        }
+    }
+
+    // playback speed of the sample: all blocks share one sample, and every kind sounds at
+    // its own pitch
+    fn pitch(self) -> f32 {
+        match self {
+            SfxKind::Match(kind) => MATCH_PITCH.get(kind as usize).copied().unwrap_or(1.0),
+            _ => 1.0,
+        }
+    }
+}
+
+// Checks whether sounds can be played: `open` tries to start a sound. A machine without a sound
+// device must still run the game, so an error only turns audio off
+pub fn check_device<F: FnOnce() -> tetra::Result<()>>(open: F) -> bool {
+    match open() {
+        Ok(()) => true,
+        Err(e) => {
+            warn!("Audio is not available, the game runs without sound: {}", e);
+            false
+        }
+    }
+}
+
+// Tetra keeps running without a sound device and fails every playback. A silent sound is
+// played at start to find it out once
+pub fn device_available(ctx: &Context) -> bool {
+    check_device(|| Sound::from_encoded(THROW_WAV).play_with(ctx, 0.0, 1.0).map(|_| ()))
+}
+
+// Short sound effects embedded into the binary. `Sound` is a reference-counted handle, so the
+// bank is cheap to clone and every field can keep its own copy
+#[derive(Clone)]
+pub struct Sfx {
+    sounds: Vec<Sound>,
+    available: bool, // false - there is no sound device, nothing is played
+    // volume of all sound effects. It is shared by all copies of the bank, so a change in
+    // the settings applies to the fields that are already created
+    volume: Rc<Cell<f32>>,
+    // the first failed playback turns all sounds off, so a machine without a sound device
+    // does not log an error for every throw
+    muted: Rc<Cell<bool>>,
+}
+
+impl Sfx {
+    pub fn new(available: bool) -> Sfx {
+        let sounds = vec![
+            Sound::from_encoded(THROW_WAV),
+            Sound::from_encoded(include_bytes!("../assets/sfx_match.wav")),
+            Sound::from_encoded(include_bytes!("../assets/sfx_bounce.wav")),
+            Sound::from_encoded(include_bytes!("../assets/sfx_win.wav")),
+            Sound::from_encoded(include_bytes!("../assets/sfx_lose.wav")),
+            Sound::from_encoded(include_bytes!("../assets/sfx_complete.wav")),
+        ];
+        Sfx { sounds, available, volume: Rc::new(Cell::new(1.0)), muted: Rc::new(Cell::new(false)) }
+    }
+
+    pub fn set_volume(&self, volume: f32) {
+        self.volume.set(volume);
+    }
+
+    pub fn play(&self, ctx: &Context, kind: SfxKind) {
+        if !self.available || self.muted.get() {
+            return;
+        }
+        if let Err(e) = self.sounds[kind.sample()].play_with(ctx, self.volume.get(), kind.pitch()) {
+            error!("Failed to play a sound, sounds are turned off: {:?}", e);
+            self.muted.set(true);
+        }
+    }
+}
+
+// Background music tracks. The order is the order of tracks in `Music`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Track {
+    Menu,
+    Game,
+}
+
+// Progress of a crossfade from the previous track to the new one
+struct Crossfade {
+    frame: u32,
+    frames: u32,
+}
+
+impl Crossfade {
+    fn new(frames: u32) -> Crossfade {
+        Crossfade { frame: 0, frames: frames.max(1) }
+    }
+
+    fn step(&mut self) {
+        self.frame = (self.frame + 1).min(self.frames);
+    }
+
+    fn is_done(&self) -> bool {
+        self.frame >= self.frames
+    }
+
+    // volumes of the new and the previous tracks
+    fn volumes(&self) -> (f32, f32) {
+        let k = self.frame as f32 / self.frames as f32;
+        (k, 1.0 - k)
+    }
+}
+
+// Looping background music. The active scene requests a track, and a change of the track
+// fades the old one out while the new one fades in
+pub struct Music {
+    tracks: Vec<Sound>,
+    available: bool,
+    wanted: Option<Track>,                   // the track requested by the scenes
+    current: Option<(Track, SoundInstance)>, // the track that is playing or fading in
+    previous: Option<SoundInstance>,         // the track that is fading out
+    fade: Crossfade,
+    volume: f32,
+}
+
+impl Music {
+    pub fn new(available: bool) -> Music {
+        let tracks = vec![
+            Sound::from_encoded(include_bytes!("../assets/music_menu.wav")),
+            Sound::from_encoded(include_bytes!("../assets/music_game.wav")),
+        ];
+        let fade = Crossfade::new(MUSIC_FADE_FRAMES);
+        Music { tracks, available, wanted: None, current: None, previous: None, fade, volume: 1.0 }
+    }
+
+    pub fn set_volume(&mut self, volume: f32) {
+        self.volume = volume;
+    }
+
+    // the track to play from the next update. None - silence
+    pub fn request(&mut self, track: Option<Track>) {
+        self.wanted = track;
+    }
+
+    // starts the requested track and moves the crossfade one frame forward
+    pub fn update(&mut self, ctx: &Context) {
+        if !self.available {
+            return;
+        }
+        if self.wanted != self.current.as_ref().map(|(t, _)| *t) {
+            if let Some(prev) = self.previous.take() {
+                prev.stop();
+            }
+            self.previous = self.current.take().map(|(_, inst)| inst);
+            if let Some(track) = self.wanted {
+                match self.tracks[track as usize].repeat_with(ctx, 0.0, 1.0) {
+                    Ok(inst) => self.current = Some((track, inst)),
+                    Err(e) => {
+                        error!("Failed to play music, music is turned off: {:?}", e);
+                        self.available = false;
+                        self.stop();
+                        return;
+                    }
+                }
+            }
+            self.fade = Crossfade::new(MUSIC_FADE_FRAMES);
+        }
+        self.fade.step();
+        let (vol_in, vol_out) = self.fade.volumes();
+        if let Some((_, inst)) = &self.current {
+            inst.set_volume(vol_in * self.volume);
+        }
+        if let Some(prev) = self.previous.take() {
+            if self.fade.is_done() {
+                prev.stop();
+            } else {
+                prev.set_volume(vol_out * self.volume);
+                self.previous = Some(prev);
+            }
+        }
+    }
+
+    // stops all tracks at once, e.g. when the game is closing
+    pub fn stop(&mut self) {
+        if let Some((_, inst)) = self.current.take() {
+            inst.stop();
+        }
+        if let Some(prev) = self.previous.take() {
+            prev.stop();
+        }
+        self.wanted = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tetra::TetraError;
+
+    #[test]
+    fn missing_device_turns_audio_off() {
+        assert!(check_device(|| Ok(())));
+        assert!(!check_device(|| Err(TetraError::NoAudioDevice)));
+        assert!(!check_device(|| Err(TetraError::PlatformError("ALSA: no such device".to_string()))));
+    }
+
+    #[test]
+    fn crossfade_swaps_volumes() {
+        let mut fade = Crossfade::new(4);
+        assert_eq!(fade.volumes(), (0.0, 1.0));
+        fade.step();
+        fade.step();
+        assert_eq!(fade.volumes(), (0.5, 0.5));
+        assert!(!fade.is_done());
+        for _ in 0..10 {
+            fade.step();
+        }
+        assert!(fade.is_done());
+        assert_eq!(fade.volumes(), (1.0, 0.0));
+
+        // an instant fade is done after one frame
+        let mut fade = Crossfade::new(0);
+        fade.step();
+        assert!(fade.is_done());
+    }
+
+    #[test]
+    fn every_kind_has_its_own_pitch() {
+        use BrickKind::*;
+        let kinds = [K1, K2, K3, K4, K5, K6, K7, K8, K9];
+        let pitches: Vec<f32> = kinds.iter().map(|k| SfxKind::Match(*k).pitch()).collect();
+        assert!(pitches.windows(2).all(|w| w[0] < w[1]), "{:?}", pitches);
+        assert_eq!(SfxKind::Match(K1).sample(), SfxKind::Match(K9).sample());
+        assert_eq!(SfxKind::Throw.pitch(), 1.0);
+    }
+}
@@ -0,0 +1,179 @@
+use std::collections::HashMap;
+
+use tetra::input::{self, Key};
+use tetra::Context;
+
+use crate::config::Config;
+
+// logical actions the player can do with the keyboard
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Command {
+    Up,
+    Down,
+    Left,
+    Right,
+    Throw,
+    Confirm,
+    Help,
+    Hint,
+    SaveReplay,
+    Undo,
+    Restart,
+    Pause,
+    Back,
+    Preview,
+    SwitchLevel,
+    Ghost,
+    History,
+}
+
+impl Command {
+    const ALL: [Command; 17] = [
+        Command::Up,
+        Command::Down,
+        Command::Left,
+        Command::Right,
+        Command::Throw,
+        Command::Confirm,
+        Command::Help,
+        Command::Hint,
+        Command::SaveReplay,
+        Command::Undo,
+        Command::Restart,
+        Command::Pause,
+        Command::Back,
+        Command::Preview,
+        Command::SwitchLevel,
+        Command::Ghost,
+        Command::History,
+    ];
+
+    // the name of the command in the `keys` section of the config
+    pub fn name(self) -> &'static str {
+        match self {
+            Command::Up => "up",
+            Command::Down => "down",
+            Command::Left => "left",
+            Command::Right => "right",
+            Command::Throw => "throw",
+            Command::Confirm => "confirm",
+            Command::Help => "help",
+            Command::Hint => "hint",
+            Command::SaveReplay => "save_replay",
+            Command::Undo => "undo",
+            Command::Restart => "restart",
+            Command::Pause => "pause",
+            Command::Back => "back",
+            Command::Preview => "preview",
+            Command::SwitchLevel => "switch_level",
+            Command::Ghost => "ghost",
+            Command::History => "history",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Command> {
+        Command::ALL.iter().find(|c| c.name() == name).copied()
+    }
+}
+
+// converts a key name from the config (e.g, "Space" or "NumPadEnter") to a key
+pub fn key_by_name(name: &str) -> Option<Key> {
+    toml::Value::String(name.to_string()).try_into().ok()
+}
+
+// Every command can be bound to a few keys at a time. The command is triggered by any of them
+pub struct InputBindings {
+    keys: HashMap<Command, Vec<Key>>,
+}
+
+impl Default for InputBindings {
+    fn default() -> InputBindings {
+        let mut keys = HashMap::new();
+        keys.insert(Command::Up, vec![Key::Up, Key::W]);
+        keys.insert(Command::Down, vec![Key::Down, Key::S]);
+        keys.insert(Command::Left, vec![Key::Left, Key::A]);
+        keys.insert(Command::Right, vec![Key::Right, Key::D]);
+        keys.insert(Command::Throw, vec![Key::Space, Key::NumPadEnter]);
+        keys.insert(Command::Confirm, vec![Key::Space, Key::Enter, Key::NumPadEnter]);
+        keys.insert(Command::Help, vec![Key::F1]);
+        keys.insert(Command::Hint, vec![Key::H]);
+        keys.insert(Command::SaveReplay, vec![Key::F5]);
+        keys.insert(Command::Undo, vec![Key::Z]);
+        keys.insert(Command::Restart, vec![Key::R]);
+        keys.insert(Command::Pause, vec![Key::P, Key::Pause]);
+        keys.insert(Command::Back, vec![Key::Escape]);
+        keys.insert(Command::Preview, vec![Key::Tab]);
+        keys.insert(Command::SwitchLevel, vec![Key::Backspace]);
+        keys.insert(Command::Ghost, vec![Key::G]);
+        keys.insert(Command::History, vec![Key::L]);
+        InputBindings { keys }
+    }
+}
+
+impl InputBindings {
+    // default bindings with the commands from the config rebound. The config is
+    // validated when it is loaded, so invalid names are just skipped here
+    pub fn new(cfg: &Config) -> InputBindings {
+        let mut bindings = InputBindings::default();
+        for (name, keys) in cfg.keys.iter() {
+            if let Some(cmd) = Command::from_name(name) {
+                bindings.keys.insert(cmd, keys.iter().filter_map(|k| key_by_name(k)).collect());
+            }
+        }
+        bindings
+    }
+
+    pub fn keys(&self, cmd: Command) -> &[Key] {
+        self.keys.get(&cmd).map_or(&[], |k| k.as_slice())
+    }
+
+    // true if `check` is true for any key bound to the command
+    fn any_key<F: Fn(Key) -> bool>(&self, cmd: Command, check: F) -> bool {
+        self.keys(cmd).iter().any(|k| check(*k))
+    }
+
+    // true if any key bound to the command has been pressed
+    pub fn is_pressed(&self, ctx: &Context, cmd: Command) -> bool {
+        self.any_key(cmd, |k| input::is_key_pressed(ctx, k))
+    }
+
+    // true if any key bound to the command has been released
+    pub fn is_released(&self, ctx: &Context, cmd: Command) -> bool {
+        self.any_key(cmd, |k| input::is_key_released(ctx, k))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn any_bound_key_triggers_command() {
+        let keys = InputBindings::default();
+        for key in [Key::Up, Key::W] {
+            assert!(keys.any_key(Command::Up, |k| k == key));
+        }
+        assert!(!keys.any_key(Command::Up, |k| k == Key::S));
+        // a key may trigger a few commands
+        assert!(keys.any_key(Command::Throw, |k| k == Key::Space));
+        assert!(keys.any_key(Command::Confirm, |k| k == Key::Space));
+    }
+
+    #[test]
+    fn config_replaces_default_keys() {
+        let mut cfg = Config::default();
+        cfg.keys.insert("up".to_string(), vec!["I".to_string(), "NoSuchKey".to_string(), "NumPad8".to_string()]);
+        let keys = InputBindings::new(&cfg);
+        assert_eq!(keys.keys(Command::Up), &[Key::I, Key::NumPad8]);
+        assert!(!keys.any_key(Command::Up, |k| k == Key::Up));
+        assert_eq!(keys.keys(Command::Down), &[Key::Down, Key::S]);
+    }
+
+    #[test]
+    fn command_names_round_trip() {
+        for cmd in Command::ALL.iter() {
+            assert_eq!(Command::from_name(cmd.name()), Some(*cmd));
+        }
+        assert_eq!(Command::from_name("fly"), None);
+    }
+}
@@ -0,0 +1,112 @@
+use crate::consts::{BRICK_SIZE, INFO_WIDTH, MAX_SIZE, SCR_H, SCR_W};
+
+// the biggest puzzle side a level pack can declare with `size:`
+pub const MAX_BOARD_SIZE: usize = 10;
+
+// Dimensions of the board in blocks. All of them are derived from the biggest puzzle side:
+// the puzzle takes up to `max_size` bottom rows, the corner is above it, and the player's
+// block needs as much room at the right. The walls surround the play area, and the info panel
+// is at the right of it. The default puzzle side 7 gives the classic 21x16 board
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Board {
+    pub width: usize,    // width of the board including the info panel
+    pub height: usize,   // height of the board including the top and bottom walls
+    pub max_size: usize, // puzzle max dimension
+}
+
+impl Board {
+    pub fn new(max_size: usize) -> Board {
+        Board { width: max_size * 2 + 2 + INFO_WIDTH, height: max_size * 2 + 2, max_size }
+    }
+
+    // cells of the board are one-dimensional arrays, the function converts X,Y coordinate
+    // into position inside such an array
+    pub fn pos(&self, x: usize, y: usize) -> usize {
+        x + y * self.width
+    }
+
+    pub fn cells(&self) -> usize {
+        self.width * self.height
+    }
+
+    // width of the play area: everything at the left of the info panel
+    pub fn play_width(&self) -> usize {
+        self.width - INFO_WIDTH
+    }
+
+    // the lowest row the player's block can be in
+    pub fn bottom(&self) -> usize {
+        self.height - 2
+    }
+
+    // the first row that may contain puzzle blocks. A block thrown from a row above it
+    // always falls down
+    pub fn puzzle_top(&self) -> usize {
+        self.height - 1 - self.max_size
+    }
+
+    // The board bigger than the default one does not fit the screen, so it is drawn scaled
+    // down. The default board is drawn as is
+    pub fn scale(&self) -> f32 {
+        let sx = SCR_W / (self.width as f32 * BRICK_SIZE);
+        let sy = SCR_H / (self.height as f32 * BRICK_SIZE);
+        sx.min(sy).min(1.0)
+    }
+
+    // The scrolling view draws a big board at the width of the screen, so its blocks are
+    // bigger than with `scale`. The bottom of the board is below the screen then
+    pub fn scroll_scale(&self) -> f32 {
+        (SCR_W / (self.width as f32 * BRICK_SIZE)).min(1.0)
+    }
+
+    // the board drawn at the scale is taller than the screen
+    pub fn scrolls(&self, scale: f32) -> bool {
+        self.height as f32 * BRICK_SIZE * scale > SCR_H
+    }
+
+    // Moves the scroll offset of the board drawn at the scale by at most `max_step` points
+    // toward the offset that puts a block at `y` in the middle of the screen. Nothing above
+    // the top wall or below the bottom one is ever shown
+    pub fn follow(&self, offset: f32, y: f32, scale: f32, max_step: f32) -> f32 {
+        let view_h = SCR_H / scale;
+        let max_offset = (self.height as f32 * BRICK_SIZE - view_h).max(0.0);
+        let target = (y + (BRICK_SIZE - view_h) / 2.0).clamp(0.0, max_offset);
+        (offset + (target - offset).clamp(-max_step, max_step)).clamp(0.0, max_offset)
+    }
+}
+
+impl Default for Board {
+    fn default() -> Board {
+        Board::new(MAX_SIZE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn big_board_scrolls_to_player_row() {
+        // the default board fits the screen and never scrolls
+        let board = Board::default();
+        assert_eq!(board.scroll_scale(), 1.0);
+        assert!(!board.scrolls(board.scroll_scale()));
+        assert_eq!(board.follow(0.0, board.bottom() as f32 * BRICK_SIZE, 1.0, f32::MAX), 0.0);
+
+        // the biggest board fits the screen width, and its bottom is below the screen
+        let board = Board::new(MAX_BOARD_SIZE);
+        let scale = board.scroll_scale();
+        assert_eq!(board.width as f32 * BRICK_SIZE * scale, SCR_W);
+        assert!(board.scrolls(scale) && !board.scrolls(board.scale()));
+        let max_offset = board.height as f32 * BRICK_SIZE - SCR_H / scale;
+        let bottom = board.bottom() as f32 * BRICK_SIZE;
+        assert_eq!(board.follow(0.0, BRICK_SIZE, scale, f32::MAX), 0.0);
+        assert_eq!(board.follow(0.0, bottom, scale, f32::MAX), max_offset);
+        // the view moves to the row gradually and stops at it
+        assert_eq!(board.follow(0.0, bottom, scale, 8.0), 8.0);
+        assert_eq!(board.follow(max_offset - 4.0, bottom, scale, 8.0), max_offset);
+        assert_eq!(board.follow(max_offset, BRICK_SIZE, scale, 8.0), max_offset - 8.0);
+        // the whole board is shown when it is scaled down
+        assert_eq!(board.follow(max_offset, bottom, board.scale(), f32::MAX), 0.0);
+    }
+}
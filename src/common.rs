@@ -1,16 +1,71 @@
 use std::env::current_exe;
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, OnceLock};
 
+use log::info;
 use tetra::math::Vec2;
 
 use crate::consts::{BRICK_SIZE, INFO_WIDTH, SCR_H, SCR_W};
 
 const CONF_FILE: &str = "config.toml";
 const SCORE_FILE: &str = "hiscores.toml";
+const SESSION_FILE: &str = "session.toml";
+const LOG_FILE: &str = "unblocked.log";
+const CRASH_FILE: &str = "crash.log";
 const DEV_NAME: &str = "rionnag";
 const GAME_NAME: &str = "unblocked";
 const REPLAY_DIR: &str = "replays";
+const ASSET_DIR: &str = "assets";
+const LANG_DIR: &str = "lang";
+const THEME_DIR: &str = "themes";
+const PACK_EXT: &str = "puz";
+
+// read-only mode: progress is loaded but nothing is written back to disk
+static READ_ONLY: AtomicBool = AtomicBool::new(false);
+
+// tests that switch read-only mode or expect files to be written hold the lock, so they do not
+// run at the same time
+#[cfg(test)]
+pub static WRITE_LOCK: Mutex<()> = Mutex::new(());
+
+pub fn set_read_only(on: bool) {
+    READ_ONLY.store(on, Ordering::Relaxed);
+}
+
+// Every function that writes to disk must check it first. In read-only mode it logs
+// what has been skipped and returns true
+pub fn skip_write(what: &str) -> bool {
+    let ro = READ_ONLY.load(Ordering::Relaxed);
+    if ro {
+        info!("Read-only mode: {} not saved", what);
+    }
+    ro
+}
+
+// portable mode is detected once, at the first call of `is_portable`
+static PORTABLE: OnceLock<bool> = OnceLock::new();
+
+// name of the custom level pack being played. None - the built-in levels
+static PACK: Mutex<Option<String>> = Mutex::new(None);
+
+// Every custom level pack has its own hiscores, replays, and hardcore session
+pub fn set_pack(name: Option<String>) {
+    *PACK.lock().unwrap() = name;
+}
+
+pub fn current_pack() -> Option<String> {
+    PACK.lock().unwrap().clone()
+}
+
+// adds the name of the current custom pack to a file name: `hiscores.toml` -> `hiscores-mypack.toml`
+fn pack_file_name(name: &str) -> String {
+    match (current_pack(), name.rsplit_once('.')) {
+        (Some(pack), Some((stem, ext))) => format!("{}-{}.{}", stem, pack, ext),
+        _ => name.to_string(),
+    }
+}
 
 // Returns the number of digits in a number.
 // Used for small numbers like level number or the number of throws
@@ -58,12 +113,12 @@ fn user_config_path() -> PathBuf {
 //    For Windows it is %USER%/Appdata/Roaming/DEV_NAME/GAME_NAME/
 //    For Linux it is ~/.config/DEV_NAME/GAME_NAME/
 // In portable mode:
-//    For all OSes it is directory where the application binary is
+//    For all OSes it is directory where the application binary is. Config, hiscores,
+//    replays, and level packs are all there
 fn base_path() -> PathBuf {
     if is_portable() {
         exe_path()
     } else {
-        // exe_path() // TODO:
         let mut path = user_config_path();
         path.push(DEV_NAME);
         path.push(GAME_NAME);
@@ -75,23 +130,52 @@ fn base_path() -> PathBuf {
 // Returns if the application works in portable mode.
 // If there is CONF_FILE file in the directory where the application binary, it means the
 // portable mode is on
-fn is_portable() -> bool {
-    let mut p = exe_path();
-    p.push(CONF_FILE);
-    p.exists()
+pub fn is_portable() -> bool {
+    *PORTABLE.get_or_init(|| {
+        let mut p = exe_path();
+        p.push(CONF_FILE);
+        p.exists()
+    })
 }
 
-// there is no config yet, so comment the function out for now
-// pub fn config_path() -> PathBuf {
-//     let mut p = base_path();
-//     p.push(CONF_FILE);
-//     p
-// }
+// Turns portable mode on whether CONF_FILE exists or not. It must be called before any
+// path is requested
+pub fn force_portable() {
+    let _ = PORTABLE.set(true);
+}
+
+// Returns path to the file with game settings
+pub fn config_path() -> PathBuf {
+    let mut p = base_path();
+    p.push(CONF_FILE);
+    p
+}
 
 // Returns path to the file with hiscores
 pub fn score_path() -> PathBuf {
     let mut p = base_path();
-    p.push(SCORE_FILE);
+    p.push(pack_file_name(SCORE_FILE));
+    p
+}
+
+// Returns path to the file with the progress of the current hardcore run
+pub fn session_path() -> PathBuf {
+    let mut p = base_path();
+    p.push(pack_file_name(SESSION_FILE));
+    p
+}
+
+// Returns path to the log file
+pub fn log_path() -> PathBuf {
+    let mut p = base_path();
+    p.push(LOG_FILE);
+    p
+}
+
+// Returns path to the file with the error that closed the game
+pub fn crash_path() -> PathBuf {
+    let mut p = base_path();
+    p.push(CRASH_FILE);
     p
 }
 
@@ -99,10 +183,70 @@ pub fn score_path() -> PathBuf {
 pub fn replay_path() -> PathBuf {
     let mut path = base_path();
     path.push(REPLAY_DIR);
+    if let Some(pack) = current_pack() {
+        path.push(pack);
+    }
     ensure_path_exists(&path);
     path
 }
 
+// Returns all custom level packs (`*.puz` files in the game data directory) sorted by name
+pub fn find_packs() -> Vec<PathBuf> {
+    let mut packs: Vec<PathBuf> = match fs::read_dir(base_path()) {
+        Ok(entries) => entries
+            .filter_map(|e| e.ok().map(|e| e.path()))
+            .filter(|p| p.is_file() && p.extension().is_some_and(|ext| ext == PACK_EXT))
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    packs.sort();
+    packs
+}
+
+// Returns path to the directory with extra images (e.g, level backgrounds)
+pub fn asset_path() -> PathBuf {
+    let mut path = base_path();
+    path.push(ASSET_DIR);
+    path
+}
+
+// Returns path to the directory with images of the theme
+pub fn theme_path(theme: &str) -> PathBuf {
+    let mut path = base_path();
+    path.push(THEME_DIR);
+    path.push(theme);
+    path
+}
+
+// Returns the directories, relative to the game data directory, where an image declared by
+// a level (e.g, its background) is looked for: the theme, the assets of the level pack, and
+// the common assets
+fn level_image_dirs(theme: &str, pack: Option<&str>) -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+    if !theme.is_empty() {
+        dirs.push([THEME_DIR, theme].iter().collect());
+    }
+    if let Some(pack) = pack {
+        dirs.push([ASSET_DIR, pack].iter().collect());
+    }
+    dirs.push(PathBuf::from(ASSET_DIR));
+    dirs
+}
+
+// Returns path to the first existing image of a level with the name. None - the image is missing
+pub fn level_image_path(theme: &str, name: &str) -> Option<PathBuf> {
+    let base = base_path();
+    level_image_dirs(theme, current_pack().as_deref()).iter().map(|dir| base.join(dir).join(name)).find(|p| p.is_file())
+}
+
+// Returns path to the file with strings of the language
+pub fn lang_path(lang: &str) -> PathBuf {
+    let mut path = base_path();
+    path.push(LANG_DIR);
+    path.push(format!("{}.toml", lang));
+    path
+}
+
 // Creates all path's intermediate directories to make sure that the `p` exists.
 // Returns false if it failed to create required directories (may happen, e.g, on read-only media
 pub fn ensure_path_exists(p: &Path) -> bool {
@@ -135,3 +279,38 @@ pub fn clamp(value: u32, max: u32) -> u32 {
         value
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A config next to the binary turns portable mode on: hiscores and replays are kept there.
+    // Portable mode is detected once, so no other test may ask for a path
+    #[test]
+    fn portable_paths_are_next_to_binary() {
+        let exe = exe_path();
+        let (conf, replays) = (exe.join(CONF_FILE), exe.join(REPLAY_DIR));
+        let (had_conf, had_replays) = (conf.exists(), replays.exists());
+        if !had_conf {
+            fs::write(&conf, "").unwrap();
+        }
+        let portable = is_portable();
+        let paths = (config_path(), score_path(), replay_path());
+        if !had_conf {
+            let _ = fs::remove_file(&conf);
+        }
+        if !had_replays {
+            let _ = fs::remove_dir(&replays);
+        }
+        assert!(portable);
+        assert_eq!(paths, (conf, exe.join(SCORE_FILE), replays));
+    }
+
+    #[test]
+    fn level_images_are_looked_for_in_theme_and_pack() {
+        let dirs = level_image_dirs("dark", Some("mypack"));
+        let expected: Vec<PathBuf> = ["themes/dark", "assets/mypack", "assets"].iter().map(PathBuf::from).collect();
+        assert_eq!(dirs, expected);
+        assert_eq!(level_image_dirs("", None), vec![PathBuf::from("assets")]);
+    }
+}
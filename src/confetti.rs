@@ -0,0 +1,114 @@
+use std::f32::consts::PI;
+
+use tetra::graphics::mesh::{Mesh, ShapeStyle};
+use tetra::graphics::{Color, DrawParams, Rectangle};
+use tetra::math::Vec2;
+use tetra::Context;
+
+// the number of pieces in a burst
+const PIECES: usize = 60;
+// a burst lasts about a second
+const LIFETIME: u32 = 60;
+// size of a piece
+const PIECE_SIZE: f32 = 8.0;
+// pixels per frame added to the vertical speed of every piece
+const GRAVITY: f32 = 0.35;
+// range of the initial speed of a piece in pixels per frame
+const MIN_SPEED: f32 = 4.0;
+const MAX_SPEED: f32 = 11.0;
+const COLORS: [Color; 5] = [
+    Color::rgb(1.0, 0.8, 0.0),
+    Color::rgb(0.9, 0.2, 0.2),
+    Color::rgb(0.2, 0.7, 0.2),
+    Color::rgb(0.2, 0.5, 1.0),
+    Color::rgb(0.8, 0.3, 0.9),
+];
+
+struct Piece {
+    pos: Vec2<f32>,
+    speed: Vec2<f32>,
+    color: Color,
+    spin: f32, // rotation in radians per frame
+}
+
+// A short burst of colored pieces that fly up from a point and fall down, fading out.
+// It is only decoration: it does not affect the game and never blocks input
+pub struct Confetti {
+    pieces: Vec<Piece>,
+    frames: u32, // frames left until the burst ends
+    mesh: Option<Mesh>,
+}
+
+impl Confetti {
+    pub fn new() -> Self {
+        Confetti { pieces: Vec::new(), frames: 0, mesh: None }
+    }
+
+    // Starts a burst from the point. The pieces are scattered with a simple generator, so the
+    // same seed gives the same burst
+    pub fn start(&mut self, origin: Vec2<f32>, seed: u32) {
+        let mut rnd = seed.wrapping_mul(2_654_435_761).max(1);
+        let mut next = move || {
+            // xorshift32
+            rnd ^= rnd << 13;
+            rnd ^= rnd >> 17;
+            rnd ^= rnd << 5;
+            (rnd % 10_000) as f32 / 10_000.0
+        };
+        self.pieces = (0..PIECES)
+            .map(|i| {
+                // mostly upwards: from 30 to 150 degrees above the horizon
+                let angle = PI / 6.0 + next() * PI * 2.0 / 3.0;
+                let speed = MIN_SPEED + next() * (MAX_SPEED - MIN_SPEED);
+                Piece {
+                    pos: origin,
+                    speed: Vec2::new(angle.cos() * speed, -angle.sin() * speed),
+                    color: COLORS[i % COLORS.len()],
+                    spin: (next() - 0.5) * 0.4,
+                }
+            })
+            .collect();
+        self.frames = LIFETIME;
+    }
+
+    pub fn stop(&mut self) {
+        self.pieces.clear();
+        self.frames = 0;
+    }
+
+    pub fn update(&mut self) {
+        if self.frames == 0 {
+            return;
+        }
+        self.frames -= 1;
+        if self.frames == 0 {
+            self.pieces.clear();
+            return;
+        }
+        for p in self.pieces.iter_mut() {
+            p.pos += p.speed;
+            p.speed.y += GRAVITY;
+        }
+    }
+
+    pub fn draw(&mut self, ctx: &mut Context) -> tetra::Result {
+        if self.frames == 0 {
+            return Ok(());
+        }
+        if self.mesh.is_none() {
+            let rect = Rectangle::new(-PIECE_SIZE / 2.0, -PIECE_SIZE / 4.0, PIECE_SIZE, PIECE_SIZE / 2.0);
+            self.mesh = Some(Mesh::rectangle(ctx, ShapeStyle::Fill, rect)?);
+        }
+        if let Some(mesh) = &self.mesh {
+            // pieces fade out during the last third of the burst
+            let alpha = (self.frames as f32 * 3.0 / LIFETIME as f32).min(1.0);
+            let passed = (LIFETIME - self.frames) as f32;
+            for p in self.pieces.iter() {
+                let color = Color::rgba(p.color.r, p.color.g, p.color.b, alpha);
+                let dp = DrawParams::new().position(p.pos).rotation(p.spin * passed).color(color);
+                mesh.draw(ctx, dp);
+            }
+        }
+        Ok(())
+    }
+}
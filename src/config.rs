@@ -0,0 +1,287 @@
+use serde_derive::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{read_to_string, write};
+
+use log::{error, warn};
+use tetra::graphics::scaling::ScalingMode;
+use tetra::graphics::Color;
+
+use crate::bindings::{key_by_name, Command};
+use crate::common::{config_path, skip_write};
+use crate::consts::{FPS, SCR_H, SCR_W};
+
+// default distance between checkpoints in hardcore mode
+const DEF_CHECKPOINT_EVERY: usize = 5;
+// default background of the game screens
+pub const DEF_CLEAR_COLOR: Color = Color::rgb(0.094, 0.11, 0.16);
+// default duration of the fade between scenes in milliseconds
+const DEF_FADE_MS: u32 = 200;
+// default longest pause between actions of a saved replay in milliseconds
+const DEF_REPLAY_MAX_PAUSE: u32 = 3000;
+// the window cannot be smaller than this at start
+const MIN_WINDOW_W: i32 = 320;
+const MIN_WINDOW_H: i32 = 240;
+// names of ways to scale the game screen to the window in the order they are cycled
+const SCALING_MODES: [(&str, ScalingMode); 6] = [
+    ("show_all", ScalingMode::ShowAll),
+    ("pixel_perfect", ScalingMode::ShowAllPixelPerfect),
+    ("stretch", ScalingMode::Stretch),
+    ("crop", ScalingMode::Crop),
+    ("crop_pixel_perfect", ScalingMode::CropPixelPerfect),
+    ("fixed", ScalingMode::Fixed),
+];
+// default color of the bars around the game screen when the window aspect ratio differs
+const DEF_LETTERBOX_COLOR: Color = Color::BLACK;
+// default volumes: the music is quieter than sound effects
+const DEF_MASTER_VOLUME: f32 = 1.0;
+const DEF_MUSIC_VOLUME: f32 = 0.7;
+const DEF_SFX_VOLUME: f32 = 1.0;
+// default memory for the undo history in kilobytes
+const DEF_UNDO_MEMORY: usize = 64;
+
+// Game settings. All fields are optional in the config file
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    // failing a level restarts the run from the last checkpoint instead of the failed level
+    pub hardcore: bool,
+    // a checkpoint is made every N levels in hardcore mode
+    pub checkpoint_every: usize,
+    // highlight all rows the player's block can be thrown from
+    pub show_throwable: bool,
+    // show faded arrows of all rows, not only the row of player's block
+    pub show_all_arrows: bool,
+    // warn when the board becomes unsolvable before the player runs out of moves
+    pub deadlock_warning: bool,
+    // the game rules have been shown before the demo once
+    pub rules_seen: bool,
+    // colors in hex format (e.g, "#181C29"). None - default color
+    pub clear_color: Option<String>,
+    pub letterbox_color: Option<String>,
+    // a band sweeps over the play area before the `level solved` plate is shown
+    pub win_sweep: bool,
+    // player's block gently bobs while waiting for a throw
+    pub idle_animation: bool,
+    // confetti bursts from player's block when a level is solved
+    pub celebration: bool,
+    // a board bigger than the screen is drawn at the width of the screen and scrolls to follow
+    // player's block, and a minimap shows the whole board. false - the board is scaled down to fit
+    pub board_scroll: bool,
+    // the ghost of the saved best replay of a level starts by itself when the level is entered.
+    // Unlike the ghost turned on with a key, it does not count as help
+    pub auto_ghost: bool,
+    // memory in kilobytes the undo history of a level can take. The oldest throws are forgotten
+    // when it is full
+    pub undo_memory: usize,
+    // pause in milliseconds after player's block returns before it can be moved or thrown
+    pub throw_cooldown: u32,
+    // longest pause in milliseconds between actions of a saved replay, longer pauses are
+    // shortened to it. 0 - replays keep the original timing
+    pub replay_max_pause: u32,
+    // duration in milliseconds of the fade out and the fade in when a scene changes. 0 - instant
+    pub fade_ms: u32,
+    // size of the window at start. It is updated when the window is resized. The game screen
+    // is always SCR_W x SCR_H and it is scaled to fit the window
+    pub window_width: i32,
+    pub window_height: i32,
+    // borderless fullscreen. The window size above is the one restored when it is turned off
+    pub fullscreen: bool,
+    // how the game screen is scaled to the window: one of names in `SCALING_MODES`
+    pub scaling: String,
+    // load existing progress but never write anything to disk
+    pub read_only: bool,
+    // language of on-screen text drawn with the bitmap font (e.g, "en"). Empty - the built-in
+    // images with English text are shown
+    pub language: String,
+    // name of a directory in `themes` with images of blocks and background. Empty - built-in
+    pub theme: String,
+    // volumes from 0.0 (silence) to 1.0. The music and sound effects volumes are multiplied
+    // by the master one
+    pub master_volume: f32,
+    pub music_volume: f32,
+    pub sfx_volume: f32,
+    // keys bound to commands: command name -> key names. Missing commands keep default keys
+    pub keys: HashMap<String, Vec<String>>,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            hardcore: false,
+            checkpoint_every: DEF_CHECKPOINT_EVERY,
+            show_throwable: false,
+            show_all_arrows: false,
+            deadlock_warning: false,
+            rules_seen: false,
+            clear_color: None,
+            letterbox_color: None,
+            win_sweep: true,
+            idle_animation: true,
+            celebration: true,
+            board_scroll: false,
+            auto_ghost: false,
+            undo_memory: DEF_UNDO_MEMORY,
+            throw_cooldown: 0,
+            replay_max_pause: DEF_REPLAY_MAX_PAUSE,
+            fade_ms: DEF_FADE_MS,
+            window_width: SCR_W as i32,
+            window_height: SCR_H as i32,
+            fullscreen: false,
+            scaling: SCALING_MODES[0].0.to_string(),
+            read_only: false,
+            language: String::new(),
+            theme: String::new(),
+            master_volume: DEF_MASTER_VOLUME,
+            music_volume: DEF_MUSIC_VOLUME,
+            sfx_volume: DEF_SFX_VOLUME,
+            keys: HashMap::new(),
+        }
+    }
+}
+
+impl Config {
+    pub fn load() -> Config {
+        let path = config_path();
+        if !path.exists() {
+            return Config::default();
+        }
+
+        let data = match read_to_string(path) {
+            Ok(s) => s,
+            Err(_) => return Config::default(),
+        };
+
+        let mut cfg: Config = match toml::from_str(&data) {
+            Ok(cfg) => cfg,
+            Err(e) => {
+                error!("Failed to parse config file: {:?}", e);
+                return Config::default();
+            }
+        };
+        cfg.check();
+        cfg
+    }
+
+    // Loads the config. If the file does not exist, it is created with default values, so
+    // a user can find all settings in it
+    pub fn load_or_create() -> Config {
+        let cfg = Config::load();
+        if !config_path().exists() {
+            cfg.save();
+        }
+        cfg
+    }
+
+    // invalid values are reset to default ones with a warning
+    fn check(&mut self) {
+        check_color(&mut self.clear_color, "clear_color");
+        check_color(&mut self.letterbox_color, "letterbox_color");
+        check_keys(&mut self.keys);
+        if scaling_index(&self.scaling).is_none() {
+            warn!("Invalid scaling: {:?}. Default scaling is used", self.scaling);
+            self.scaling = SCALING_MODES[0].0.to_string();
+        }
+        check_volume(&mut self.master_volume, DEF_MASTER_VOLUME, "master_volume");
+        check_volume(&mut self.music_volume, DEF_MUSIC_VOLUME, "music_volume");
+        check_volume(&mut self.sfx_volume, DEF_SFX_VOLUME, "sfx_volume");
+    }
+
+    pub fn clear_color(&self) -> Color {
+        hex_color(&self.clear_color).unwrap_or(DEF_CLEAR_COLOR)
+    }
+
+    pub fn letterbox_color(&self) -> Color {
+        hex_color(&self.letterbox_color).unwrap_or(DEF_LETTERBOX_COLOR)
+    }
+
+    pub fn scaling_mode(&self) -> ScalingMode {
+        SCALING_MODES[scaling_index(&self.scaling).unwrap_or(0)].1
+    }
+
+    // switches to the next scaling mode and returns it
+    pub fn next_scaling(&mut self) -> ScalingMode {
+        let idx = scaling_index(&self.scaling).map_or(0, |i| (i + 1) % SCALING_MODES.len());
+        self.scaling = SCALING_MODES[idx].0.to_string();
+        SCALING_MODES[idx].1
+    }
+
+    // the longest pause between actions of a saved replay in ticks. 0 - the original timing
+    pub fn replay_max_delay(&self) -> u64 {
+        u64::from(self.replay_max_pause) * u64::from(FPS) / 1000
+    }
+
+    // too small window size is increased to the minimal one
+    pub fn window_size(&self) -> (i32, i32) {
+        (self.window_width.max(MIN_WINDOW_W), self.window_height.max(MIN_WINDOW_H))
+    }
+
+    pub fn save(&self) {
+        if skip_write("config") {
+            return;
+        }
+        let tml = toml::to_string(self).unwrap();
+        let _ = write(config_path(), tml);
+    }
+}
+
+fn scaling_index(name: &str) -> Option<usize> {
+    SCALING_MODES.iter().position(|(n, _)| *n == name)
+}
+
+fn hex_color(value: &Option<String>) -> Option<Color> {
+    value.as_ref().and_then(|v| Color::try_hex(v).ok())
+}
+
+// invalid color is reset to default one with a warning
+fn check_color(value: &mut Option<String>, name: &str) {
+    if value.is_some() && hex_color(value).is_none() {
+        warn!("Invalid color {}: {:?}. Default color is used", name, value);
+        *value = None;
+    }
+}
+
+fn check_volume(value: &mut f32, def: f32, name: &str) {
+    if !(0.0..=1.0).contains(value) {
+        warn!("Invalid {}: {}. It must be between 0.0 and 1.0, {} is used", name, value, def);
+        *value = def;
+    }
+}
+
+// unknown commands and key names are removed with a warning
+fn check_keys(keys: &mut HashMap<String, Vec<String>>) {
+    keys.retain(|cmd, names| {
+        if Command::from_name(cmd).is_none() {
+            warn!("Unknown command in keys: {:?}. It is ignored", cmd);
+            return false;
+        }
+        names.retain(|name| {
+            let known = key_by_name(name).is_some();
+            if !known {
+                warn!("Unknown key {:?} for command {}. It is ignored", name, cmd);
+            }
+            known
+        });
+        true
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn volumes_are_checked() {
+        let mut cfg: Config = toml::from_str("music_volume = 0.25").unwrap();
+        cfg.check();
+        assert_eq!((cfg.master_volume, cfg.music_volume, cfg.sfx_volume), (1.0, 0.25, 1.0));
+
+        let mut cfg: Config = toml::from_str("master_volume = 1.5\nsfx_volume = -0.1\nmusic_volume = 0.0").unwrap();
+        cfg.check();
+        assert_eq!((cfg.master_volume, cfg.music_volume, cfg.sfx_volume), (DEF_MASTER_VOLUME, 0.0, DEF_SFX_VOLUME));
+
+        // the saved volumes are loaded back
+        let cfg = Config { master_volume: 0.5, ..Config::default() };
+        let loaded: Config = toml::from_str(&toml::to_string(&cfg).unwrap()).unwrap();
+        assert_eq!(loaded.master_volume, 0.5);
+    }
+}
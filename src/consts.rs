@@ -3,13 +3,16 @@ pub const SCR_W: f32 = 1024.0;
 pub const SCR_H: f32 = 768.0;
 
 pub const BRICK_SIZE: f32 = 48.0; // Width and height of a block
-pub const WIDTH: usize = 21; // width of the window in blocks
 pub const INFO_WIDTH: usize = 5; // width of the info window at the right in blocks
-pub const HEIGHT: usize = 16; // height of the window in blocks
-pub const MAX_SIZE: usize = 7; // puzzle max dimension: 7x7
+pub const MAX_SIZE: usize = 7; // default puzzle max dimension: 7x7. See `Board` for the board size
 
 pub const NUM_STATES: i32 = 4; // number of states
 
+// Game updates per second. The game runs on a fixed timestep: tetra accumulates real time and
+// calls `update` exactly this many times per second whatever the display refresh rate is, so
+// block speeds (in pixels per update) and replay ticks are the same on every machine
+pub const FPS: u32 = 60;
+
 // number of the level used to show demo from main menu
 // this level must be inaccessible in normal game
 pub const DEMO_LEVEL: usize = 0;
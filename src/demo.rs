@@ -1,18 +1,83 @@
 use std::cell::RefCell;
 use std::rc::Rc;
 
-use tetra::graphics::{DrawParams, Rectangle, Texture};
+use log::{debug, trace};
+use tetra::graphics::mesh::{Mesh, ShapeStyle};
+use tetra::graphics::{Color, DrawParams, Rectangle, Texture};
 use tetra::input::{self, Key};
 use tetra::math::Vec2;
 use tetra::Context;
 
+use crate::assets::Assets;
+use crate::audio::Track;
+use crate::bindings::{Command, InputBindings};
 use crate::common::{center_play_area, center_screen};
-use crate::consts::{BRICK_SIZE, DEMO_LEVEL, INFO_WIDTH, NUM_STATES, PLATE_REPLAY_COMPLETED, WIDTH};
+use crate::config::Config;
+use crate::consts::{BRICK_SIZE, DEMO_LEVEL, NUM_STATES, PLATE_REPLAY_COMPLETED};
 use crate::field::{GameField, GameState};
+use crate::i18n::Labels;
 use crate::loader::Loader;
+use crate::net::StreamServer;
+use crate::play::{PlayMode, PlayScene};
 use crate::replay::{Action, ReplayEngine};
 use crate::scenes::{Scene, Transition};
 use crate::scores::Scores;
+use crate::textnum::{TextNumber, TextParams};
+
+// fast forward gives up after this number of frames (one hour of a replay)
+const MAX_FAST_FORWARD: u32 = 60 * 60 * 60;
+// the number of actions the replay goes back by
+const REWIND_ACTIONS: usize = 10;
+// playback speeds in half-ticks per frame: 0.5x, 1x, 2x, and 4x
+const SPEEDS: [u32; 4] = [1, 2, 4, 8];
+const NORMAL_SPEED: usize = 1;
+// gap between localized rules and the border of their background
+const RULES_PADDING: f32 = 20.0;
+
+// applies all replay actions that are due by the tick to the field. Returns the number of actions
+fn play_actions(replay: &mut ReplayEngine, field: &mut GameField, tick: u64) -> usize {
+    let mut cnt = 0;
+    while let Some(act) = replay.next_replay_action(tick) {
+        cnt += 1;
+        match act {
+            Action::Up => {
+                trace!("{} - UP", tick);
+                field.player_up();
+            }
+            Action::Down => {
+                trace!("{} - DOWN", tick);
+                field.player_down();
+            }
+            Action::Throw => {
+                trace!("{} - THROW", tick);
+                field.throw_brick();
+            }
+        }
+    }
+    cnt
+}
+
+// Plays the replay of the level on a headless field frame by frame exactly as the demo does,
+// until all actions are applied and all blocks stop. Returns the field in its final state
+pub fn play_headless(replay: &mut ReplayEngine, loader: Rc<Loader>, lvl: usize) -> GameField {
+    let scores = Rc::new(RefCell::new(Scores::detached(loader.level_count())));
+    let mut field = GameField::headless(loader, scores);
+    field.demoing = true;
+    field.load(lvl);
+    replay.replay_start();
+    let mut tick = 0;
+    for _ in 0..MAX_FAST_FORWARD {
+        if !replay.is_playing() && field.is_settled() {
+            break;
+        }
+        if replay.clock_runs(&field) {
+            tick += 1;
+        }
+        play_actions(replay, &mut field, tick);
+        field.step();
+    }
+    field
+}
 
 pub struct DemoScene {
     field: GameField,
@@ -22,42 +87,228 @@ pub struct DemoScene {
     replay: ReplayEngine,
     tick: u64,         // internal ticker counter for displaying replays correctly
     rules_shown: bool, // true if replay must pause before start and show the game rules
+    config: Rc<RefCell<Config>>,
+    keys: InputBindings,
+    bookmarks: Vec<u64>, // sorted ticks marked by the user during the session
+    paused: bool,
+    speed: usize,    // index in SPEEDS
+    half_ticks: u32, // half-ticks accumulated at slow speed
+    txt_num: TextNumber,
+    labels: Option<Labels>,    // None - the plate and the rules are drawn from images
+    rules_shade: Option<Mesh>, // background of localized rules, created at first draw call
+    assets: Rc<Assets>,
+    // the level pack of the game that replaces the demo after it ends.
+    // None - the demo returns to the previous scene
+    play_loader: Option<Rc<Loader>>,
+    stream: Option<Rc<RefCell<StreamServer>>>, // spectators of the game that replaces the demo
 }
 
 impl DemoScene {
-    pub fn new(ctx: &mut Context, ld: Rc<Loader>, sc: Rc<RefCell<Scores>>, lvl: usize) -> tetra::Result<Self> {
+    pub fn new(
+        assets: Rc<Assets>,
+        ld: Rc<Loader>,
+        sc: Rc<RefCell<Scores>>,
+        config: Rc<RefCell<Config>>,
+        lvl: usize,
+    ) -> tetra::Result<Self> {
         let lvl = if lvl == 0 { DEMO_LEVEL } else { lvl };
-        let state_image = include_bytes!("../assets/all_plates.png");
-        let progress_image = include_bytes!("../assets/progress.png");
-        let info_image = include_bytes!("../assets/rules.png");
+        // the rules are shown automatically only before the first demo
+        let show_rules = lvl == DEMO_LEVEL && !config.borrow().rules_seen;
+        let keys = InputBindings::new(&config.borrow());
+        let labels = Labels::for_language(assets.font.clone(), &config.borrow().language);
         let mut p = DemoScene {
-            field: GameField::new(ctx, ld, sc, true)?,
-            state_tx: Texture::from_encoded(ctx, state_image)?,
-            progress_tx: Texture::from_encoded(ctx, progress_image)?,
-            info_tx: Texture::from_encoded(ctx, info_image)?,
+            field: GameField::new(&assets, ld, sc.clone(), true)?,
+            state_tx: assets.plates.clone(),
+            progress_tx: assets.progress.clone(),
+            info_tx: assets.rules.clone(),
             replay: ReplayEngine::new(),
             tick: 0,
-            rules_shown: lvl == DEMO_LEVEL,
+            rules_shown: show_rules,
+            config,
+            keys,
+            bookmarks: Vec::new(),
+            paused: false,
+            speed: NORMAL_SPEED,
+            half_ticks: 0,
+            txt_num: TextNumber::new(assets.numbers.clone()),
+            labels,
+            rules_shade: None,
+            assets,
+            play_loader: None,
+            stream: None,
         };
+        p.field.clear_color = p.config.borrow().clear_color();
         p.field.load(lvl);
         p.replay.load(lvl);
         p.replay.replay_start();
-        println!("Replay for level {} loaded. {} moves.", lvl, p.replay.action_count());
+        debug!("Replay for level {} loaded. {} moves.", lvl, p.replay.action_count());
         Ok(p)
     }
 
+    // After the replay ends, Confirm starts the game from the current level of the pack instead
+    // of returning to the previous scene
+    pub fn with_play(mut self, ld: Rc<Loader>, stream: Option<Rc<RefCell<StreamServer>>>) -> Self {
+        self.play_loader = Some(ld);
+        self.stream = stream;
+        self
+    }
+
+    // plays the replay instead of the saved one of the level (e.g, a replay file from
+    // the command line)
+    pub fn with_replay(mut self, replay: ReplayEngine) -> Self {
+        self.replay = replay;
+        self.replay.replay_start();
+        self
+    }
+
+    // applies all replay actions that are due by the current tick. Returns the number of actions
+    fn play_actions(&mut self) -> usize {
+        play_actions(&mut self.replay, &mut self.field, self.tick)
+    }
+
+    // the replay clock runs the same way as it did while the replay was recorded
+    fn tick(&mut self) {
+        if self.replay.clock_runs(&self.field) {
+            self.tick += 1;
+        }
+    }
+
+    // advances the replay by one frame
+    fn advance(&mut self) {
+        self.tick();
+        self.play_actions();
+        self.field.step();
+    }
+
+    // While paused, plays the replay up to its next action and waits until all blocks stop
+    fn step_action(&mut self) {
+        let mut applied = false;
+        for _ in 0..MAX_FAST_FORWARD {
+            if applied && self.field.is_settled() {
+                return;
+            }
+            if !applied && !self.replay.is_playing() {
+                return;
+            }
+            self.tick();
+            applied |= self.play_actions() != 0;
+            self.field.step();
+        }
+    }
+
+    // Plays the rest of the replay instantly: the field is advanced frame by frame without
+    // drawing until all actions are applied and all blocks stop
+    fn fast_forward(&mut self) {
+        for _ in 0..MAX_FAST_FORWARD {
+            if !self.replay.is_playing() && self.field.is_settled() {
+                return;
+            }
+            self.advance();
+        }
+    }
+
+    // Jumps to the tick: the level is reloaded and the replay is played from the beginning
+    // without drawing. Ticks after the last action of the replay are clamped
+    fn seek(&mut self, tick: u64) {
+        let tick = tick.min(self.replay.last_tick());
+        self.field.load(self.field.level);
+        self.field.score = 0;
+        self.replay.rewind();
+        self.tick = 0;
+        // the clock stops for good if the replay fails the level before the tick
+        for _ in 0..MAX_FAST_FORWARD {
+            if self.tick >= tick {
+                break;
+            }
+            self.advance();
+        }
+    }
+
+    // Goes back by a few actions: the level is reloaded and the actions before the target are
+    // applied instantly. After every action the field runs until all blocks stop, so the board
+    // is the same as forward playback produces
+    fn rewind_actions(&mut self, cnt: usize) {
+        let target = self.replay.position().saturating_sub(cnt);
+        self.field.load(self.field.level);
+        self.field.score = 0;
+        let field = &mut self.field;
+        self.tick = self.replay.apply_up_to(target, |act| {
+            match act {
+                Action::Up => field.player_up(),
+                Action::Down => field.player_down(),
+                Action::Throw => {
+                    field.throw_brick();
+                }
+            }
+            for _ in 0..MAX_FAST_FORWARD {
+                if field.is_settled() {
+                    break;
+                }
+                field.step();
+            }
+        });
+    }
+
+    fn add_bookmark(&mut self) {
+        if let Err(idx) = self.bookmarks.binary_search(&self.tick) {
+            self.bookmarks.insert(idx, self.tick);
+        }
+    }
+
+    fn next_bookmark(&mut self) {
+        if let Some(tick) = self.bookmarks.iter().find(|t| **t > self.tick) {
+            self.seek(*tick);
+        }
+    }
+
+    fn prev_bookmark(&mut self) {
+        if let Some(tick) = self.bookmarks.iter().rev().find(|t| **t < self.tick) {
+            self.seek(*tick);
+        }
+    }
+
     // the only decoration is a plate that shows that the replay has finished
-    fn draw_deco(&mut self, ctx: &mut Context) {
+    fn draw_deco(&mut self, ctx: &mut Context) -> tetra::Result {
+        if self.field.state == GameState::Unfinished {
+            return Ok(());
+        }
+        if let Some(labels) = self.labels.as_mut() {
+            let color = Color::rgb(0.0, 0.6, 0.6);
+            labels.draw_plate(ctx, "plate_completed", "plate_replay_completed_hint", color)?;
+            return Ok(());
+        }
+
         let w = self.state_tx.width() as f32;
         let h = (self.state_tx.height() / NUM_STATES) as f32;
-
-        let clip_rect = match self.field.state {
-            GameState::Unfinished => return,
-            _ => Rectangle::new(0.0, h * PLATE_REPLAY_COMPLETED, w, h),
-        };
-
+        let clip_rect = Rectangle::new(0.0, h * PLATE_REPLAY_COMPLETED, w, h);
         let dp = DrawParams::new().position(center_screen(w, h));
         self.state_tx.draw_region(ctx, clip_rect, dp);
+        Ok(())
+    }
+
+    // the game rules over the play area: the image or localized text on a dark background
+    fn draw_rules(&mut self, ctx: &mut Context) -> tetra::Result {
+        let w = self.info_tx.width() as f32;
+        let h = self.info_tx.height() as f32;
+        let pos = center_play_area(w, h);
+        let labels = match self.labels.as_mut() {
+            None => {
+                self.info_tx.draw(ctx, DrawParams::new().position(pos));
+                return Ok(());
+            }
+            Some(l) => l,
+        };
+        if self.rules_shade.is_none() {
+            self.rules_shade = Some(Mesh::rectangle(ctx, ShapeStyle::Fill, Rectangle::new(0.0, 0.0, w, h))?);
+        }
+        if let Some(shade) = &self.rules_shade {
+            shade.draw(ctx, DrawParams::new().position(pos).color(Color::rgba(0.0, 0.0, 0.0, 0.85)));
+        }
+        let text_w = w - RULES_PADDING * 2.0;
+        let text_h = labels.wrapped_height("rules", text_w);
+        let y = pos.y + ((h - text_h) / 2.0).max(0.0);
+        labels.draw_wrapped(ctx, Vec2::new(pos.x + RULES_PADDING, y), text_w, "rules", Color::WHITE);
+        Ok(())
     }
 
     // show progress bar for replay
@@ -67,7 +318,7 @@ impl DemoScene {
             return;
         }
 
-        let x = ((WIDTH - INFO_WIDTH) as f32 + 0.5) * BRICK_SIZE;
+        let x = (self.field.board().play_width() as f32 + 0.5) * BRICK_SIZE;
         let y = BRICK_SIZE * 1.0;
         let w = self.progress_tx.width() * progress / 100;
         let h = self.progress_tx.height() as f32;
@@ -75,34 +326,82 @@ impl DemoScene {
         let dp = DrawParams::new().position(Vec2::new(x, y));
         self.progress_tx.draw_region(ctx, clip_rect, dp);
     }
+
+    // playback speed in percent below the progress bar. It is grey while the replay is paused
+    fn draw_speed(&mut self, ctx: &mut Context) {
+        let x = (self.field.board().play_width() as f32 + 0.5) * BRICK_SIZE;
+        let y = BRICK_SIZE * 5.0;
+        let mut tp = TextParams::new().with_width(3).with_right_align();
+        if self.paused {
+            tp = tp.with_color(Color::rgb(0.5, 0.5, 0.5));
+        }
+        let percent = SPEEDS[self.speed] * 50;
+        self.txt_num.draw(ctx, Vec2::new(x, y), percent, tp);
+    }
 }
 
 impl Scene for DemoScene {
+    fn music(&self) -> Option<Track> {
+        Some(Track::Game)
+    }
+
     fn update(&mut self, ctx: &mut Context) -> tetra::Result<Transition> {
         // take a break while game rules are displayed
         if self.rules_shown {
-            if input::is_key_pressed(ctx, Key::Space) || input::is_key_pressed(ctx, Key::Escape) {
+            if self.keys.is_pressed(ctx, Command::Confirm) || self.keys.is_pressed(ctx, Command::Back) {
                 self.rules_shown = false;
+                let mut cfg = self.config.borrow_mut();
+                if !cfg.rules_seen {
+                    cfg.rules_seen = true;
+                    cfg.save();
+                }
             }
             return Ok(Transition::None);
         }
+        if self.keys.is_pressed(ctx, Command::Help) {
+            self.rules_shown = true;
+            return Ok(Transition::None);
+        }
 
-        self.tick += 1;
-        while let Some(act) = self.replay.next_replay_action(self.tick) {
-            match act {
-                Action::Up => {
-                    println!("{} - UP", self.tick);
-                    self.field.player_up();
-                }
-                Action::Down => {
-                    println!("{} - DOWN", self.tick);
-                    self.field.player_down();
-                }
-                Action::Throw => {
-                    println!("{} - THROW", self.tick);
-                    self.field.throw_brick();
-                }
-            }
+        // skip to the end of the replay, or go back by a few actions
+        if input::is_key_pressed(ctx, Key::End) {
+            self.fast_forward();
+        } else if input::is_key_pressed(ctx, Key::Backspace) {
+            self.rewind_actions(REWIND_ACTIONS);
+        }
+
+        // bookmarks: B marks the current moment, PageUp and PageDown jump between marks
+        if input::is_key_pressed(ctx, Key::B) {
+            self.add_bookmark();
+        } else if input::is_key_pressed(ctx, Key::PageUp) {
+            self.prev_bookmark();
+        } else if input::is_key_pressed(ctx, Key::PageDown) {
+            self.next_bookmark();
+        }
+
+        // playback: P pauses and resumes, N plays the next action while paused, F changes speed
+        if self.keys.is_pressed(ctx, Command::Pause) {
+            self.paused = !self.paused;
+        } else if input::is_key_pressed(ctx, Key::F) {
+            self.speed = (self.speed + 1) % SPEEDS.len();
+            self.half_ticks = 0;
+        } else if self.paused && input::is_key_pressed(ctx, Key::N) {
+            self.step_action();
+        }
+
+        let mut ticks = 0;
+        if !self.paused {
+            self.half_ticks += SPEEDS[self.speed];
+            ticks = self.half_ticks / 2;
+            self.half_ticks %= 2;
+        }
+        // the last tick of the frame is stepped by field.update below
+        for _ in 1..ticks {
+            self.advance();
+        }
+        if ticks > 0 {
+            self.tick();
+            self.play_actions();
         }
 
         // if replay ends, consider this as the level is solved
@@ -110,35 +409,60 @@ impl Scene for DemoScene {
             self.field.state = GameState::Winner;
         }
 
-        if input::is_key_pressed(ctx, Key::Escape) {
+        if self.keys.is_pressed(ctx, Command::Back) {
             return Ok(Transition::Pop);
         }
 
-        if input::is_key_pressed(ctx, Key::Space)
-            || input::is_key_pressed(ctx, Key::Enter)
-            || input::is_key_pressed(ctx, Key::NumPadEnter) && self.field.state == GameState::Winner
-        {
+        if self.keys.is_pressed(ctx, Command::Confirm) {
+            if let (Some(ld), GameState::Winner) = (&self.play_loader, self.field.state) {
+                return Ok(Transition::Replace(Box::new(PlayScene::new(
+                    self.assets.clone(),
+                    ld.clone(),
+                    self.field.scores.clone(),
+                    self.config.clone(),
+                    self.stream.clone(),
+                    PlayMode::Normal,
+                )?)));
+            }
             return Ok(Transition::Pop);
         }
 
         // field.update is always considered to return None because DEMO mode
-        // contols itself
-        let _ = self.field.update(ctx);
+        // contols itself. In frames without a tick (pause or slow speed) only animations run
+        if ticks > 0 {
+            let _ = self.field.update(ctx);
+        } else {
+            self.field.animate(ctx);
+        }
         Ok(Transition::None)
     }
 
     fn draw(&mut self, ctx: &mut Context) -> tetra::Result<Transition> {
         let _ = self.field.draw(ctx)?;
-        self.draw_deco(ctx);
+        self.draw_deco(ctx)?;
         self.draw_progress(ctx);
+        self.draw_speed(ctx);
 
         if self.rules_shown {
-            let w = self.info_tx.width() as f32;
-            let h = self.info_tx.width() as f32;
-            let pos = center_play_area(w, h);
-            self.info_tx.draw(ctx, DrawParams::new().position(pos));
+            self.draw_rules(ctx)?;
         }
 
         Ok(Transition::None)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the built-in demo replay must solve the demo level when it is played as the demo shows it
+    #[test]
+    fn demo_replay_solves_demo_level() {
+        let mut replay = ReplayEngine::new();
+        replay.load(DEMO_LEVEL);
+        assert!(replay.is_loaded());
+        let field = play_headless(&mut replay, Rc::new(Loader::new()), DEMO_LEVEL);
+        assert!(field.state == GameState::Winner, "the demo ends with '{}'", field.state);
+        assert!(field.score > 0);
+    }
+}
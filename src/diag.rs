@@ -0,0 +1,86 @@
+use std::backtrace::Backtrace;
+use std::fs::write;
+use std::panic;
+
+use chrono::Local;
+use tetra::graphics;
+use tetra::Context;
+
+use crate::audio::device_available;
+use crate::common::{
+    asset_path, config_path, crash_path, is_portable, log_path, replay_path, score_path, session_path, skip_write,
+};
+use crate::config::Config;
+use crate::loader::Loader;
+use crate::scores::Scores;
+
+// Prints information about the game environment to attach to a bug report.
+// If the game failed to create its window, the error is printed instead of renderer info
+pub fn print_report(ctx: Result<&Context, &tetra::TetraError>) {
+    println!("Unblocked {}", env!("CARGO_PKG_VERSION"));
+    println!("Portable mode: {}", if is_portable() { "on" } else { "off" });
+
+    let cfg = config_path();
+    println!("Config: {:?} ({})", cfg, if cfg.exists() { "found" } else { "not found, defaults used" });
+    let config = Config::load();
+    println!(
+        "  hardcore: {}, checkpoint every: {}, read-only: {}",
+        config.hardcore, config.checkpoint_every, config.read_only
+    );
+    println!("Hiscores: {:?} ({})", score_path(), if score_path().exists() { "found" } else { "not found" });
+    println!("Session: {:?} ({})", session_path(), if session_path().exists() { "found" } else { "not found" });
+    println!("Replays: {:?}", replay_path());
+    println!("Assets: {:?}", asset_path());
+    println!("Log: {:?}", log_path());
+
+    let loader = Loader::new();
+    let scores = Scores::new(loader.level_count());
+    println!("Levels: {}, reached: {}", loader.level_count(), scores.max_avail_level());
+
+    match ctx {
+        Ok(ctx) => {
+            let audio = if device_available(ctx) { "available" } else { "no sound device, the game is silent" };
+            println!("Audio: {}", audio);
+            let info = graphics::get_device_info(ctx);
+            println!("Renderer: {} ({})", info.renderer, info.vendor);
+            println!("OpenGL: {}, GLSL: {}", info.opengl_version, info.glsl_version);
+        }
+        Err(e) => println!("Renderer and audio: failed to initialize: {}", e),
+    }
+}
+
+// Writes the error that closed the game to the crash log in the game data directory. The
+// Windows build does not have a console, so there the error is shown in a message box as well
+pub fn report_crash(msg: &str, backtrace: Option<&Backtrace>) {
+    eprintln!("{}", msg);
+    if !skip_write("crash log") {
+        let mut text = format!("Unblocked {} at {}\n{}\n", env!("CARGO_PKG_VERSION"), Local::now(), msg);
+        if let Some(bt) = backtrace {
+            text += &format!("\nBacktrace:\n{}\n", bt);
+        }
+        let path = crash_path();
+        match write(&path, text) {
+            Ok(()) => eprintln!("The error is saved to {:?}", path),
+            Err(e) => eprintln!("Failed to save the error to {:?}: {}", path, e),
+        }
+    }
+    show_crash_box(msg);
+}
+
+// a panic (e.g, no audio device) is reported in the same way as an error
+pub fn catch_panics() {
+    panic::set_hook(Box::new(|info| {
+        let bt = Backtrace::force_capture();
+        report_crash(&format!("Unexpected error: {}", info), Some(&bt));
+    }));
+}
+
+#[cfg(windows)]
+fn show_crash_box(msg: &str) {
+    use sdl2::messagebox::{show_simple_message_box, MessageBoxFlag};
+    let text = format!("{}\n\nSee {:?} for details", msg, crash_path());
+    let _ = show_simple_message_box(MessageBoxFlag::ERROR, "Unblocked", &text, None);
+}
+
+#[cfg(not(windows))]
+fn show_crash_box(_msg: &str) {}
@@ -5,22 +5,54 @@ use std::fmt;
 use std::rc::Rc;
 use std::time::Duration;
 
+use log::{debug, error};
+use tetra::graphics::mesh::{Mesh, ShapeStyle};
 use tetra::graphics::{self, animation, Color, DrawParams, Rectangle, Texture};
-use tetra::input::{self, Key};
-use tetra::math::Vec2;
+use tetra::math::{Mat4, Vec2, Vec3};
 use tetra::Context;
 
-use crate::common::{clamp, digits};
-use crate::consts::{BRICK_SIZE, HEIGHT, INFO_WIDTH, MAX_SIZE, SCR_H, SCR_W, WIDTH};
+use crate::assets::Assets;
+use crate::audio::{Sfx, SfxKind};
+use crate::bindings::{Command, InputBindings};
+use crate::board::Board;
+use crate::common::{clamp, current_pack, digits, level_image_path};
+use crate::confetti::Confetti;
+use crate::config::DEF_CLEAR_COLOR;
+use crate::consts::{BRICK_SIZE, INFO_WIDTH, SCR_H};
 use crate::loader::Loader;
 use crate::scenes::Transition;
-use crate::scores::{Score, Scores};
+use crate::scores::{ModeFlags, Score, Scores};
+use crate::sim::BoardSim;
+use crate::solver::{search, Search};
+use crate::stars::{level_goal, stars_for};
 use crate::textnum::{TextNumber, TextParams};
+use crate::undo::{Block, Delta, UndoStack};
 
+// block speeds are in updates and pixels per update, so they rely on the fixed timestep `FPS`
 const TICKS: u32 = 1;
 const BRICK_DEF_SPEED: f32 = 48.0;
 const BRICK_FALL_SPEED: f32 = 16.0;
 const ARROW_FRAMES: usize = 4;
+// how long every frame of the arrow animation is shown
+const ARROW_FRAME_TIME: Duration = Duration::from_millis(250);
+// width of a highlight of a throwable row
+const GLOW_WIDTH: f32 = 4.0;
+// the deadlock check gives up after this many boards to keep the game responsive
+const DEADLOCK_BOARDS: usize = 20_000;
+// the hint checks at most this many boards for all rows together, so it does not stall
+// the game on a big board with many rows
+const HINT_BOARDS: usize = 20_000;
+// memory in bytes the undo history of a field takes unless it is set from config
+const DEF_UNDO_BUDGET: usize = 64 * 1024;
+// idle player's block bobs up and down: period in frames and amplitude in pixels
+const IDLE_PERIOD: u32 = 120;
+const IDLE_AMPLITUDE: f32 = 1.5;
+// points per frame player's block slides to its new row after a move (a row in 4 frames)
+const SLIDE_SPEED: f32 = BRICK_SIZE / 4.0;
+// points per frame a scrolling board moves to follow player's block
+const SCROLL_SPEED: f32 = 8.0;
+// size of a block on the minimap of a scrolling board in points
+const MINIMAP_CELL: f32 = 6.0;
 
 // developer best results - I know some of them can be improved
 static RECORDS: &[u32] = &[
@@ -32,7 +64,20 @@ static RECORDS: &[u32] = &[
     12, 12, 14, 11, 10, 11, 15, 15, 17, 12, // 41-50
     15, 12, 12, 14, 16, 12, // 51-56
 ];
-const RECORD_LEN: usize = 57;
+
+// The developer best result for the level. The demo level, levels after the end of the
+// table, and custom level packs do not have records
+pub fn developer_record(level: usize) -> Option<u32> {
+    record_in_pack(level, current_pack().is_some())
+}
+
+fn record_in_pack(level: usize, custom_pack: bool) -> Option<u32> {
+    if level == 0 || custom_pack {
+        None
+    } else {
+        RECORDS.get(level).copied()
+    }
+}
 
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub enum GameState {
@@ -63,6 +108,11 @@ pub enum BrickKind {
     K5,
     K6,
     Joker,
+    // kinds for bigger custom packs. They go after the joker, so the values of the old
+    // kinds stay the same and so do board hashes of saved replays
+    K7,
+    K8,
+    K9,
 }
 
 impl fmt::Display for BrickKind {
@@ -74,23 +124,27 @@ impl fmt::Display for BrickKind {
             BrickKind::K4 => write!(f, "'T'"),
             BrickKind::K5 => write!(f, "'Z'"),
             BrickKind::K6 => write!(f, "'W'"),
+            BrickKind::K7 => write!(f, "'V'"),
+            BrickKind::K8 => write!(f, "'Y'"),
+            BrickKind::K9 => write!(f, "'U'"),
             BrickKind::Joker => write!(f, "'?'"),
             _ => write!(f, "???"),
         }
     }
 }
 
+// The image of blocks is a column of cells in the order of `BrickKind` values: a wall
+// (`None`), kinds 1 to 6, a joker, and kinds 7 to 9
 fn brick2shift(k: BrickKind) -> f32 {
-    match k {
-        BrickKind::K1 => BRICK_SIZE,
-        BrickKind::K2 => BRICK_SIZE * 2.0,
-        BrickKind::K3 => BRICK_SIZE * 3.0,
-        BrickKind::K4 => BRICK_SIZE * 4.0,
-        BrickKind::K5 => BRICK_SIZE * 5.0,
-        BrickKind::K6 => BRICK_SIZE * 6.0,
-        BrickKind::Joker => BRICK_SIZE * 7.0,
-        BrickKind::None => 0.0,
-    }
+    k as u8 as f32 * BRICK_SIZE
+}
+
+// the board when the running throw started. It becomes an undo delta when the board settles
+struct ThrowStart {
+    blocks: Vec<Block>,
+    player: Block,
+    score: u32,
+    state: GameState,
 }
 
 #[derive(Debug, Clone)]
@@ -180,108 +234,214 @@ impl Brick {
     }
 }
 
+// a block where it comes to rest
+fn block_of(b: &Brick) -> Block {
+    let (x, y) = if b.is_moving() {
+        ((b.limit.x / BRICK_SIZE).round() as usize, (b.limit.y / BRICK_SIZE).round() as usize)
+    } else {
+        (b.x, b.y)
+    };
+    Block { x: x as u8, y: y as u8, kind: b.kind }
+}
+
 // convert coordinate in whole blocks into screen coordinates
 fn b2s<T: Into<usize>>(x: T, y: T) -> Vec2<f32> {
     Vec2::new(x.into() as f32 * BRICK_SIZE, y.into() as f32 * BRICK_SIZE)
 }
-// puzzle is a one-dimensional array, the function converts X,Y coordinate into
-// position inside the puzzle array
-fn pos2puz<T: Into<usize>>(x: T, y: T) -> usize {
-    x.into() + y.into() * WIDTH
+// screen position of an arrow pointing at the target block at (x, y)
+fn arrow_pos(is_down: bool, x: usize, y: usize) -> Vec2<f32> {
+    if is_down {
+        b2s(x, y)
+    } else {
+        b2s(x + 1, y)
+    }
+}
+// textures the field is drawn with and sounds it plays
+struct FieldGfx {
+    brick_tx: Texture,
+    back_tx: Texture,
+    level_no_tx: Texture,
+    throws_tx: Texture,
+    attempts_tx: Texture,
+    solved_tx: Texture,
+    txt_num: TextNumber,
+    arrow_animation: animation::Animation,
+    theme: String, // a level background is looked for in the theme first
+    sfx: Sfx,
+}
+
+impl FieldGfx {
+    fn new(assets: &Assets) -> FieldGfx {
+        FieldGfx {
+            brick_tx: assets.bricks.clone(),
+            back_tx: assets.background.clone(),
+            level_no_tx: assets.level_no.clone(),
+            throws_tx: assets.throws.clone(),
+            attempts_tx: assets.attempts.clone(),
+            solved_tx: assets.solved.clone(),
+            txt_num: TextNumber::new(assets.numbers.clone()),
+            arrow_animation: animation::Animation::new(
+                assets.arrows.clone(),
+                Rectangle::row(0.0, 0.0, BRICK_SIZE, BRICK_SIZE).take(ARROW_FRAMES).collect(),
+                ARROW_FRAME_TIME,
+            ),
+            theme: assets.theme.clone(),
+            sfx: assets.sfx.clone(),
+        }
+    }
 }
 
 pub struct GameField {
-    puzzle: [u32; HEIGHT * WIDTH],
+    board: Board,     // dimensions of the board, set when a level is loaded
+    puzzle: Vec<u32>, // walls of the board, see `Board::pos` for the position of a cell
     bricks: Vec<Brick>,
     pub level: usize, // current level No inside puzzle_set
     pub state: GameState,
 
     player: Brick,
     player_row: usize,
-    going_back: bool,  // the player's block is flying back after throw
-    pub score: u32,    // the number of throws so far
-    lvl_score: Score,  // info about level hiscores
-    pub demoing: bool, // is in demo mode(for demo mode some things are not displayed)
+    going_back: bool,                // the player's block is flying back after throw
+    pub score: u32,                  // the number of throws so far
+    lvl_score: Score,                // info about level hiscores
+    pub demoing: bool,               // is in demo mode(for demo mode some things are not displayed)
+    match_n: usize,                  // matching blocks in a row a falling block needs to clear them
+    column_matched: usize,           // blocks cleared by the falling player's block during the current throw
+    pub mode: ModeFlags,             // modes of the game, recorded with the result
+    pub hint_used: bool,             // the solution was previewed during the current attempt
+    undo_stack: UndoStack,           // changes of the board made by the last throws
+    throw_start: Option<ThrowStart>, // the board before the last throw, not in `undo_stack` yet
+    pub undo_used: bool,             // a throw was undone during the current attempt
 
     // calculated and orientation of an arrow that shows the first block that
-    // player's block would hit after throwing
-    arrow_pos: Vec2<f32>,
+    // player's block would hit after throwing. None if the row has no target
+    arrow_pos: Option<Vec2<f32>>,
     arrow_down: bool,
-    arrow_animation: animation::Animation,
 
     // kind of a block that player's block would hit after throwing
     first_brick: BrickKind,
-
-    brick_tx: Texture,
-    back_tx: Texture,
-
-    level_no_tx: Texture,
-    throws_tx: Texture,
-    attempts_tx: Texture,
-    solved_tx: Texture,
-
-    txt_num: TextNumber,
+    // rows the player's block can be thrown from (recalculated with the arrow)
+    throwable: Vec<usize>,
+    pub show_throwable: bool,
+    // arrows of all rows a block can be thrown from: position, direction, and if the
+    // current player's block matches the target (recalculated with the arrow)
+    row_arrows: Vec<(Vec2<f32>, bool, bool)>,
+    pub show_all_arrows: bool,
+    pub deadlock_warning: bool, // the player is warned when the board becomes unsolvable
+    pub idle_animation: bool,   // player's block bobs while it is waiting for a throw
+    pub celebration: bool,      // confetti bursts from player's block when the level is solved
+    pub sound: bool,            // game events play sounds. Demo and replays are silent
+    sounds: Vec<SfxKind>,       // sounds of the events since the last update, played by `update`
+    // vertical distance in points between the drawn player's block and its row. The block
+    // slides to its row after a move up or down
+    slide: f32,
+    confetti: Confetti,
+    pub throw_cooldown: u32, // frames after player's block returns before it accepts commands
+    cooldown: u32,           // frames left until player's block accepts commands
+    idle_frames: u32,        // frames since player's block has stopped
+    pub clear_color: Color,  // background color around the play area tiles
+    glow: Option<Mesh>,      // highlight for throwable rows, created at first draw call
+    // a board taller than the screen is drawn at the width of the screen and scrolls to follow
+    // player's block instead of being scaled down to fit the screen
+    pub board_scroll: bool,
+    scroll: f32,                          // vertical offset of the scrolling board in points
+    minimap_frame: Option<(Board, Mesh)>, // created at first draw call and when the board changes
+
+    gfx: Option<FieldGfx>, // None - the field is headless and is never drawn
+    // background image declared by the level. The image is loaded at first draw call
+    // because `load` does not have a context
+    level_bg: Option<String>,
+    level_bg_tx: Option<Texture>,
+    level_bg_pending: bool,
+
+    pub keys: InputBindings,
     loader: Rc<Loader>,
     pub scores: Rc<RefCell<Scores>>,
 }
 
 impl GameField {
-    pub fn new(ctx: &mut Context, loader: Rc<Loader>, scores: Rc<RefCell<Scores>>, demo: bool) -> tetra::Result<Self> {
+    pub fn new(assets: &Assets, loader: Rc<Loader>, scores: Rc<RefCell<Scores>>, demo: bool) -> tetra::Result<Self> {
+        Ok(Self::with_gfx(Some(FieldGfx::new(assets)), loader, scores, demo))
+    }
+
+    // A field without textures: it plays levels exactly as the drawn one, but `draw` does
+    // nothing. It does not need a context, so tests can play replays on it
+    pub fn headless(loader: Rc<Loader>, scores: Rc<RefCell<Scores>>) -> Self {
+        Self::with_gfx(None, loader, scores, false)
+    }
+
+    fn with_gfx(gfx: Option<FieldGfx>, loader: Rc<Loader>, scores: Rc<RefCell<Scores>>, demo: bool) -> Self {
         let lvl_curr = scores.borrow().curr_level();
         let lvl_info = scores.borrow().level_info(lvl_curr);
-        let arrow_image = include_bytes!("../assets/arrows.png");
-        let brick_image = include_bytes!("../assets/bricks.png");
-        let background_image = include_bytes!("../assets/background.png");
-        let number_image = include_bytes!("../assets/numbers.png");
-        let level_no_image = include_bytes!("../assets/level_no.png");
-        let throws_image = include_bytes!("../assets/throws.png");
-        let attempts_image = include_bytes!("../assets/attempts.png");
-        let solved_image = include_bytes!("../assets/solved.png");
-        Ok(GameField {
+        GameField {
             bricks: Vec::new(),
-            puzzle: [0; HEIGHT * WIDTH],
+            board: Board::default(),
+            puzzle: vec![0; Board::default().cells()],
             level: lvl_curr,
             state: GameState::Unfinished,
-            player: Brick::new(WIDTH - INFO_WIDTH - 1, HEIGHT - 2, BrickKind::Joker),
-            player_row: HEIGHT - 2,
+            player: Brick::new(Board::default().play_width() - 1, Board::default().bottom(), BrickKind::Joker),
+            player_row: Board::default().bottom(),
             going_back: false,
             lvl_score: lvl_info,
             score: 0,
             demoing: demo,
+            match_n: 1,
+            column_matched: 0,
+            mode: ModeFlags::default(),
+            hint_used: false,
+            undo_stack: UndoStack::new(DEF_UNDO_BUDGET),
+            throw_start: None,
+            undo_used: false,
 
             arrow_down: false,
-            arrow_pos: Vec2::new(0.0, 0.0),
+            arrow_pos: None,
             first_brick: BrickKind::None,
-
-            txt_num: TextNumber::new(ctx, number_image)?,
+            throwable: Vec::new(),
+            show_throwable: false,
+            row_arrows: Vec::new(),
+            show_all_arrows: false,
+            deadlock_warning: false,
+            idle_animation: false,
+            celebration: false,
+            sound: false,
+            sounds: Vec::new(),
+            slide: 0.0,
+            confetti: Confetti::new(),
+            idle_frames: 0,
+            throw_cooldown: 0,
+            cooldown: 0,
+            clear_color: DEF_CLEAR_COLOR,
+            glow: None,
+            board_scroll: false,
+            scroll: 0.0,
+            minimap_frame: None,
+
+            keys: InputBindings::default(),
             loader,
             scores,
 
-            brick_tx: Texture::from_encoded(ctx, brick_image)?,
-            back_tx: Texture::from_encoded(ctx, background_image)?,
-            level_no_tx: Texture::from_encoded(ctx, level_no_image)?,
-            throws_tx: Texture::from_encoded(ctx, throws_image)?,
-            attempts_tx: Texture::from_encoded(ctx, attempts_image)?,
-            solved_tx: Texture::from_encoded(ctx, solved_image)?,
-
-            arrow_animation: animation::Animation::new(
-                Texture::from_encoded(ctx, arrow_image)?,
-                Rectangle::row(0.0, 0.0, BRICK_SIZE, BRICK_SIZE).take(ARROW_FRAMES).collect(),
-                Duration::from_millis(150), // 60HZ to a frame per 250ms
-            ),
-        })
+            gfx,
+            level_bg: None,
+            level_bg_tx: None,
+            level_bg_pending: false,
+        }
     }
 
     // are user key strokes processed?
     // All key presses are ignored if the player's block in moving or game is over
     pub fn is_interactive(&self) -> bool {
-        !self.going_back && self.state == GameState::Unfinished
+        !self.going_back && self.cooldown == 0 && self.state == GameState::Unfinished
+    }
+
+    // true if neither player's block nor any other block is moving
+    pub fn is_settled(&self) -> bool {
+        !self.going_back && !self.player.is_moving() && !self.bricks.iter().any(|b| b.is_moving())
     }
 
     // start moving player's block back after hitting the floor or an non-matching block
     fn go_back(&mut self) {
         self.going_back = true;
-        let xlimit = (WIDTH - INFO_WIDTH - 1) as f32 * BRICK_SIZE;
+        self.play(SfxKind::Bounce);
+        let xlimit = (self.board.play_width() - 1) as f32 * BRICK_SIZE;
         let ylimit = self.player_row as f32 * BRICK_SIZE;
         let xn = (xlimit - self.player.scr_pos.x) / BRICK_DEF_SPEED;
         let dy = (self.player_row as f32 * BRICK_SIZE - self.player.scr_pos.y) / xn;
@@ -302,8 +462,16 @@ impl GameField {
         // player's block returned back after throw
         if self.going_back && stopped {
             self.going_back = false;
+            self.cooldown = self.throw_cooldown;
             self.recalc_arrow();
             self.state = self.calc_state();
+            match self.state {
+                GameState::Winner => self.play(SfxKind::Win),
+                GameState::Looser => self.play(SfxKind::Lose),
+                GameState::Completed => self.play(SfxKind::Complete),
+                _ => {}
+            }
+            self.celebrate();
             return;
         }
 
@@ -313,7 +481,13 @@ impl GameField {
             // player's block is falling
             //
             // hit the floor
-            if self.player.y == HEIGHT - 2 {
+            if self.player.y == self.board.bottom() {
+                self.player.stop();
+                self.go_back();
+                return;
+            }
+
+            if self.column_run_too_short(self.player.x, self.player.y + 1) {
                 self.player.stop();
                 self.go_back();
                 return;
@@ -347,6 +521,10 @@ impl GameField {
                 self.go_back();
                 return;
             }
+            if removed {
+                self.column_matched += 1;
+                self.play(SfxKind::Match(new_kind));
+            }
             self.player.kind = new_kind;
             self.player.fall(BRICK_DEF_SPEED);
 
@@ -363,13 +541,19 @@ impl GameField {
             let x = self.player.x;
             let y = self.player.y;
 
-            let (dx, dy) = if self.puzzle[pos2puz(x - 1, y)] == 1 {
+            let (dx, dy) = if self.puzzle[self.board.pos(x - 1, y)] == 1 {
                 //hit wall -> block falls down
                 (0i32, 1i32)
             } else {
                 (-1i32, 0i32)
             };
 
+            if dx == 0 && self.column_run_too_short(x, y + 1) {
+                self.player.stop();
+                self.go_back();
+                return;
+            }
+
             let bricks =
                 self.bricks.iter().filter(|b| b.x == (x as i32 + dx) as usize && b.y == (y as i32 + dy) as usize);
             let mut removed: bool = false;
@@ -383,7 +567,7 @@ impl GameField {
                 new_kind = brick.kind;
             }
 
-            if !removed && !exists && dx != 0 && self.puzzle[pos2puz(self.player.x - 1, self.player.y)] == 0 {
+            if !removed && !exists && dx != 0 && self.puzzle[self.board.pos(self.player.x - 1, self.player.y)] == 0 {
                 self.player.vel = Vec2::new(-BRICK_DEF_SPEED, 0.0);
                 self.player.limit = Vec2::new(BRICK_SIZE * (x as i32 + dx) as f32, BRICK_SIZE * y as f32);
                 self.player.ticks = TICKS;
@@ -391,6 +575,7 @@ impl GameField {
             }
 
             if removed {
+                self.play(SfxKind::Match(new_kind));
                 self.player.kind = new_kind;
                 self.bricks.retain(|b| b.x != (x as i32 + dx) as usize || b.y != (y as i32 + dy) as usize);
                 self.bricks.iter_mut().for_each(|b| {
@@ -399,7 +584,8 @@ impl GameField {
                     }
                 });
                 if dx == 0 {
-                    if self.player.y == HEIGHT - 2 {
+                    self.column_matched += 1;
+                    if self.player.y == self.board.bottom() {
                         self.player.stop();
                         self.go_back();
 
@@ -427,7 +613,7 @@ impl GameField {
                 return;
             }
             // hit the floor
-            if self.player.y == HEIGHT - 2 {
+            if self.player.y == self.board.bottom() {
                 self.player.stop();
                 self.go_back();
 
@@ -437,13 +623,86 @@ impl GameField {
         }
     }
 
-    pub fn update(&mut self, ctx: &mut Context) -> tetra::Result<Transition> {
-        self.arrow_animation.advance(ctx);
+    // Match-N rules: returns true if the falling player's block hits a matching block at (x, y)
+    // but the run of the matching blocks in the column is too short to clear them
+    fn column_run_too_short(&self, x: usize, y: usize) -> bool {
+        if self.match_n <= 1 {
+            return false;
+        }
+        let kind = match self.bricks.iter().find(|b| b.x == x && b.y == y) {
+            None => return false,
+            Some(b) => b.kind,
+        };
+        if kind != self.player.kind && self.player.kind != BrickKind::Joker {
+            return false;
+        }
+        let run = (y..self.board.height - 1)
+            .take_while(|yy| self.bricks.iter().any(|b| b.x == x && b.y == *yy && b.kind == kind))
+            .count();
+        self.column_matched + run < self.match_n
+    }
+
+    // moves all blocks by one frame. It does not depend on user input, so a field
+    // driven from outside (e.g, by a spectator stream) can be advanced with it
+    pub fn step(&mut self) {
+        if self.cooldown > 0 {
+            self.cooldown -= 1;
+        }
         for b in self.bricks.iter_mut() {
             b.update();
         }
 
         self.update_player();
+    }
+
+    // queues the sound of an event. Sounds are played by `update` because it has a context
+    fn play(&mut self, kind: SfxKind) {
+        if self.sound {
+            self.sounds.push(kind);
+        }
+    }
+
+    // returns and forgets the sounds queued since the last call
+    pub fn take_sounds(&mut self) -> Vec<SfxKind> {
+        std::mem::take(&mut self.sounds)
+    }
+
+    // a level is just solved: start a burst of confetti from player's block
+    fn celebrate(&mut self) {
+        if !self.celebration || self.demoing {
+            return;
+        }
+        if self.state == GameState::Winner || self.state == GameState::Completed {
+            let origin = self.player.scr_pos + Vec2::new(BRICK_SIZE / 2.0, BRICK_SIZE / 2.0);
+            self.confetti.start(origin, self.level as u32 * 1000 + self.score);
+        }
+    }
+
+    pub fn animate(&mut self, ctx: &mut Context) {
+        // the animation counts elapsed time, not calls, so its speed does not depend on FPS
+        if let Some(gfx) = self.gfx.as_mut() {
+            gfx.arrow_animation.advance(ctx);
+        }
+        self.confetti.update();
+        self.slide =
+            if self.slide > 0.0 { (self.slide - SLIDE_SPEED).max(0.0) } else { (self.slide + SLIDE_SPEED).min(0.0) };
+        if self.player.is_moving() || self.going_back {
+            self.idle_frames = 0;
+        } else {
+            self.idle_frames = (self.idle_frames + 1) % IDLE_PERIOD;
+        }
+    }
+
+    pub fn update(&mut self, ctx: &mut Context) -> tetra::Result<Transition> {
+        self.animate(ctx);
+        self.step();
+        self.scroll = self.board.follow(self.scroll, self.player.scr_pos.y, self.view_scale(), SCROLL_SPEED);
+        let sounds = self.take_sounds();
+        if let Some(gfx) = &self.gfx {
+            for kind in sounds {
+                gfx.sfx.play(ctx, kind);
+            }
+        }
 
         if self.going_back {
             return Ok(Transition::None);
@@ -453,21 +712,28 @@ impl GameField {
             return Ok(Transition::None);
         }
 
-        // reach here only if the level solved or failed or demo replay finished.
-        // Update hiscores if it is not in DEMO mode
-        if input::is_key_pressed(ctx, Key::Space)
-            || input::is_key_pressed(ctx, Key::Enter)
-            || input::is_key_pressed(ctx, Key::NumPadEnter)
-        {
-            match self.state {
-                GameState::Completed => {
-                    if !self.demoing {
-                        let mut sc = self.scores.borrow_mut();
-                        sc.set_win(self.level, self.score);
-                    }
-                    return Ok(Transition::Pop);
+        // reach here only if the level solved or failed or demo replay finished
+        if self.keys.is_pressed(ctx, Command::Confirm) {
+            return Ok(self.close_result());
+        }
+        Ok(Transition::None)
+    }
+
+    // The plate of a finished level is closed: the result is saved, and the next level is
+    // loaded or the failed one is restarted. A demoing field only shows a game played
+    // elsewhere (a replay or a spectator stream), so it saves nothing and keeps the board:
+    // the game it shows decides what comes next
+    fn close_result(&mut self) -> Transition {
+        match self.state {
+            GameState::Completed => {
+                if !self.demoing {
+                    let mut sc = self.scores.borrow_mut();
+                    sc.set_win(self.level, self.score, self.result_mode());
                 }
-                GameState::Looser => {
+                return Transition::Pop;
+            }
+            GameState::Looser => {
+                if !self.demoing {
                     {
                         let mut sc = self.scores.borrow_mut();
                         sc.set_fail(self.level);
@@ -475,142 +741,190 @@ impl GameField {
                     self.load(self.level);
                     self.score = 0;
                 }
-                GameState::Winner => {
-                    if !self.demoing {
-                        {
-                            let mut sc = self.scores.borrow_mut();
-                            sc.set_win(self.level, self.score);
-                        }
-                        self.level += 1;
-                        self.score = 0;
-                        self.load(self.level);
+            }
+            GameState::Winner => {
+                if !self.demoing {
+                    {
+                        let mut sc = self.scores.borrow_mut();
+                        sc.set_win(self.level, self.score, self.result_mode());
                     }
-                }
-                _ => {
-                    dbg!(self.state);
+                    self.level += 1;
+                    self.score = 0;
+                    self.load(self.level);
                 }
             }
+            _ => {
+                debug!("Unexpected game state: {}", self.state);
+            }
         }
-        Ok(Transition::None)
+        Transition::None
     }
 
     fn draw_background(&mut self, ctx: &mut Context) {
-        let info_w = INFO_WIDTH as i32 * BRICK_SIZE as i32;
-        let bw = self.back_tx.width();
-        let bh = self.back_tx.height();
-        let wn = (SCR_W as i32 - info_w + bw - 1) / bw;
-        let hn = (SCR_H as i32 + bh - 1) / bh;
+        let gfx = match &self.gfx {
+            Some(gfx) => gfx,
+            None => return,
+        };
+        if self.level_bg_pending {
+            self.level_bg_pending = false;
+            if let Some(name) = &self.level_bg {
+                match level_image_path(&gfx.theme, name) {
+                    Some(path) => match Texture::new(ctx, &path) {
+                        Ok(tx) => self.level_bg_tx = Some(tx),
+                        Err(e) => error!("Failed to load background {:?}: {:?}", path, e),
+                    },
+                    None => error!("Background {:?} not found", name),
+                }
+            }
+        }
+
+        let back_tx = self.level_bg_tx.as_ref().unwrap_or(&gfx.back_tx);
+        let area_w = self.board.play_width() as i32 * BRICK_SIZE as i32;
+        let area_h = self.board.height as i32 * BRICK_SIZE as i32;
+        let bw = back_tx.width();
+        let bh = back_tx.height();
+        let wn = (area_w + bw - 1) / bw;
+        let hn = (area_h + bh - 1) / bh;
         for y in 0..hn {
             for x in 0..wn {
                 let pos = Vec2::new((x * bw) as f32, (y * bh) as f32);
-                self.back_tx.draw(ctx, DrawParams::new().position(pos));
+                back_tx.draw(ctx, DrawParams::new().position(pos));
             }
         }
     }
 
-    fn draw_static(&mut self, ctx: &mut Context) {
-        for y in 0..HEIGHT {
-            for x in 0..WIDTH {
-                let t = self.puzzle[pos2puz(x, y)];
+    // walls of the columns from `left` to `right`: the play area scrolls and the info panel does not
+    fn draw_walls(&mut self, ctx: &mut Context, left: usize, right: usize) {
+        let gfx = match &self.gfx {
+            Some(gfx) => gfx,
+            None => return,
+        };
+        for y in 0..self.board.height {
+            for x in left..right {
+                let t = self.puzzle[self.board.pos(x, y)];
                 if t == 0 {
                     continue;
                 }
                 let clip_rect = Rectangle::new(0.0, (t - 1) as f32 * BRICK_SIZE, BRICK_SIZE, BRICK_SIZE);
                 let pos = b2s(x, y);
                 let dp = DrawParams::new().position(pos);
-                self.brick_tx.draw_region(ctx, clip_rect, dp);
+                gfx.brick_tx.draw_region(ctx, clip_rect, dp);
             }
         }
+    }
+
+    fn draw_info(&mut self, ctx: &mut Context) {
+        self.draw_walls(ctx, self.board.play_width(), self.board.width);
+        let gfx = match &mut self.gfx {
+            Some(gfx) => gfx,
+            None => return,
+        };
 
         let first_num_pos = |x: f32, y: f32| -> Vec2<f32> { Vec2::new(x + BRICK_SIZE * 0.25, y + 10.0) };
         let second_num_pos = |x: f32, y: f32| -> Vec2<f32> { Vec2::new(x + BRICK_SIZE * 2.0, y + 10.0) };
 
         // score
-        let x = ((WIDTH - INFO_WIDTH) as f32 + 0.5) * BRICK_SIZE;
+        let x = (self.board.play_width() as f32 + 0.5) * BRICK_SIZE;
         let y = BRICK_SIZE * 3.0;
-        self.throws_tx.draw(ctx, DrawParams::new().position(Vec2::new(x, y)));
+        gfx.throws_tx.draw(ctx, DrawParams::new().position(Vec2::new(x, y)));
         let tp = TextParams::new().with_width(3).with_right_align();
         let n = clamp(self.score, 999);
-        self.txt_num.draw(ctx, first_num_pos(x, y), n, tp);
-        #[allow(clippy::comparison_chain)]
+        gfx.txt_num.draw(ctx, first_num_pos(x, y), n, tp);
         if self.lvl_score.hiscore != 0 {
-            let dev_hiscore =
-                if self.level >= RECORD_LEN || self.level == 0 { self.lvl_score.hiscore } else { RECORDS[self.level] };
-            let mut tp_hscore = TextParams::new().with_width(3).with_right_align();
-            if self.lvl_score.hiscore < dev_hiscore {
-                tp_hscore = tp_hscore.with_color(Color::rgb(0.0, 0.8, 0.3));
-            } else if self.lvl_score.hiscore > dev_hiscore {
-                tp_hscore = tp_hscore.with_color(Color::rgb(0.0, 0.3, 0.8));
-            }
-            self.txt_num.draw(ctx, second_num_pos(x, y), self.lvl_score.hiscore, tp_hscore);
+            let hiscore = self.lvl_score.hiscore;
+            let tp_hscore = TextParams::new().with_width(3).with_right_align();
+            // a level without a developer record is never colored
+            let tp_hscore = match self.scores.borrow().record(self.level) {
+                Some(r) if hiscore < r => tp_hscore.with_color(Color::rgb(0.0, 0.8, 0.3)),
+                Some(r) if hiscore > r => tp_hscore.with_color(Color::rgb(0.0, 0.3, 0.8)),
+                _ => tp_hscore,
+            };
+            gfx.txt_num.draw(ctx, second_num_pos(x, y), hiscore, tp_hscore);
         }
 
         // level # in game, replay progress in demo
         let y = BRICK_SIZE * 1.0;
-        self.level_no_tx.draw(ctx, DrawParams::new().position(Vec2::new(x, y)));
+        gfx.level_no_tx.draw(ctx, DrawParams::new().position(Vec2::new(x, y)));
 
         if self.demoing {
             return;
         }
 
-        let digit_size = self.txt_num.digit_size();
+        let digit_size = gfx.txt_num.digit_size();
         // level #
         let level_digits = digits(self.loader.level_count());
-        let w = (self.level_no_tx.width() / 2) as f32;
-        let lw = f32::from(level_digits) * digit_size.x;
+        let tp = TextParams::new().with_width(level_digits).with_leading_zeroes();
+        let w = (gfx.level_no_tx.width() / 2) as f32;
+        let lw = gfx.txt_num.number_width(level_digits, &tp);
         let pos = Vec2::new(x + w - lw / 2.0, y + 10.0);
-        self.txt_num.draw(
-            ctx,
-            pos,
-            self.level as u32,
-            TextParams::new().with_width(level_digits).with_leading_zeroes(),
-        );
+        gfx.txt_num.draw(ctx, pos, self.level as u32, tp);
 
         // attempts
         let y = BRICK_SIZE * 5.0;
-        self.attempts_tx.draw(ctx, DrawParams::new().position(Vec2::new(x, y)));
+        gfx.attempts_tx.draw(ctx, DrawParams::new().position(Vec2::new(x, y)));
         let tp = TextParams::new().with_width(3).with_right_align();
         let att = clamp(self.lvl_score.attempts, 999);
         let win = clamp(self.lvl_score.wins, 999);
-        self.txt_num.draw(ctx, first_num_pos(x, y), att, tp.clone());
-        self.txt_num.draw(ctx, second_num_pos(x, y), win, tp);
+        gfx.txt_num.draw(ctx, first_num_pos(x, y), att, tp.clone());
+        gfx.txt_num.draw(ctx, second_num_pos(x, y), win, tp);
 
         // solved on
         let y = BRICK_SIZE * 7.0;
-        self.solved_tx.draw(ctx, DrawParams::new().position(Vec2::new(x, y)));
+        gfx.solved_tx.draw(ctx, DrawParams::new().position(Vec2::new(x, y)));
         if self.lvl_score.first_win > 0 {
-            let dw = digit_size.x;
+            let mut tp = TextParams::new().with_width(2).with_leading_zeroes();
+            // two-digit parts of the date are separated by half of a digit
+            let dw = gfx.txt_num.number_width(2, &tp) + digit_size.x * 0.5;
             let dt: NaiveDate = NaiveDate::from_num_days_from_ce_opt(self.lvl_score.first_win)
                 .unwrap_or_else(|| Local::now().date_naive());
-            let mut tp = TextParams::new().with_width(2).with_leading_zeroes();
             // change color if help had been used before the level was solved
             if self.lvl_score.help_used {
                 tp = tp.with_color(Color::rgb(0.0, 0.7, 0.7));
             }
             let year = (dt.year() as u32) % 100;
-            self.txt_num.draw(ctx, first_num_pos(x, y), year, tp.clone());
+            gfx.txt_num.draw(ctx, first_num_pos(x, y), year, tp.clone());
             let month = dt.month();
-            self.txt_num.draw(ctx, first_num_pos(x + dw * 2.5, y), month, tp.clone());
+            gfx.txt_num.draw(ctx, first_num_pos(x + dw, y), month, tp.clone());
             let day = dt.day();
-            self.txt_num.draw(ctx, first_num_pos(x + dw * 5.0, y), day, tp);
+            gfx.txt_num.draw(ctx, first_num_pos(x + dw * 2.0, y), day, tp);
         };
     }
 
     fn draw_bricks(&mut self, ctx: &mut Context) {
+        let gfx = match &self.gfx {
+            Some(gfx) => gfx,
+            None => return,
+        };
         for b in self.bricks.iter() {
             let clip_rect = Rectangle::new(0.0, brick2shift(b.kind), BRICK_SIZE, BRICK_SIZE);
             let dp = DrawParams::new().position(b.scr_pos);
-            self.brick_tx.draw_region(ctx, clip_rect, dp);
+            gfx.brick_tx.draw_region(ctx, clip_rect, dp);
         }
     }
 
     fn draw_player(&mut self, ctx: &mut Context) {
+        let gfx = match &self.gfx {
+            Some(gfx) => gfx,
+            None => return,
+        };
         let clip_rect = Rectangle::new(0.0, brick2shift(self.player.kind), BRICK_SIZE, BRICK_SIZE);
-        let dp = DrawParams::new().position(self.player.scr_pos);
-        self.brick_tx.draw_region(ctx, clip_rect, dp);
-
+        let mut pos = self.player.scr_pos;
         if !self.player.is_moving() {
-            let color = if self.first_brick != BrickKind::None
+            pos.y += self.slide;
+        }
+        // the bob is only drawn: the logical position of the block does not change
+        if self.idle_animation && !self.demoing && !self.going_back && !self.player.is_moving() {
+            let phase = self.idle_frames as f32 / IDLE_PERIOD as f32 * 2.0 * PI;
+            pos.y -= phase.sin() * IDLE_AMPLITUDE;
+        }
+        let dp = DrawParams::new().position(pos);
+        gfx.brick_tx.draw_region(ctx, clip_rect, dp);
+
+        if let (false, Some(apos)) = (self.player.is_moving(), self.arrow_pos) {
+            let color = if self.cooldown > 0 {
+                // the arrow is grey until player's block is ready for the next throw
+                Color::rgb(0.5, 0.5, 0.5)
+            } else if self.first_brick != BrickKind::None
                 && (self.first_brick == self.player.kind || self.player.kind == BrickKind::Joker)
             {
                 Color::rgb(0.0, 0.8, 0.2)
@@ -619,16 +933,107 @@ impl GameField {
             };
             let rotate: f32 = if self.arrow_down { 0.0 } else { PI / 2.0 };
 
-            self.arrow_animation.draw(ctx, DrawParams::new().position(self.arrow_pos).color(color).rotation(rotate));
+            gfx.arrow_animation.draw(ctx, DrawParams::new().position(apos).color(color).rotation(rotate));
+        }
+    }
+
+    // draws a semi-transparent copy of player's block at the row the next throw must be made from.
+    // If the block is already at that row, the copy is drawn a block to the left to hint a throw
+    pub fn draw_hint(&mut self, ctx: &mut Context, row: usize) {
+        let gfx = match &self.gfx {
+            Some(gfx) => gfx,
+            None => return,
+        };
+        let x = if row == self.player.y { self.player.x - 1 } else { self.player.x };
+        let clip_rect = Rectangle::new(0.0, brick2shift(self.player.kind), BRICK_SIZE, BRICK_SIZE);
+        let dp = DrawParams::new().position(b2s(x, row)).color(Color::rgba(1.0, 1.0, 1.0, 0.4));
+        gfx.brick_tx.draw_region(ctx, clip_rect, dp);
+    }
+
+    // draws a semi-transparent "ghost" of a block of the kind at the row and its aim arrow.
+    // `aim` is the direction of the throw and the cell the arrow points at
+    pub fn draw_ghost(&mut self, ctx: &mut Context, row: usize, kind: BrickKind, aim: Option<(bool, usize, usize)>) {
+        let gfx = match &self.gfx {
+            Some(gfx) => gfx,
+            None => return,
+        };
+        let color = Color::rgba(1.0, 1.0, 1.0, 0.3);
+        let clip_rect = Rectangle::new(0.0, brick2shift(kind), BRICK_SIZE, BRICK_SIZE);
+        let dp = DrawParams::new().position(b2s(self.player.x, row)).color(color);
+        gfx.brick_tx.draw_region(ctx, clip_rect, dp);
+        if let Some((is_down, x, y)) = aim {
+            let rotate: f32 = if is_down { 0.0 } else { PI / 2.0 };
+            let dp = DrawParams::new().position(arrow_pos(is_down, x, y)).color(color).rotation(rotate);
+            gfx.arrow_animation.draw(ctx, dp);
+        }
+    }
+
+    // modes the current attempt is played in
+    pub fn result_mode(&self) -> ModeFlags {
+        let mut mode = self.mode;
+        if self.show_throwable || self.show_all_arrows || self.deadlock_warning || self.hint_used || self.undo_used {
+            mode.set(ModeFlags::ASSISTED);
+        }
+        mode
+    }
+
+    // rating of the current solution of the level
+    pub fn stars(&self) -> u8 {
+        let goal = level_goal(self.scores.borrow().record(self.level), self.lvl_score.hiscore, self.score);
+        stars_for(self.score, goal)
+    }
+
+    // highlights the edge of player's column in every row a throw can be made from
+    fn draw_throwable(&mut self, ctx: &mut Context) -> tetra::Result {
+        if !self.show_throwable || self.demoing || !self.is_interactive() || self.player.is_moving() {
+            return Ok(());
+        }
+        if self.glow.is_none() {
+            let rect = Rectangle::new(0.0, 0.0, GLOW_WIDTH, BRICK_SIZE);
+            self.glow = Some(Mesh::rectangle(ctx, ShapeStyle::Fill, rect)?);
+        }
+        if let Some(glow) = &self.glow {
+            for row in self.throwable.iter() {
+                let dp = DrawParams::new().position(b2s(self.player.x, *row)).color(Color::rgba(0.0, 0.8, 0.2, 0.5));
+                glow.draw(ctx, dp);
+            }
+        }
+        Ok(())
+    }
+
+    // faded arrows for all other rows: green if the current block can be thrown from the row
+    fn draw_row_arrows(&mut self, ctx: &mut Context) {
+        if !self.show_all_arrows || self.demoing || !self.is_interactive() || self.player.is_moving() {
+            return;
+        }
+        let gfx = match &self.gfx {
+            Some(gfx) => gfx,
+            None => return,
+        };
+        for (pos, is_down, matched) in self.row_arrows.iter() {
+            let color = if *matched { Color::rgba(0.0, 0.8, 0.2, 0.35) } else { Color::rgba(0.6, 0.6, 0.6, 0.35) };
+            let rotate: f32 = if *is_down { 0.0 } else { PI / 2.0 };
+            gfx.arrow_animation.draw(ctx, DrawParams::new().position(*pos).color(color).rotation(rotate));
         }
     }
 
     pub fn draw(&mut self, ctx: &mut Context) -> tetra::Result<Transition> {
-        graphics::clear(ctx, Color::rgb(0.094, 0.11, 0.16));
+        if self.gfx.is_none() {
+            return Ok(Transition::None);
+        }
+        graphics::clear(ctx, self.clear_color);
+        graphics::set_transform_matrix(ctx, self.play_transform());
         self.draw_background(ctx);
-        self.draw_static(ctx);
+        self.draw_walls(ctx, 0, self.board.play_width());
+        self.draw_throwable(ctx)?;
         self.draw_bricks(ctx);
+        self.draw_row_arrows(ctx);
         self.draw_player(ctx);
+        self.confetti.draw(ctx)?;
+        graphics::set_transform_matrix(ctx, self.info_transform());
+        self.draw_info(ctx);
+        self.draw_minimap(ctx)?;
+        graphics::reset_transform_matrix(ctx);
 
         Ok(Transition::None)
     }
@@ -637,13 +1042,142 @@ impl GameField {
         if self.player.is_moving() {
             return;
         }
-        if self.player.y < HEIGHT - 2 {
+        if self.player.y < self.board.bottom() {
             self.player.y += 1;
             self.player.scr_pos = b2s(self.player.x, self.player.y);
+            self.slide_from(-BRICK_SIZE);
         }
         self.recalc_arrow();
     }
 
+    // replaces the level set. The caller must load a level of the new set right after it
+    pub fn set_loader(&mut self, loader: Rc<Loader>) {
+        self.loader = loader;
+    }
+
+    pub fn board(&self) -> Board {
+        self.board
+    }
+
+    // the scale the board is drawn at: a big board is scaled down to fit the screen or, if it
+    // scrolls, to fit the screen width only
+    pub fn view_scale(&self) -> f32 {
+        if self.board_scroll {
+            self.board.scroll_scale()
+        } else {
+            self.board.scale()
+        }
+    }
+
+    // the board scrolls, so the info panel shows the minimap of the whole board
+    pub fn has_minimap(&self) -> bool {
+        self.board_scroll && self.board.scrolls(self.board.scroll_scale())
+    }
+
+    // transformation of the play area and everything drawn over it: scaled and scrolled
+    pub fn play_transform(&self) -> Mat4<f32> {
+        self.info_transform() * Mat4::translation_3d(Vec3::new(0.0, -self.scroll, 0.0))
+    }
+
+    // transformation of the info panel: it is scaled with the board but it never scrolls
+    pub fn info_transform(&self) -> Mat4<f32> {
+        let scale = self.view_scale();
+        Mat4::scaling_3d(Vec3::new(scale, scale, 1.0))
+    }
+
+    // the point of the board under a point of the screen
+    pub fn screen_to_board(&self, pos: Vec2<f32>) -> Vec2<f32> {
+        pos / self.view_scale() + Vec2::new(0.0, self.scroll)
+    }
+
+    // The lowest point of the info panel free for text. The panel of a scrolling board is cut
+    // by the bottom of the screen, and the minimap is at the bottom of the panel
+    pub fn info_bottom(&self) -> f32 {
+        if self.has_minimap() {
+            self.minimap_pos().y - BRICK_SIZE * 0.5
+        } else {
+            self.panel_height() - BRICK_SIZE
+        }
+    }
+
+    // height of the info panel visible on the screen
+    fn panel_height(&self) -> f32 {
+        (self.board.height as f32 * BRICK_SIZE).min(SCR_H / self.view_scale())
+    }
+
+    // top left corner of the minimap: one block above the bottom of the visible info panel
+    fn minimap_pos(&self) -> Vec2<f32> {
+        let x = (self.board.play_width() as f32 + 0.5) * BRICK_SIZE;
+        Vec2::new(x, self.panel_height() - BRICK_SIZE - self.board.height as f32 * MINIMAP_CELL)
+    }
+
+    // The whole play area at a small scale with a frame around its part visible on the screen.
+    // Walls, blocks, and player's block are the same images as on the board
+    fn draw_minimap(&mut self, ctx: &mut Context) -> tetra::Result {
+        if !self.has_minimap() {
+            return Ok(());
+        }
+        let k = MINIMAP_CELL / BRICK_SIZE;
+        let origin = self.minimap_pos();
+        let view_h = SCR_H / self.view_scale();
+        if self.minimap_frame.as_ref().map(|(b, _)| *b) != Some(self.board) {
+            let w = self.board.play_width() as f32 * MINIMAP_CELL;
+            let rect = Rectangle::new(0.0, 0.0, w, view_h * k);
+            self.minimap_frame = Some((self.board, Mesh::rectangle(ctx, ShapeStyle::Stroke(1.0), rect)?));
+        }
+        let gfx = match &self.gfx {
+            Some(gfx) => gfx,
+            None => return Ok(()),
+        };
+        let dp = |pos: Vec2<f32>| DrawParams::new().position(origin + pos * k).scale(Vec2::new(k, k));
+        for y in 0..self.board.height {
+            for x in 0..self.board.play_width() {
+                let t = self.puzzle[self.board.pos(x, y)];
+                if t != 0 {
+                    let clip_rect = Rectangle::new(0.0, (t - 1) as f32 * BRICK_SIZE, BRICK_SIZE, BRICK_SIZE);
+                    gfx.brick_tx.draw_region(ctx, clip_rect, dp(b2s(x, y)));
+                }
+            }
+        }
+        for b in self.bricks.iter().chain(std::iter::once(&self.player)) {
+            let clip_rect = Rectangle::new(0.0, brick2shift(b.kind), BRICK_SIZE, BRICK_SIZE);
+            gfx.brick_tx.draw_region(ctx, clip_rect, dp(b.scr_pos));
+        }
+        if let Some((_, mesh)) = &self.minimap_frame {
+            mesh.draw(ctx, DrawParams::new().position(origin + Vec2::new(0.0, self.scroll * k)));
+        }
+        Ok(())
+    }
+
+    pub fn player_y(&self) -> usize {
+        self.player.y
+    }
+
+    // moves player's block to the row at once. It is the same as a few up or down moves
+    pub fn set_player_row(&mut self, row: usize) {
+        if self.player.is_moving() {
+            return;
+        }
+        let row = row.clamp(1, self.board.bottom());
+        let shift = (self.player.y as f32 - row as f32) * BRICK_SIZE;
+        self.player.y = row;
+        self.player.scr_pos = b2s(self.player.x, self.player.y);
+        self.slide_from(shift);
+        self.recalc_arrow();
+    }
+
+    // The move itself is instant, only the drawn block slides. So replays, throws, and the
+    // arrow always use the real row, and a throw right after a move is never delayed.
+    // The slide is limited to one row, so quick moves do not make the block lag far behind
+    fn slide_from(&mut self, shift: f32) {
+        self.slide = (self.slide + shift).clamp(-BRICK_SIZE, BRICK_SIZE);
+    }
+
+    // player's block can be moved up and down only while it is not flying
+    pub fn can_move(&self) -> bool {
+        !self.player.is_moving()
+    }
+
     pub fn player_up(&mut self) {
         if self.player.is_moving() {
             return;
@@ -651,45 +1185,122 @@ impl GameField {
         if self.player.y > 1 {
             self.player.y -= 1;
             self.player.scr_pos = b2s(self.player.x, self.player.y);
+            self.slide_from(BRICK_SIZE);
         }
         self.recalc_arrow();
     }
 
+    // Looks up to `max_depth` throws ahead for a way to solve the level. Returns false only
+    // if the current board definitely cannot be solved. The check is expensive, so it
+    // should be called only after the board has settled
+    pub fn is_currently_solvable(&self, max_depth: u32) -> bool {
+        search(self.to_sim(), max_depth, DEADLOCK_BOARDS) != Search::Unsolvable
+    }
+
+    // Looks for a throw that makes progress: it must not leave a board that cannot be solved.
+    // Every row is checked up to `max_depth` throws ahead, and the row with the shortest known
+    // solution wins. If no solution is found within the limits, the row closest to player's
+    // block that does not lead to a dead end is returned. None - every throw is a dead end
+    pub fn hint_throw(&self, max_depth: u32) -> Option<usize> {
+        self.hint_within(max_depth, HINT_BOARDS)
+    }
+
+    // `max_boards` is shared by the rows: every row a throw can be made from gets an equal part
+    fn hint_within(&self, max_depth: u32, max_boards: usize) -> Option<usize> {
+        let sim = self.to_sim();
+        let starts: Vec<(usize, BoardSim)> = (1..self.board.height - 1)
+            .filter_map(|row| {
+                let mut next = sim.clone();
+                next.row = row;
+                if !next.throw_brick() || next.state == GameState::Looser {
+                    None
+                } else {
+                    Some((row, next))
+                }
+            })
+            .collect();
+        let row_boards = (max_boards / starts.len().max(1)).max(1);
+        let mut best: Option<(u32, usize, usize)> = None; // throws to win (MAX if unknown), distance, row
+        for (row, next) in starts {
+            let throws = match search(next, max_depth, row_boards) {
+                Search::Solved(n) => n,
+                Search::Unknown => u32::MAX,
+                Search::Unsolvable => continue,
+            };
+            let cand = (throws, row.abs_diff(sim.row), row);
+            if best.is_none() || Some(cand) < best {
+                best = Some(cand);
+            }
+        }
+        best.map(|(_, _, row)| row)
+    }
+
+    // returns a headless copy of the current board
+    pub fn to_sim(&self) -> BoardSim {
+        let bricks: Vec<(usize, usize, BrickKind)> = self.bricks.iter().map(|b| (b.x, b.y, b.kind)).collect();
+        BoardSim::from_parts(
+            self.board,
+            &self.puzzle,
+            &bricks,
+            self.player.kind,
+            self.player.y,
+            self.score,
+            self.match_n,
+        )
+    }
+
     // should return error?
     pub fn load(&mut self, lvl_no: usize) {
+        self.level = lvl_no;
         self.state = GameState::Unfinished;
-        self.puzzle = [0u32; WIDTH * HEIGHT];
+        self.confetti.stop();
+        self.slide = 0.0;
+        let lvl = self.loader.level(lvl_no);
+        let board = lvl.board;
+        self.board = board;
+        self.puzzle = vec![0u32; board.cells()];
 
         // top and bottom lines
-        for i in 0..WIDTH {
-            self.puzzle[pos2puz(i, 0)] = 1;
-            self.puzzle[pos2puz(i, HEIGHT - 1)] = 1;
+        for i in 0..board.width {
+            self.puzzle[board.pos(i, 0)] = 1;
+            self.puzzle[board.pos(i, board.height - 1)] = 1;
         }
         // info panel
-        for i in 1..HEIGHT - 1 {
-            self.puzzle[pos2puz(0, i)] = 1;
+        for i in 1..board.height - 1 {
+            self.puzzle[board.pos(0, i)] = 1;
             for p in 0..INFO_WIDTH {
-                self.puzzle[pos2puz(WIDTH - p - 1, i)] = 1;
+                self.puzzle[board.pos(board.width - p - 1, i)] = 1;
             }
         }
 
-        let lvl = self.loader.level(lvl_no);
-        self.player = Brick::new(WIDTH - INFO_WIDTH - 1, HEIGHT - 2, lvl.first);
+        self.player = Brick::new(board.play_width() - 1, board.bottom(), lvl.first);
+        // a new level is shown at once with player's block in view
+        self.scroll = board.follow(0.0, self.player.scr_pos.y, self.view_scale(), f32::MAX);
+        self.going_back = false;
+        self.cooldown = 0;
+        self.match_n = lvl.match_n;
+        self.hint_used = false;
+        self.undo_used = false;
+        self.undo_stack.clear();
+        self.throw_start = None;
+        if self.level_bg != lvl.background {
+            self.level_bg = lvl.background.clone();
+            self.level_bg_tx = None;
+            self.level_bg_pending = self.level_bg.is_some();
+        }
 
         // corner
         if lvl.corner.is_empty() {
-            for i in 1..=MAX_SIZE {
-                for j in 1..=(MAX_SIZE - i) {
-                    self.puzzle[pos2puz(j, i)] = 1;
+            for i in 1..=board.max_size {
+                for j in 1..=(board.max_size - i) {
+                    self.puzzle[board.pos(j, i)] = 1;
                 }
             }
         } else {
-            let mut y = 1usize;
-            for line_len in lvl.corner.iter() {
-                for x in 1..=*line_len {
-                    self.puzzle[pos2puz(x, y as u8)] = 1;
+            for (y, line_len) in (1usize..).zip(lvl.corner.iter()) {
+                for x in 1..=*line_len as usize {
+                    self.puzzle[board.pos(x, y)] = 1;
                 }
-                y += 1;
             }
         }
 
@@ -700,7 +1311,7 @@ impl GameField {
                 if *brick == BrickKind::None {
                     continue;
                 }
-                let y = HEIGHT - cnt + yidx - 1;
+                let y = board.height - cnt + yidx - 1;
                 let x = xidx + 1;
                 self.bricks.push(Brick::new(x, y, *brick));
             }
@@ -712,16 +1323,27 @@ impl GameField {
 
     fn can_throw(&self) -> bool {
         !self.player.is_moving()
+            && self.cooldown == 0
             && self.state == GameState::Unfinished
             && self.first_brick != BrickKind::None
             && (self.player.kind == BrickKind::Joker || self.player.kind == self.first_brick)
     }
 
-    pub fn throw_brick(&mut self) {
+    // starts a throw. Returns false if the throw is impossible
+    pub fn throw_brick(&mut self) -> bool {
         if !self.can_throw() {
-            return;
+            return false;
         }
+        self.slide = 0.0;
+        self.finish_throw();
+        self.throw_start = Some(ThrowStart {
+            blocks: self.blocks(),
+            player: block_of(&self.player),
+            score: self.score,
+            state: self.state,
+        });
         self.score += 1;
+        self.column_matched = 0;
         self.player_row = self.player.y;
         let bricks = self.bricks.iter().filter(|b| b.y == self.player.y);
         let mut x = 0;
@@ -731,41 +1353,77 @@ impl GameField {
             }
         }
         if x == 0 {
-            for i in 0..MAX_SIZE + 4 {
-                if self.puzzle[self.player.y * WIDTH + i] != 0 {
+            for i in 0..self.board.max_size + 4 {
+                if self.puzzle[self.board.pos(i, self.player.y)] != 0 {
                     x = i;
                 }
             }
         }
         x += 1;
         self.player.start_moving(Vec2::new(-BRICK_DEF_SPEED, 0.0), b2s(x, self.player.y));
+        self.play(SfxKind::Throw);
+        true
     }
 
-    fn target(&self, row: usize) -> (bool, usize, usize, BrickKind) {
+    // the most memory in bytes the undo history can take. The oldest throws are forgotten
+    // when the history grows bigger
+    pub fn set_undo_budget(&mut self, bytes: usize) {
+        self.undo_stack.set_budget(bytes);
+    }
+
+    // blocks of the board. A falling block is where it comes to rest
+    fn blocks(&self) -> Vec<Block> {
+        self.bricks.iter().map(block_of).collect()
+    }
+
+    // puts the change of the board made by the last throw to the undo history
+    fn finish_throw(&mut self) {
+        if let Some(start) = self.throw_start.take() {
+            let delta = Delta::new(&start.blocks, &self.blocks(), start.player, start.score, start.state);
+            self.undo_stack.push(delta);
+        }
+    }
+
+    // Restores the board as it was before the last throw. It works only between throws
+    // of an unfinished or a lost level. Returns false if there is nothing to undo
+    pub fn undo(&mut self) -> bool {
+        // the throw that lost the level can be undone, a solved level cannot be changed
+        let solved = self.state == GameState::Winner || self.state == GameState::Completed;
+        if solved || !self.is_settled() {
+            return false;
+        }
+        self.finish_throw();
+        let delta = match self.undo_stack.pop() {
+            Some(delta) => delta,
+            None => return false,
+        };
+        self.bricks = delta.revert(&self.blocks()).iter().map(|b| Brick::new(b.x.into(), b.y.into(), b.kind)).collect();
+        self.player = Brick::new(delta.player.x.into(), delta.player.y.into(), delta.player.kind);
+        self.score = delta.score;
+        self.state = delta.state;
+        self.undo_used = true;
+        self.recalc_arrow();
+        true
+    }
+
+    // returns the direction of a throw from the row, the position of the cell where player's
+    // block stops, and the kind of the first block it hits. None if nothing can be hit from the row
+    fn target(&self, row: usize) -> Option<(bool, usize, usize, BrickKind)> {
         let mut first = BrickKind::None;
         let mut bx: usize = 0;
         let mut by: usize = row;
 
-        let down = if row < HEIGHT - 1 - MAX_SIZE { true } else { !self.bricks.iter().any(|b| b.y == row) };
+        let down = if row < self.board.puzzle_top() { true } else { !self.bricks.iter().any(|b| b.y == row) };
 
         if down {
-            bx = if row >= HEIGHT - 1 - MAX_SIZE {
+            bx = if row >= self.board.puzzle_top() {
                 1
             } else {
-                let mut n: usize = 0;
-                for i in 0..MAX_SIZE + 4 {
-                    if self.puzzle[row * WIDTH + i] == 0 {
-                        n = i;
-                        break;
-                    }
-                }
-                if n == 0 {
-                    panic!("Nothing found");
-                };
-                n
+                // a row without a free cell after the left wall is malformed: nothing to hit there
+                (1..self.board.max_size + 4).find(|i| self.puzzle[self.board.pos(*i, row)] == 0)?
             };
             let bricks = self.bricks.iter().filter(|b| b.x == bx && b.y >= row);
-            by = HEIGHT - 1;
+            by = self.board.height - 1;
             for brick in bricks {
                 if brick.y < by {
                     by = brick.y;
@@ -786,18 +1444,43 @@ impl GameField {
             }
         }
 
-        (down, bx, by, first)
+        Some((down, bx, by, first))
     }
 
     fn recalc_arrow(&mut self) {
-        let (is_down, x, y, brick) = self.target(self.player.y);
-        if is_down {
-            self.arrow_pos = b2s(x, y);
-        } else {
-            self.arrow_pos = b2s(x + 1, y);
+        match self.target(self.player.y) {
+            Some((is_down, x, y, brick)) => {
+                self.arrow_pos = Some(arrow_pos(is_down, x, y));
+                self.first_brick = brick;
+                self.arrow_down = is_down;
+            }
+            None => {
+                self.arrow_pos = None;
+                self.first_brick = BrickKind::None;
+            }
         }
-        self.first_brick = brick;
-        self.arrow_down = is_down;
+        self.throwable = self.throwable_rows();
+        self.row_arrows = (1..self.board.height - 1)
+            .filter(|row| *row != self.player.y)
+            .filter_map(|row| {
+                let (is_down, x, y, kind) = self.target(row)?;
+                if kind == BrickKind::None {
+                    return None;
+                }
+                let matched = self.player.kind == BrickKind::Joker || kind == self.player.kind;
+                Some((arrow_pos(is_down, x, y), is_down, matched))
+            })
+            .collect();
+    }
+
+    // all rows where a throw of the current player's block would annihilate a block
+    pub fn throwable_rows(&self) -> Vec<usize> {
+        (1..self.board.height - 1)
+            .filter(|y| {
+                let kind = self.target(*y).map_or(BrickKind::None, |t| t.3);
+                kind != BrickKind::None && (self.player.kind == BrickKind::Joker || kind == self.player.kind)
+            })
+            .collect()
     }
 
     pub fn calc_state(&self) -> GameState {
@@ -811,8 +1494,12 @@ impl GameField {
             return GameState::Unfinished;
         }
 
-        for y in 1..HEIGHT - 1 {
-            let (_d, _x, _y, kind) = self.target(y);
+        for y in 1..self.board.height - 1 {
+            // rows without a target are unplayable
+            let kind = match self.target(y) {
+                Some((_d, _x, _y, kind)) => kind,
+                None => continue,
+            };
             if kind == self.player.kind {
                 return GameState::Unfinished;
             }
@@ -821,3 +1508,210 @@ impl GameField {
         GameState::Looser
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headless_field(lvl: usize) -> GameField {
+        let loader = Rc::new(Loader::new());
+        let scores = Rc::new(RefCell::new(Scores::detached(loader.level_count())));
+        let mut field = GameField::headless(loader, scores);
+        field.load(lvl);
+        field
+    }
+
+    fn settle(field: &mut GameField) {
+        while !field.is_settled() {
+            field.step();
+        }
+    }
+
+    #[test]
+    fn moves_are_ignored_while_block_flies() {
+        let mut field = headless_field(1);
+        let row = (1..=field.board().bottom()).rev().find(|row| {
+            field.set_player_row(*row);
+            field.throw_brick()
+        });
+        let row = row.expect("the level has a row to throw from");
+        assert!(!field.can_move());
+        field.player_up();
+        settle(&mut field);
+        assert!(field.can_move());
+        assert_eq!(field.player_y(), row);
+    }
+
+    // the field aims exactly as the simulation does on every row of every built-in level
+    #[test]
+    fn target_matches_simulation() {
+        let loader = Loader::new();
+        for lvl in 0..loader.level_count() {
+            let field = headless_field(lvl);
+            let sim = BoardSim::new(&loader.level(lvl));
+            for row in 1..field.board().height - 1 {
+                let target = field.target(row).map(|(down, x, y, _)| (down, x, y));
+                assert_eq!(target, sim.aim(row), "level {} row {}", lvl, row);
+            }
+        }
+    }
+
+    #[test]
+    fn malformed_row_has_no_target() {
+        let mut field = headless_field(1);
+        let row = 1;
+        for x in 0..field.board().width {
+            let pos = field.board().pos(x, row);
+            field.puzzle[pos] = 1;
+        }
+        assert!(field.target(row).is_none());
+        field.set_player_row(row);
+        assert!(field.arrow_pos.is_none());
+        assert!(!field.throwable_rows().contains(&row));
+    }
+
+    #[test]
+    fn records_are_bound_by_table() {
+        let last = RECORDS.len() - 1;
+        assert_eq!(record_in_pack(0, false), None);
+        assert_eq!(record_in_pack(1, false), Some(RECORDS[1]));
+        assert_eq!(record_in_pack(last, false), Some(RECORDS[last]));
+        assert_eq!(record_in_pack(last + 1, false), None);
+        assert_eq!(record_in_pack(usize::MAX, false), None);
+        assert_eq!(record_in_pack(1, true), None);
+
+        // every built-in level has a record
+        assert_eq!(RECORDS.len(), Loader::new().level_count());
+    }
+
+    // throws the player's block from the first row it can clear a block in
+    fn throw_matching(field: &mut GameField) {
+        let row = *field.throwable_rows().first().expect("a row to throw from");
+        field.set_player_row(row);
+        assert!(field.throw_brick());
+        settle(field);
+    }
+
+    #[test]
+    fn sounds_follow_game_events() {
+        use crate::loader::Level;
+        // a single block that the first throw clears
+        let puzzle = vec![vec![BrickKind::None, BrickKind::None], vec![BrickKind::K1, BrickKind::None]];
+        let lvl = Level { puzzle, first: BrickKind::K1, ..Level::default() };
+        let loader = Rc::new(Loader::from_levels(vec![lvl.clone(), lvl]).unwrap());
+        let scores = Rc::new(RefCell::new(Scores::detached(loader.level_count())));
+        let mut field = GameField::headless(loader, scores);
+
+        // demo and replays are silent
+        field.load(0);
+        throw_matching(&mut field);
+        assert!(field.take_sounds().is_empty());
+
+        field.sound = true;
+        field.load(0);
+        throw_matching(&mut field);
+        let sounds = field.take_sounds();
+        assert_eq!(sounds, vec![SfxKind::Throw, SfxKind::Match(BrickKind::K1), SfxKind::Bounce, SfxKind::Win]);
+        assert!(field.take_sounds().is_empty());
+
+        field.load(1);
+        throw_matching(&mut field);
+        assert_eq!(field.take_sounds().last(), Some(&SfxKind::Complete));
+    }
+
+    // throws from the first row that clears a block until the level ends. Returns the board
+    // hash before every throw
+    fn play_throws(field: &mut GameField, max: usize) -> Vec<u64> {
+        let mut hashes = Vec::new();
+        while hashes.len() < max && field.state == GameState::Unfinished && !field.throwable_rows().is_empty() {
+            hashes.push(field.to_sim().board_hash());
+            throw_matching(field);
+            while !field.is_interactive() && field.state == GameState::Unfinished {
+                field.step();
+            }
+        }
+        hashes
+    }
+
+    #[test]
+    fn undo_restores_boards_within_budget() {
+        let mut field = headless_field(1);
+        let hashes = play_throws(&mut field, 4);
+        assert!(hashes.len() >= 2);
+        for hash in hashes.iter().rev() {
+            assert!(field.undo());
+            assert_eq!(field.to_sim().board_hash(), *hash);
+        }
+        assert!(!field.undo());
+
+        // a budget for a couple of small throws keeps only the latest ones
+        let mut field = headless_field(1);
+        field.set_undo_budget(200);
+        let hashes = play_throws(&mut field, 4);
+        let mut undone = 0;
+        while field.undo() {
+            undone += 1;
+            assert_eq!(field.to_sim().board_hash(), hashes[hashes.len() - undone]);
+        }
+        assert!(undone >= 1 && undone < hashes.len(), "{} of {}", undone, hashes.len());
+    }
+
+    #[test]
+    fn hint_fits_a_shared_budget() {
+        let field = headless_field(1);
+        let row = field.hint_within(6, HINT_BOARDS).expect("a hint");
+        // the hinted throw keeps the level solvable
+        let mut next = field.to_sim();
+        next.row = row;
+        assert!(next.throw_brick());
+        assert_ne!(search(next, u32::MAX, 100_000), Search::Unsolvable);
+        // a budget too small to look ahead still points at a throw that is not a dead end
+        let row = field.hint_within(6, 1).expect("a hint");
+        let mut next = field.to_sim();
+        next.row = row;
+        assert!(next.throw_brick());
+        assert_ne!(next.state, GameState::Looser);
+    }
+
+    // a field with a level the first throw loses
+    fn lost_field(demoing: bool) -> GameField {
+        use crate::loader::Level;
+        use BrickKind::{None as Empty, K1, K2, K3};
+        // the throw from above clears K1, and then no row leads player's block to a matching one
+        let puzzle = vec![vec![Empty, K3], vec![K1, K2]];
+        let lvl = Level { puzzle, first: K1, ..Level::default() };
+        let loader = Rc::new(Loader::from_levels(vec![lvl.clone(), lvl]).unwrap());
+        let scores = Rc::new(RefCell::new(Scores::detached(loader.level_count())));
+        let mut field = GameField::headless(loader, scores);
+        field.demoing = demoing;
+        field.load(1);
+        throw_matching(&mut field);
+        assert_eq!((field.state, field.score), (GameState::Looser, 1));
+        field
+    }
+
+    #[test]
+    fn demo_keeps_failed_level() {
+        // a played level is restarted, and the fail is counted
+        let mut field = lost_field(false);
+        assert!(matches!(field.close_result(), Transition::None));
+        assert_eq!((field.state, field.score), (GameState::Unfinished, 0));
+        assert_eq!(field.scores.borrow().level_info(1).attempts, 1);
+
+        // a demoing field shows a game played elsewhere: no fail is saved, and the board stays
+        let mut field = lost_field(true);
+        assert!(matches!(field.close_result(), Transition::None));
+        assert_eq!((field.state, field.score), (GameState::Looser, 1));
+        assert_eq!(field.scores.borrow().level_info(1).attempts, 0);
+    }
+
+    #[test]
+    fn losing_throw_can_be_undone() {
+        let mut field = lost_field(false);
+
+        assert!(field.undo());
+        assert_eq!((field.state, field.score), (GameState::Unfinished, 0));
+        assert!(field.undo_used);
+        assert!(!field.throwable_rows().is_empty());
+    }
+}
@@ -0,0 +1,104 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::field::BrickKind;
+use crate::loader::{Level, Loader};
+use crate::solver::min_throws;
+
+// the largest seed: it must fit the info panel
+pub const MAX_SEED: u32 = 999_999;
+// puzzle width and height range
+const MIN_SIDE: u64 = 3;
+const MAX_SIDE: u64 = 5;
+// range of the number of block kinds in a puzzle
+const MIN_KINDS: u64 = 2;
+const MAX_KINDS: u64 = 3;
+// a generated puzzle must need at least this many throws
+const MIN_THROWS: u32 = 3;
+// the solver checks at most this many boards of a generated puzzle. A puzzle it cannot
+// solve within the limit is thrown away, so every generated level is known to be solvable
+const GEN_BOARDS: usize = 20_000;
+const KINDS: [BrickKind; 3] = [BrickKind::K1, BrickKind::K2, BrickKind::K3];
+
+// a new seed every time it is called
+pub fn random_seed() -> u32 {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.subsec_nanos() ^ d.as_secs() as u32);
+    nanos % (MAX_SEED + 1)
+}
+
+// SplitMix64: small and good enough for shuffling blocks. The same seed gives the same
+// numbers on every machine
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    // a number from `lo` to `hi` inclusive
+    fn range(&mut self, lo: u64, hi: u64) -> u64 {
+        lo + self.next() % (hi - lo + 1)
+    }
+}
+
+// Endless sequence of random levels. The levels depend only on the seed, so players can share
+// a seed to play the same levels
+pub struct Generator {
+    seed: u32,
+    rng: Rng,
+    pub solved: u32, // the number of generated levels solved so far
+}
+
+impl Generator {
+    pub fn new(seed: u32) -> Self {
+        let seed = seed.min(MAX_SEED);
+        Generator { seed, rng: Rng(seed as u64), solved: 0 }
+    }
+
+    pub fn seed(&self) -> u32 {
+        self.seed
+    }
+
+    // Generates the next level. The loader contains the level twice: as the demo level that is
+    // never played and as level 1 - the only playable level
+    pub fn next_loader(&mut self) -> Loader {
+        loop {
+            let lvl = self.random_level();
+            match min_throws(&lvl, GEN_BOARDS) {
+                Some(n) if n >= MIN_THROWS => {}
+                _ => continue,
+            }
+            if let Ok(ld) = Loader::from_levels(vec![lvl.clone(), lvl]) {
+                return ld;
+            }
+        }
+    }
+
+    // Random blocks in a rectangle. Every column is filled from the bottom to a random height,
+    // so columns never have holes. At least one column is full-height
+    fn random_level(&mut self) -> Level {
+        let w = self.rng.range(MIN_SIDE, MAX_SIDE) as usize;
+        let h = self.rng.range(MIN_SIDE, MAX_SIDE) as usize;
+        let kinds = self.rng.range(MIN_KINDS, MAX_KINDS) as usize;
+        let full = self.rng.range(0, w as u64 - 1) as usize;
+        let heights: Vec<usize> =
+            (0..w).map(|x| if x == full { h } else { self.rng.range(1, h as u64) as usize }).collect();
+
+        let mut puzzle = vec![vec![BrickKind::None; w]; h];
+        for (x, height) in heights.iter().enumerate() {
+            for row in puzzle.iter_mut().skip(h - height) {
+                row[x] = KINDS[self.rng.range(0, kinds as u64 - 1) as usize];
+            }
+        }
+        // a joker in every third level, otherwise a block of one of the puzzle kinds
+        let first = if self.rng.range(0, 2) == 0 {
+            BrickKind::Joker
+        } else {
+            KINDS[self.rng.range(0, kinds as u64 - 1) as usize]
+        };
+        Level { puzzle, first, ..Level::default() }
+    }
+}
@@ -0,0 +1,172 @@
+use std::collections::HashMap;
+use std::fs::read_to_string;
+
+use log::error;
+use tetra::graphics::mesh::{Mesh, ShapeStyle};
+use tetra::graphics::{Color, DrawParams, Rectangle, Texture};
+use tetra::math::Vec2;
+use tetra::Context;
+
+use crate::common::lang_path;
+use crate::consts::{SCR_H, SCR_W};
+use crate::textnum::{BitmapText, TextParams};
+
+// English strings: the default language and the fallback for strings missing in a translation
+const EN: &str = include_str!("../lang/en.toml");
+// glyph grid of the font texture
+const FONT_COLUMNS: usize = 16;
+const FONT_ROWS: usize = 3;
+// vertical gap between lines of text
+const LINE_GAP: f32 = 6.0;
+// gap between the text and the border of a plate
+const PLATE_PADDING: f32 = 24.0;
+const PLATE_HINT_COLOR: Color = Color::rgb(0.3, 0.7, 1.0);
+
+fn parse(data: &str) -> Result<HashMap<String, String>, String> {
+    toml::from_str(data).map_err(|e| e.to_string())
+}
+
+// Strings of the selected language
+pub struct I18n {
+    strings: HashMap<String, String>,
+    fallback: HashMap<String, String>,
+}
+
+impl I18n {
+    // English is built-in, other languages are loaded from `lang/<code>.toml` in the game
+    // data directory. If the file cannot be loaded, all strings are English
+    pub fn new(lang: &str) -> I18n {
+        let fallback = parse(EN).unwrap_or_else(|e| panic!("Invalid built-in English strings: {}", e));
+        let strings = if lang.is_empty() || lang == "en" {
+            HashMap::new()
+        } else {
+            let path = lang_path(lang);
+            match read_to_string(&path).map_err(|e| e.to_string()).and_then(|data| parse(&data)) {
+                Ok(strings) => strings,
+                Err(e) => {
+                    error!("Failed to load language file {:?}: {}", path, e);
+                    HashMap::new()
+                }
+            }
+        };
+        I18n { strings, fallback }
+    }
+
+    // the string of the language, or English one if it is not translated. Unknown keys are
+    // returned as is
+    pub fn get<'a>(&'a self, key: &'a str) -> &'a str {
+        self.strings.get(key).or_else(|| self.fallback.get(key)).map_or(key, |s| s.as_str())
+    }
+}
+
+// Localized text drawn with the bitmap font. Scenes use it instead of images with English
+// text only if a language is selected in the config
+pub struct Labels {
+    i18n: I18n,
+    font: BitmapText,
+    shade: Option<Mesh>, // background of plates, created at first draw call
+}
+
+impl Labels {
+    // the font is any texture with glyphs in the order `BitmapText` expects
+    pub fn new(font: Texture, lang: &str) -> Labels {
+        Labels { i18n: I18n::new(lang), font: BitmapText::new(font, FONT_COLUMNS, FONT_ROWS), shade: None }
+    }
+
+    // None if no language is selected: scenes draw built-in images then
+    pub fn for_language(font: Texture, lang: &str) -> Option<Labels> {
+        if lang.is_empty() {
+            None
+        } else {
+            Some(Labels::new(font, lang))
+        }
+    }
+
+    pub fn width(&self, key: &str) -> f32 {
+        self.font.text_width(self.i18n.get(key), &TextParams::new())
+    }
+
+    pub fn line_height(&self) -> f32 {
+        self.font.glyph_h() + LINE_GAP
+    }
+
+    pub fn draw(&mut self, ctx: &mut Context, pos: Vec2<f32>, key: &str, color: Color) -> f32 {
+        let text = self.i18n.get(key);
+        self.font.draw(ctx, pos, text, TextParams::new().with_color(color))
+    }
+
+    // width of a text that is not translated
+    pub fn text_width(&self, text: &str) -> f32 {
+        self.font.text_width(text, &TextParams::new())
+    }
+
+    // draws a text that is not translated (e.g, a date)
+    pub fn draw_text(&mut self, ctx: &mut Context, pos: Vec2<f32>, text: &str, color: Color) -> f32 {
+        self.font.draw(ctx, pos, text, TextParams::new().with_color(color))
+    }
+
+    // draws the string centered horizontally around `center_x`
+    pub fn draw_centered(&mut self, ctx: &mut Context, center_x: f32, y: f32, key: &str, color: Color) {
+        let x = center_x - self.width(key) / 2.0;
+        self.draw(ctx, Vec2::new(x, y), key, color);
+    }
+
+    // splits the string into lines no wider than `width`. Line breaks of the string are kept
+    fn wrap(&self, key: &str, width: f32) -> Vec<String> {
+        let max_chars = ((width / self.font.glyph_w()) as usize).max(1);
+        let mut lines = Vec::new();
+        for paragraph in self.i18n.get(key).lines() {
+            let mut line = String::new();
+            for word in paragraph.split_whitespace() {
+                if !line.is_empty() && line.chars().count() + 1 + word.chars().count() > max_chars {
+                    lines.push(std::mem::take(&mut line));
+                }
+                if !line.is_empty() {
+                    line.push(' ');
+                }
+                line.push_str(word);
+            }
+            lines.push(line);
+        }
+        lines
+    }
+
+    // height of the string wrapped to the width
+    pub fn wrapped_height(&self, key: &str, width: f32) -> f32 {
+        self.wrap(key, width).len() as f32 * self.line_height()
+    }
+
+    // draws the string wrapped to the width
+    pub fn draw_wrapped(&mut self, ctx: &mut Context, pos: Vec2<f32>, width: f32, key: &str, color: Color) {
+        let tp = TextParams::new().with_color(color);
+        for (idx, line) in self.wrap(key, width).iter().enumerate() {
+            let y = pos.y + idx as f32 * self.line_height();
+            self.font.draw(ctx, Vec2::new(pos.x, y), line, tp.clone());
+        }
+    }
+
+    // Draws a plate in the center of the screen: a title in the color and a hint below it.
+    // Returns the position and the size of the plate
+    pub fn draw_plate(
+        &mut self,
+        ctx: &mut Context,
+        title: &str,
+        hint: &str,
+        color: Color,
+    ) -> tetra::Result<(Vec2<f32>, Vec2<f32>)> {
+        if self.shade.is_none() {
+            self.shade = Some(Mesh::rectangle(ctx, ShapeStyle::Fill, Rectangle::new(0.0, 0.0, 1.0, 1.0))?);
+        }
+        let w = self.width(title).max(self.width(hint)) + PLATE_PADDING * 2.0;
+        let h = self.line_height() * 2.0 + PLATE_PADDING * 2.0;
+        let pos = Vec2::new((SCR_W - w) / 2.0, (SCR_H - h) / 2.0);
+        if let Some(shade) = &self.shade {
+            let dp = DrawParams::new().position(pos).scale(Vec2::new(w, h)).color(Color::rgba(0.0, 0.0, 0.0, 0.8));
+            shade.draw(ctx, dp);
+        }
+        let y = pos.y + PLATE_PADDING;
+        self.draw_centered(ctx, SCR_W / 2.0, y, title, color);
+        self.draw_centered(ctx, SCR_W / 2.0, y + self.line_height(), hint, PLATE_HINT_COLOR);
+        Ok((pos, Vec2::new(w, h)))
+    }
+}
@@ -0,0 +1,89 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use tetra::graphics::{self, Color, DrawParams, Texture};
+use tetra::math::Vec2;
+use tetra::Context;
+
+use crate::assets::Assets;
+use crate::bindings::{Command, InputBindings};
+use crate::config::Config;
+use crate::scenes::{Scene, Transition};
+use crate::scores::Scores;
+use crate::stars::{draw_stars, mode_color, STAR_SIZE};
+use crate::textnum::{TextNumber, TextParams};
+
+// the number of best results on the screen
+const MAX_ENTRIES: usize = 12;
+// column positions
+const LEVEL_X: f32 = 220.0;
+const THROWS_X: f32 = 420.0;
+const DELTA_X: f32 = 560.0;
+const STARS_X: f32 = 700.0;
+const TOP_Y: f32 = 60.0;
+
+// "Hall of fame": the best results relative to the developer records
+pub struct LeaderboardScene {
+    assets: Rc<Assets>,
+    scores: Rc<RefCell<Scores>>,
+    config: Rc<RefCell<Config>>,
+    txt_num: TextNumber,
+    keys: InputBindings,
+    level_tx: Texture,
+    throws_tx: Texture,
+}
+
+impl LeaderboardScene {
+    pub fn new(assets: Rc<Assets>, sc: Rc<RefCell<Scores>>, config: Rc<RefCell<Config>>) -> Self {
+        let keys = InputBindings::new(&config.borrow());
+        LeaderboardScene {
+            txt_num: TextNumber::new(assets.numbers.clone()),
+            keys,
+            level_tx: assets.level_no.clone(),
+            throws_tx: assets.throws.clone(),
+            assets,
+            scores: sc,
+            config,
+        }
+    }
+}
+
+impl Scene for LeaderboardScene {
+    fn update(&mut self, ctx: &mut Context) -> tetra::Result<Transition> {
+        if self.keys.is_pressed(ctx, Command::Back) || self.keys.is_pressed(ctx, Command::Confirm) {
+            return Ok(Transition::Pop);
+        }
+        Ok(Transition::None)
+    }
+
+    fn draw(&mut self, ctx: &mut Context) -> tetra::Result<Transition> {
+        graphics::clear(ctx, self.config.borrow().clear_color());
+
+        self.level_tx.draw(ctx, DrawParams::new().position(Vec2::new(LEVEL_X, TOP_Y)));
+        self.throws_tx.draw(ctx, DrawParams::new().position(Vec2::new(THROWS_X, TOP_Y)));
+
+        let line_h = self.txt_num.digit_size().y + 16.0;
+        let mut y = TOP_Y + self.level_tx.height().max(self.throws_tx.height()) as f32 + 20.0;
+        let scores = self.scores.borrow();
+        for (lvl, throws, delta) in scores.leaderboard().into_iter().take(MAX_ENTRIES) {
+            let tp = TextParams::new().with_width(3).with_right_align();
+            self.txt_num.draw(ctx, Vec2::new(LEVEL_X, y), lvl as u32, tp.clone());
+            self.txt_num.draw(ctx, Vec2::new(THROWS_X, y), throws, tp.clone());
+            // the difference with the record in the same colors as hiscores in the game:
+            // green - the record is beaten, blue - worse than the record
+            let color = match delta {
+                d if d < 0 => Color::rgb(0.0, 0.8, 0.3),
+                d if d > 0 => Color::rgb(0.0, 0.3, 0.8),
+                _ => Color::WHITE,
+            };
+            self.txt_num.draw(ctx, Vec2::new(DELTA_X, y), delta.unsigned_abs(), tp.with_color(color));
+
+            let info = scores.level_info(lvl);
+            let pos = Vec2::new(STARS_X + STAR_SIZE, y + line_h * 0.5 - 8.0);
+            draw_stars(ctx, &self.assets.star, pos, info.best_stars, mode_color(info.hiscore_mode));
+            y += line_h;
+        }
+
+        Ok(Transition::None)
+    }
+}
@@ -1,3 +1,11 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::read_to_string;
+use std::path::Path;
+
+use log::debug;
+
+use crate::board::{Board, MAX_BOARD_SIZE};
 use crate::consts::MAX_SIZE;
 use crate::field::BrickKind;
 
@@ -6,6 +14,23 @@ const STD_LEVELS: &str = include_str!("../assets/std_puzzles");
 // if starting block is not set for a level in the file, use this one
 const DEFAULT_KIND: BrickKind = BrickKind::Joker;
 
+// letters that encode blocks (see `c2brick`). A metadata key must contain a letter that is
+// not one of them, so a puzzle line with `:` blocks is never taken for metadata
+const BLOCK_LETTERS: &str = "sxotzwvyu";
+
+// Splits a metadata line `key:value` into the key and the trimmed value. A key is a lowercase
+// word that is not made of block letters only
+fn parse_meta(line: &str) -> Option<(&str, &str)> {
+    let (key, value) = line.split_once(':')?;
+    let is_key = key.chars().all(|c| c.is_ascii_lowercase() || c == '_')
+        && key.chars().any(|c| c.is_ascii_lowercase() && !BLOCK_LETTERS.contains(c));
+    if is_key {
+        Some((key, value.trim()))
+    } else {
+        None
+    }
+}
+
 // convert character to type of a block
 fn c2brick(c: char) -> BrickKind {
     match c {
@@ -15,6 +40,9 @@ fn c2brick(c: char) -> BrickKind {
         'T' | 't' | '=' | '4' => BrickKind::K4,
         'Z' | 'z' | '+' | '5' => BrickKind::K5,
         'W' | 'w' | ':' | '6' => BrickKind::K6,
+        'V' | 'v' | '&' | '7' => BrickKind::K7,
+        'Y' | 'y' | '^' | '8' => BrickKind::K8,
+        'U' | 'u' | '~' | '9' => BrickKind::K9,
         '?' => BrickKind::Joker,
         _ => BrickKind::None,
     }
@@ -23,14 +51,74 @@ fn c2brick(c: char) -> BrickKind {
 // a single level
 #[derive(Clone)]
 pub struct Level {
-    pub corner: Vec<u8>,             // pattern of the top left corner
-    pub puzzle: Vec<Vec<BrickKind>>, // initial block positions
-    pub first: BrickKind,            // player's starting block
+    pub corner: Vec<u8>,               // pattern of the top left corner
+    pub puzzle: Vec<Vec<BrickKind>>,   // initial block positions
+    pub first: BrickKind,              // player's starting block
+    pub background: Option<String>,    // name of the background image (None - default background)
+    pub match_n: usize,                // a falling block needs this many matching blocks in a row to clear them
+    pub name: Option<String>,          // title of the level
+    pub author: Option<String>,        // who made the level
+    pub meta: HashMap<String, String>, // other `key:value` lines the game does not know
+    pub board: Board,                  // dimensions of the board, the same for all levels of a pack
 }
 
 impl Default for Level {
     fn default() -> Self {
-        Level { corner: Vec::new(), puzzle: Vec::new(), first: DEFAULT_KIND }
+        Level {
+            corner: Vec::new(),
+            puzzle: Vec::new(),
+            first: DEFAULT_KIND,
+            background: None,
+            match_n: 1,
+            name: None,
+            author: None,
+            meta: HashMap::new(),
+            board: Board::default(),
+        }
+    }
+}
+
+// Reasons a level set cannot be loaded. Level numbers start from 0 - the demo level
+#[derive(Debug)]
+pub enum LoaderError {
+    Io(String),                       // the file cannot be read
+    CornerLines(usize, usize, usize), // level, the number of corner lines, the puzzle max dimension
+    CornerWidth(usize, u8, usize),    // level, the length of the too long corner line, the puzzle max dimension
+    PuzzleLines(usize, usize, usize), // level, the number of puzzle lines, the puzzle max dimension
+    PuzzleWidth(usize, usize, usize), // level, the number of puzzle columns, the puzzle max dimension
+    Hole(usize),                      // level with an empty cell between blocks in a column
+    MatchCount(usize, String),        // level, invalid value of `match:`
+    BoardSize(usize, String),         // level, invalid or misplaced value of `size:`
+}
+
+impl fmt::Display for LoaderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoaderError::Io(e) => write!(f, "{}", e),
+            LoaderError::CornerLines(lvl, n, max) => write!(
+                f,
+                "Level {}: corner pattern must be omitted or has between 2 and {} lines, found {} lines",
+                lvl,
+                max - 1,
+                n
+            ),
+            LoaderError::CornerWidth(lvl, n, max) => {
+                write!(f, "Level {}: corner line exceeds {} blocks = {} blocks", lvl, max, n)
+            }
+            LoaderError::PuzzleLines(lvl, n, max) => {
+                write!(f, "Level {}: puzzle must has between 2 and {} lines, found {} lines", lvl, max, n)
+            }
+            LoaderError::PuzzleWidth(lvl, n, max) => {
+                write!(f, "Level {}: puzzle must has between 2 and {} columns, found {} columns", lvl, max, n)
+            }
+            LoaderError::Hole(lvl) => write!(f, "Level {} contains a hole in a puzzle", lvl),
+            LoaderError::MatchCount(lvl, v) => write!(f, "Level {}: invalid match count '{}'", lvl, v),
+            LoaderError::BoardSize(lvl, v) => write!(
+                f,
+                "Level {}: board size must be between {} and {} and set before the first level, found '{}'",
+                lvl, MAX_SIZE, MAX_BOARD_SIZE, v
+            ),
+        }
     }
 }
 
@@ -40,9 +128,35 @@ pub struct Loader {
 
 impl Loader {
     pub fn new() -> Loader {
+        // the built-in levels are checked before every release, so an error is a bug
+        match Loader::std_levels() {
+            Ok(loader) => loader,
+            Err(e) => panic!("Invalid built-in levels: {}", e),
+        }
+    }
+
+    // loads and validates the built-in levels. The error contains the number of the bad level
+    pub fn std_levels() -> Result<Loader, LoaderError> {
+        let mut loader = Loader { levels: Vec::new() };
+        loader.load_from_string(STD_LEVELS)?;
+        Ok(loader)
+    }
+
+    // loads a level pack from a file. A pack with any invalid level is rejected
+    pub fn from_file(path: &Path) -> Result<Loader, LoaderError> {
+        let pset = read_to_string(path).map_err(|e| LoaderError::Io(format!("failed to read {:?}: {}", path, e)))?;
         let mut loader = Loader { levels: Vec::new() };
-        loader.load_from_string(STD_LEVELS);
-        loader
+        loader.load_from_string(&pset)?;
+        Ok(loader)
+    }
+
+    // builds a level set from levels made by the game (e.g, generated ones)
+    pub fn from_levels(levels: Vec<Level>) -> Result<Loader, LoaderError> {
+        let loader = Loader { levels: Vec::new() };
+        for (idx, lvl) in levels.iter().enumerate() {
+            loader.validate_level(lvl, idx)?;
+        }
+        Ok(Loader { levels })
     }
 
     // returns a level info by its number.
@@ -57,56 +171,48 @@ impl Loader {
     }
 
     // Validate level and fail early - in any case the game in not playable
-    fn validate_level(&self, level: &Level, lvl_num: usize) {
-        let max_size: u8 = MAX_SIZE as u8;
+    fn validate_level(&self, level: &Level, lvl_num: usize) -> Result<(), LoaderError> {
+        let size = level.board.max_size;
+        let max_size: u8 = size as u8;
         // 1. Corner pattern must be:
         //   - Either missing
-        //   - Or contain less than MAX_SIZE-1 lines
-        // 2. No corner line length can exceed MAX_SIZE
-        if level.corner.len() > MAX_SIZE - 1 || level.corner.len() == 1 {
-            panic!(
-                "Level {}: corner pattern must be omitted or has between 2 and {} lines, found {} lines",
-                lvl_num,
-                max_size - 1,
-                level.corner.len()
-            );
+        //   - Or contain less than max size-1 lines
+        // 2. No corner line length can exceed max size
+        if level.corner.len() > size - 1 || level.corner.len() == 1 {
+            return Err(LoaderError::CornerLines(lvl_num, level.corner.len(), size));
         }
         for l in level.corner.iter() {
             if *l > max_size {
-                panic!("Level {}: corner line exceeds {} blocks = {} blocks", lvl_num, max_size, *l);
+                return Err(LoaderError::CornerWidth(lvl_num, *l, size));
             }
         }
 
         // A puzzle must have:
-        // 1. Width and height less than or equal to MAX_SIZE
+        // 1. Width and height less than or equal to max size
         // 2. Both width and height at least 2 blocks
         // 3. No holes in any column
-        if level.puzzle.len() > MAX_SIZE || level.puzzle.len() < 2 {
-            panic!(
-                "Level {}: puzzle must has between 2 and {} lines, found {} lines",
-                lvl_num,
-                max_size,
-                level.puzzle.len()
-            );
+        if level.puzzle.len() > size || level.puzzle.len() < 2 {
+            return Err(LoaderError::PuzzleLines(lvl_num, level.puzzle.len(), size));
         }
         let max_w: usize = level.puzzle.iter().fold(0, |mx, x| if mx < x.len() { x.len() } else { mx });
-        if !(2..=MAX_SIZE).contains(&max_w) {
-            panic!("Level {}: puzzle must has between 2 and {} columns, found {} columns", lvl_num, max_size, max_w);
+        if !(2..=size).contains(&max_w) {
+            return Err(LoaderError::PuzzleWidth(lvl_num, max_w, size));
         }
         for i in 0..max_w {
             let mut found: bool = false;
             for l in level.puzzle.iter() {
-                if l.len() < i {
+                if l.len() <= i {
                     continue;
                 }
                 if l[i] == BrickKind::None && found {
-                    panic!("Level {} contains a hole in a puzzle", lvl_num);
+                    return Err(LoaderError::Hole(lvl_num));
                 }
                 if l[i] != BrickKind::None {
                     found = true;
                 }
             }
         }
+        Ok(())
     }
 
     // Load all levels from a string
@@ -129,6 +235,31 @@ impl Loader {
     //    Optional line.
     //    It should be the first line of level description. The line defines the player's
     //    block at game start. If the line is missing, the player's first block is `?`
+    // `bg:IMAGE_NAME`
+    //    Optional line.
+    //    The name of an image file. It is looked for in the directory of the current theme,
+    //    then in `assets/PACK_NAME` for a custom level pack, and then in `assets` subdirectory of
+    //    the game data directory (see `level_image_path`). The image is tiled as the level
+    //    background instead of the default one. If the image cannot be loaded, the default
+    //    background is used
+    // `match:N`
+    //    Optional line.
+    //    Match-N variant of the rules: a falling player's block clears blocks of a column only
+    //    if it hits at least N blocks of its kind in a row. Otherwise, the block stops and goes
+    //    back without clearing anything. The default is 1 - the classic rules
+    // `size:N`
+    //    Optional line.
+    //    The biggest puzzle side for all levels of the pack, between 7 (the default) and 10.
+    //    The board grows with it, and a board bigger than the default one is drawn scaled
+    //    down to fit the screen. The line must be before the first level
+    // `name:TITLE` and `author:NAME`
+    //    Optional lines.
+    //    The title of the level and its author, shown in the main menu
+    // `key:value`
+    //    Optional lines.
+    //    Any other metadata. The key is a lowercase word (`_` is allowed) that must contain
+    //    a letter not used for blocks, otherwise the line is a puzzle line. Unknown keys are
+    //    kept with the level but the game does not use them
     // `*****`
     //    If line starts from `*` it means that it is corner pattern. After `*` any characters
     //    can follow because loader only reads the line of the length and does not parse it.
@@ -149,10 +280,11 @@ impl Loader {
     // $%=
     // %%%
     // %=$
-    fn load_from_string(&mut self, pset: &str) {
+    fn load_from_string(&mut self, pset: &str) -> Result<(), LoaderError> {
         let mut in_corner: bool = false;
         let mut in_puzzle: bool = false;
         let mut lvl: Level = Default::default();
+        let mut board = Board::default();
         self.levels.clear();
 
         for s in pset.lines() {
@@ -179,12 +311,59 @@ impl Loader {
                 lvl.first = c2brick(s1.chars().next().unwrap());
                 continue;
             }
+            // sets the background image of the level
+            if s.starts_with("bg:") {
+                let s1 = s.trim_start_matches("bg:");
+                let s1 = s1.trim();
+                if !s1.is_empty() {
+                    lvl.background = Some(s1.to_string());
+                }
+                continue;
+            }
+            // sets how many matching blocks in a column a falling block must hit
+            if s.starts_with("match:") {
+                let s1 = s.trim_start_matches("match:").trim();
+                match s1.parse::<usize>() {
+                    Ok(n) if n > 0 => lvl.match_n = n,
+                    _ => return Err(LoaderError::MatchCount(self.levels.len(), s1.to_string())),
+                }
+                continue;
+            }
+            // sets the board dimensions of all levels
+            if s.starts_with("size:") {
+                let s1 = s.trim_start_matches("size:").trim();
+                match s1.parse::<usize>() {
+                    Ok(n)
+                        if (MAX_SIZE..=MAX_BOARD_SIZE).contains(&n)
+                            && self.levels.is_empty()
+                            && lvl.puzzle.is_empty() =>
+                    {
+                        board = Board::new(n);
+                        lvl.board = board;
+                    }
+                    _ => return Err(LoaderError::BoardSize(self.levels.len(), s1.to_string())),
+                }
+                continue;
+            }
+            // level title, author, and other metadata
+            if let Some((key, value)) = parse_meta(s) {
+                if !value.is_empty() {
+                    match key {
+                        "name" => lvl.name = Some(value.to_string()),
+                        "author" => lvl.author = Some(value.to_string()),
+                        _ => {
+                            lvl.meta.insert(key.to_string(), value.to_string());
+                        }
+                    }
+                }
+                continue;
+            }
             // new level starts. Save previous level and continue
             if s.starts_with('#') {
                 if !lvl.puzzle.is_empty() {
-                    self.validate_level(&lvl, self.levels.len());
+                    self.validate_level(&lvl, self.levels.len())?;
                     self.levels.push(lvl);
-                    lvl = Default::default();
+                    lvl = Level { board, ..Default::default() };
                 }
                 continue;
             }
@@ -204,9 +383,59 @@ impl Loader {
         }
         // save the last level - there is no `#` after it
         if !lvl.puzzle.is_empty() {
-            self.validate_level(&lvl, self.levels.len());
+            self.validate_level(&lvl, self.levels.len())?;
             self.levels.push(lvl);
         }
-        println!("Loaded {} levels", self.levels.len());
+        debug!("Loaded {} levels", self.levels.len());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a bad edit of `assets/std_puzzles` must fail the tests, not the first game of the level
+    #[test]
+    fn builtin_levels_are_valid() {
+        let loader = match Loader::std_levels() {
+            Ok(loader) => loader,
+            Err(e) => panic!("Invalid built-in levels: {}", e),
+        };
+        assert!(loader.level_count() > 1);
+        for idx in 0..loader.level_count() {
+            if let Err(e) = loader.validate_level(&loader.level(idx), idx) {
+                panic!("Built-in level {} is invalid: {}", idx, e);
+            }
+        }
+    }
+
+    #[test]
+    fn background_directive() {
+        let pack = "#1\nbg: stars.png\nstart:1\n12\n12\n\n#2\nbg:\n12\n12\n\n#3\n12\n12\n";
+        let mut loader = Loader { levels: Vec::new() };
+        if let Err(e) = loader.load_from_string(pack) {
+            panic!("Failed to load the pack: {}", e);
+        }
+        let bgs: Vec<Option<String>> = loader.levels.iter().map(|l| l.background.clone()).collect();
+        assert_eq!(bgs, vec![Some("stars.png".to_string()), None, None]);
+    }
+
+    fn puzzle_level(puzzle: &[&[BrickKind]]) -> Level {
+        Level { puzzle: puzzle.iter().map(|l| l.to_vec()).collect(), ..Level::default() }
+    }
+
+    #[test]
+    fn short_rows_are_not_holes() {
+        use BrickKind::{K1, K2, K3};
+        let loader = Loader { levels: Vec::new() };
+        // the second row is shorter than the others: its missing cells are not checked
+        let lvl = puzzle_level(&[&[K1, K2, K3], &[K1], &[K1, K2, K3]]);
+        assert!(loader.validate_level(&lvl, 1).is_ok());
+        let lvl = puzzle_level(&[&[K1, K2], &[K1]]);
+        assert!(loader.validate_level(&lvl, 1).is_ok());
+        // an empty cell under a block is a hole
+        let lvl = puzzle_level(&[&[K1, K2], &[K1, BrickKind::None], &[K1, K2]]);
+        assert!(matches!(loader.validate_level(&lvl, 1), Err(LoaderError::Hole(1))));
     }
 }
@@ -0,0 +1,55 @@
+use std::env;
+use std::fs::File;
+use std::io::Write;
+use std::sync::Mutex;
+
+use chrono::Local;
+use log::{LevelFilter, Log, Metadata, Record};
+
+use crate::common::{log_path, skip_write};
+
+// environment variable with the log level: off, error, warn, info, debug, or trace
+const LOG_ENV: &str = "UNBLOCKED_LOG";
+const DEFAULT_LEVEL: LevelFilter = LevelFilter::Info;
+
+// Writes messages to stderr and to the log file in the game data directory. The Windows
+// binary does not have a console, so there the log file is the only place to find them
+struct Logger {
+    file: Option<Mutex<File>>,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        eprintln!("[{}] {}", record.level(), record.args());
+        if let Some(file) = &self.file {
+            if let Ok(mut f) = file.lock() {
+                let _ = writeln!(f, "{} [{}] {}", Local::now().format("%H:%M:%S%.3f"), record.level(), record.args());
+            }
+        }
+    }
+
+    fn flush(&self) {
+        if let Some(file) = &self.file {
+            if let Ok(mut f) = file.lock() {
+                let _ = f.flush();
+            }
+        }
+    }
+}
+
+// Starts logging with the level from LOG_ENV. The log file is recreated at every start.
+// Data paths depend on portable and read-only modes, so they must be set before the call
+pub fn init() {
+    let level = env::var(LOG_ENV).ok().and_then(|v| v.parse().ok()).unwrap_or(DEFAULT_LEVEL);
+    let file = if skip_write("log file") { None } else { File::create(log_path()).ok().map(Mutex::new) };
+    if log::set_boxed_logger(Box::new(Logger { file })).is_ok() {
+        log::set_max_level(level);
+    }
+}
@@ -1,26 +1,171 @@
 #![windows_subsystem = "windows"]
 
+use std::path::Path;
+
+use tetra::time::Timestep;
 use tetra::ContextBuilder;
 
+mod analyze;
+mod args;
+mod assets;
+mod audio;
+mod bindings;
+mod board;
 mod common;
+mod confetti;
+mod config;
 mod consts;
 mod demo;
+mod diag;
 mod field;
+mod generator;
+mod i18n;
+mod leaders;
 mod loader;
+mod logger;
 mod mainmenu;
+mod net;
 mod play;
 mod replay;
+mod run;
 mod scenes;
 mod scores;
+mod settings;
+mod sim;
+mod solver;
+mod stars;
+mod stats;
 mod textnum;
+mod undo;
+mod watch;
 
+use crate::args::Args;
+use crate::config::Config;
+use crate::loader::Loader;
+use crate::replay::ReplayEngine;
 use crate::scenes::SceneManager;
+use crate::scores::Scores;
+
+// printed when the command line cannot be parsed: one group of options per line, options
+// separated by `|` cannot be used together
+const USAGE: &str = concat!(
+    "Usage: unblocked ",
+    "[--serve PORT | --watch HOST:PORT | --restore-replay LEVEL | --import-replay FILE | ",
+    "--export-replay LEVEL FILE | --analyze-pack FILE | --export-csv FILE | --export-json FILE | ",
+    "--solve-records | --verify-replays | --diagnose] ",
+    "[--endless SEED | --level LEVEL | --replay FILE] ",
+    "[--windowed WIDTHxHEIGHT] ",
+    "[--portable] [--read-only]",
+);
+
+fn main() {
+    diag::catch_panics();
+    if let Err(e) = run() {
+        diag::report_crash(&format!("Failed to run the game: {}", e), None);
+        std::process::exit(1);
+    }
+}
 
-fn main() -> tetra::Result {
-    ContextBuilder::new("Unblocked", consts::SCR_W as i32, consts::SCR_H as i32)
+fn run() -> tetra::Result {
+    let args = match Args::parse() {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}", e);
+            eprintln!("{}", USAGE);
+            return Ok(());
+        }
+    };
+    // portable mode must be set before the first path is requested
+    if args.portable {
+        common::force_portable();
+    }
+    common::set_read_only(args.read_only || Config::load().read_only);
+    logger::init();
+    if let Some(lvl) = args.restore_replay {
+        if ReplayEngine::restore_backup(lvl) {
+            println!("Replay for level {} restored from backup", lvl);
+        } else {
+            eprintln!("Failed to restore replay for level {}: no backup found", lvl);
+        }
+        return Ok(());
+    }
+    if let Some(path) = &args.import_replay {
+        match ReplayEngine::import(Path::new(path), &Loader::new()) {
+            Ok(lvl) => println!("Replay imported for level {}", lvl),
+            Err(e) => eprintln!("Failed to import replay: {}", e),
+        }
+        return Ok(());
+    }
+    if let Some((lvl, path)) = &args.export_replay {
+        let loader = Loader::new();
+        let mut engine = ReplayEngine::new();
+        if *lvl < loader.level_count() {
+            engine.load(*lvl);
+        }
+        if !engine.is_loaded() {
+            eprintln!("Failed to export replay: level {} does not have a saved replay", lvl);
+        } else if let Err(e) = engine.export_text(Path::new(path), &loader.level(*lvl)) {
+            eprintln!("Failed to export replay: {}", e);
+        } else {
+            println!("Replay of level {} exported to {}", lvl, path);
+        }
+        return Ok(());
+    }
+    if let Some(path) = &args.export_csv {
+        let scores = Scores::new(Loader::new().level_count());
+        match scores.export_csv(Path::new(path)) {
+            Ok(()) => println!("Statistics exported to {}", path),
+            Err(e) => eprintln!("Failed to export statistics: {}", e),
+        }
+        return Ok(());
+    }
+    if let Some(path) = &args.export_json {
+        let scores = Scores::new(Loader::new().level_count());
+        match scores.export_json(Path::new(path)) {
+            Ok(()) => println!("Statistics exported to {}", path),
+            Err(e) => eprintln!("Failed to export statistics: {}", e),
+        }
+        return Ok(());
+    }
+    if let Some(path) = &args.analyze_pack {
+        if let Err(e) = analyze::analyze_pack(Path::new(path)) {
+            eprintln!("Failed to analyze level pack: {}", e);
+        }
+        return Ok(());
+    }
+    if args.solve_records {
+        analyze::print_records();
+        return Ok(());
+    }
+    if args.verify_replays {
+        if !analyze::verify_replays() {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+    // a bad replay file is reported before the window is created
+    let replay = match &args.replay {
+        None => None,
+        Some(path) => match ReplayEngine::open(Path::new(path), &Loader::new()) {
+            Ok(r) => Some(r),
+            Err(e) => {
+                eprintln!("Failed to open replay: {}", e);
+                return Ok(());
+            }
+        },
+    };
+    let cfg = config::Config::load();
+    let (width, height) = args.windowed.unwrap_or_else(|| cfg.window_size());
+    let ctx = ContextBuilder::new("Unblocked", width, height)
+        .fullscreen(cfg.fullscreen && args.windowed.is_none())
         .resizable(true)
         .quit_on_escape(false)
         .show_mouse(true)
-        .build()?
-        .run(SceneManager::new)
+        .timestep(Timestep::Fixed(consts::FPS as f64))
+        .build();
+    if args.diagnose {
+        diag::print_report(ctx.as_ref());
+        return Ok(());
+    }
+    ctx?.run(|ctx| SceneManager::new(ctx, &args, replay))
 }
@@ -1,19 +1,34 @@
 use std::cell::RefCell;
+use std::path::PathBuf;
 use std::rc::Rc;
 use std::time::Duration;
 
+use log::error;
+use tetra::graphics::mesh::{Mesh, ShapeStyle};
 use tetra::graphics::{self, animation, Color, DrawParams, Rectangle, Texture};
 use tetra::input::{self, Key};
 use tetra::math::Vec2;
 use tetra::Context;
 
-use crate::common::digits;
+use crate::assets::Assets;
+use crate::audio::Track;
+use crate::bindings::{Command, InputBindings};
+use crate::common::{center_screen, digits, find_packs, set_pack};
+use crate::config::Config;
 use crate::consts::{DEMO_LEVEL, SCR_H, SCR_W};
 use crate::demo::DemoScene;
+use crate::field::{developer_record, BrickKind};
+use crate::generator::random_seed;
+use crate::i18n::Labels;
+use crate::leaders::LeaderboardScene;
 use crate::loader::Loader;
-use crate::play::PlayScene;
+use crate::net::StreamServer;
+use crate::play::{PlayMode, PlayScene};
 use crate::scenes::{Scene, Transition};
 use crate::scores::Scores;
+use crate::settings::SettingsScene;
+use crate::stars::{difficulty, draw_star_row, draw_stars, mode_color, stars_width, MAX_DIFFICULTY, STAR_SIZE};
+use crate::stats::StatsScene;
 use crate::textnum::{TextNumber, TextParams};
 
 // height of a menu item sprite
@@ -25,79 +40,119 @@ const POINTER_H: f32 = 40.0;
 const POINTER_FRAMES: usize = 6;
 // shift to draw the main menu arrow centered for the menu item
 const POINTER_SHIFT: f32 = (LBL_HEIGHT - POINTER_H) * 0.5;
+// number of menu items
+const MENU_ITEMS: usize = 5;
 // menu items to manually select a level to start from
 const LVL_MENU_ITEM: usize = 1;
+// menu item to open the settings
+const SETTINGS_MENU_ITEM: usize = 3;
+// menu item to exit the game
+const EXIT_MENU_ITEM: usize = 4;
+// color of localized menu items, the same as of the menu sprite
+const MENU_TEXT_COLOR: Color = Color::rgb(0.5, 1.0, 0.5);
+// string keys of localized menu items
+const MENU_KEYS: [&str; MENU_ITEMS] = ["menu_start", "menu_level", "menu_demo", "menu_settings", "menu_exit"];
+// the start and the width of every menu item in the menu sprite (change it if you replace main
+// menu sprites). The sprite does not have the settings item: it is drawn with the bitmap font
+const SPRITE_ITEMS: [Option<(f32, f32)>; MENU_ITEMS] =
+    [Some((0.0, 100.0)), Some((100.0, 98.0)), Some((198.0, 80.0)), None, Some((278.0, 80.0))];
+// distance from the screen corner to the custom level pack number
+const PACK_POS: f32 = 16.0;
+// color of the level difficulty stars
+const DIFFICULTY_COLOR: Color = Color::rgb(0.6, 0.8, 1.0);
+// distance from the last menu item to the title of the selected level
+const LEVEL_NAME_GAP: f32 = LBL_HEIGHT * 2.0;
 
 pub struct TitleScene {
-    item_pos: [Vec2<f32>; 4],        // positions of all 4 menu items
-    animation: animation::Animation, // arrow
+    item_pos: [Vec2<f32>; MENU_ITEMS], // positions of all menu items
+    animation: animation::Animation,   // arrow
     menu_tx: Texture,
+    labels: Option<Labels>, // None - menu items are drawn from `menu_tx`
+    text: Labels,           // untranslated text, e.g, the unknown difficulty sign
     menu_id: usize,
     txt_num: TextNumber,
 
-    lbl_width: [f32; 4],     // width of menu items (at this moment it is hardcoded)
-    lbl_gap: [f32; 4],       // extra space between menu item and arrow
-    lbl_ext_width: [f32; 4], // full menu item width (include level number)
+    lbl_width: [f32; MENU_ITEMS],     // width of menu items
+    lbl_gap: [f32; MENU_ITEMS],       // extra space between menu item and arrow
+    lbl_ext_width: [f32; MENU_ITEMS], // full menu item width (include level number)
 
+    // Escape was pressed and the game waits for the user to confirm exit
+    quit_confirm: bool,
+    shade: Mesh, // dims the menu while the exit confirmation is shown
+    keys: InputBindings,
+
+    // custom level packs found at start. `pack` is the index of the active pack plus one,
+    // 0 means the built-in levels
+    packs: Vec<PathBuf>,
+    pack: usize,
+    std_loader: Rc<Loader>, // built-in levels, the demo is always shown with them
+
+    assets: Rc<Assets>,
     loader: Rc<Loader>,
     scores: Rc<RefCell<Scores>>,
+    config: Rc<RefCell<Config>>,
+    stream: Option<Rc<RefCell<StreamServer>>>, // spectators of the game
 }
 
 impl TitleScene {
-    pub fn new(ctx: &mut Context) -> tetra::Result<TitleScene> {
-        // hardcoded menu item widths (change it if you replace main menu sprites)
-        let widths: [f32; 4] = [100.0, 98.0, 80.0, 80.0];
-        let mut ext_widths: [f32; 4] = [0.0, 0.0, 0.0, 0.0];
-        let mut lbl_gap: [f32; 4] = [0.0, 0.0, 0.0, 0.0];
+    pub fn new(
+        ctx: &mut Context,
+        assets: Rc<Assets>,
+        loader: Rc<Loader>,
+        scores: Rc<RefCell<Scores>>,
+        config: Rc<RefCell<Config>>,
+        stream: Option<Rc<RefCell<StreamServer>>>,
+    ) -> tetra::Result<TitleScene> {
+        // sprite menu items have hardcoded widths. Localized menu items and the items missing
+        // in the sprite are as wide as their text
+        let labels = Labels::for_language(assets.font.clone(), &config.borrow().language);
+        let text = Labels::new(assets.font.clone(), "en");
+        let mut widths = [0.0; MENU_ITEMS];
+        for (i, w) in widths.iter_mut().enumerate() {
+            *w = match (&labels, SPRITE_ITEMS[i]) {
+                (Some(l), _) => l.width(MENU_KEYS[i]),
+                (None, Some((_, sprite_w))) => sprite_w,
+                (None, None) => text.width(MENU_KEYS[i]),
+            };
+        }
+        let mut ext_widths = [0.0; MENU_ITEMS];
+        let mut lbl_gap = [0.0; MENU_ITEMS];
 
         let line_gap = LBL_HEIGHT * 0.5; // vertical space between items
 
         // calculate menu item positions so they all are shown in the middle of the screen
-        // Positions can be hardcoded but I was not sure that 4 menu items is permanent count.
-        let item_cnt = 4; // number of menu items
-        let half_cnt = (item_cnt / 2) as f32;
-        let first = if item_cnt % 2 == 0 {
-            half_cnt * LBL_HEIGHT + (half_cnt - 1.0) * line_gap + line_gap * 0.5
-        } else {
-            half_cnt * LBL_HEIGHT + half_cnt * line_gap + LBL_HEIGHT * 0.5
-        };
-        let half_scr_h = SCR_H * 0.5;
-        let first = half_scr_h - first; // vertical position of the first menu item
-        let mut v = [Vec2::new(0.0, 0.0); 4];
-
-        let loader = Rc::new(Loader::new());
-        let scores = Rc::new(RefCell::new(Scores::new(loader.level_count())));
+        let first = menu_top(MENU_ITEMS, line_gap); // vertical position of the first menu item
+        let mut v = [Vec2::new(0.0, 0.0); MENU_ITEMS];
 
         // calculates extra horizontal gaps - now it makes sense only for menu item
         // that allows a user manually select level to start from.
         // Extra space depends on width of one digit
-        let number_image = include_bytes!("../assets/numbers.png");
-        let txt = TextNumber::new(ctx, number_image)?;
+        let txt = TextNumber::new(assets.numbers.clone());
         let lvl_cnt = loader.level_count();
         let sz = txt.digit_size();
         let digs = digits(lvl_cnt);
-        let lvl_width = f32::from(digs) * sz.x;
+        let lvl_width = txt.number_width(digs, &TextParams::new());
         ext_widths[LVL_MENU_ITEM] += lvl_width;
         lbl_gap[LVL_MENU_ITEM] += sz.x;
 
         let half_scr_w = SCR_W * 0.5;
-        for i in 0..4 {
+        for i in 0..MENU_ITEMS {
             v[i] =
                 Vec2::new(half_scr_w - (widths[i] + ext_widths[i]) * 0.5, first + i as f32 * (LBL_HEIGHT + line_gap));
         }
 
-        let arrow_image = include_bytes!("../assets/menu_arrow.png");
-        let menu_image = include_bytes!("../assets/menu_items.png");
-
+        let keys = InputBindings::new(&config.borrow());
         Ok(TitleScene {
             item_pos: v,
             animation: animation::Animation::new(
-                Texture::from_encoded(ctx, arrow_image)?,
+                assets.menu_arrow.clone(),
                 Rectangle::row(0.0, 0.0, POINTER_W, POINTER_H).take(POINTER_FRAMES).collect(),
                 Duration::from_millis(100),
             ),
 
-            menu_tx: Texture::from_encoded(ctx, menu_image)?,
+            menu_tx: assets.menu_items.clone(),
+            labels,
+            text,
             menu_id: 0,
             txt_num: txt,
 
@@ -105,37 +160,176 @@ impl TitleScene {
             lbl_gap,
             lbl_ext_width: ext_widths,
 
+            quit_confirm: false,
+            shade: Mesh::rectangle(ctx, ShapeStyle::Fill, Rectangle::new(0.0, 0.0, SCR_W, SCR_H))?,
+            keys,
+
+            packs: find_packs(),
+            pack: 0,
+            std_loader: loader.clone(),
+
+            assets,
             loader,
             scores,
+            config,
+            stream,
         })
     }
 }
 
+impl TitleScene {
+    // Switches to the next level pack: the built-in levels, then custom packs in order.
+    // Packs that fail to load are skipped. The first level of a pack is the demo one, so
+    // a pack must have at least two levels
+    fn next_pack(&mut self) {
+        let total = self.packs.len() + 1;
+        let mut next = self.pack;
+        loop {
+            next = (next + 1) % total;
+            if next == 0 {
+                set_pack(None);
+                self.loader = self.std_loader.clone();
+                break;
+            }
+            let path = &self.packs[next - 1];
+            match Loader::from_file(path) {
+                Ok(ld) if ld.level_count() > 1 => {
+                    set_pack(path.file_stem().map(|s| s.to_string_lossy().to_string()));
+                    self.loader = Rc::new(ld);
+                    break;
+                }
+                Ok(_) => error!("Level pack {:?} has no levels to play", path),
+                Err(e) => error!("Failed to load level pack: {}", e),
+            }
+        }
+        self.pack = next;
+        self.scores = Rc::new(RefCell::new(Scores::new(self.loader.level_count())));
+    }
+}
+
+// the vertical position of the first of `item_cnt` menu items, so they all are shown in the
+// middle of the screen
+fn menu_top(item_cnt: usize, line_gap: f32) -> f32 {
+    let half_cnt = (item_cnt / 2) as f32;
+    let first = if item_cnt.is_multiple_of(2) {
+        half_cnt * LBL_HEIGHT + (half_cnt - 1.0) * line_gap + line_gap * 0.5
+    } else {
+        half_cnt * LBL_HEIGHT + half_cnt * line_gap + LBL_HEIGHT * 0.5
+    };
+    SCR_H * 0.5 - first
+}
+
+impl TitleScene {
+    // draws the menu item text: localized one, a part of the menu sprite, or English text if
+    // the sprite does not have the item
+    fn draw_item(&mut self, ctx: &mut Context, item: usize, pos: Vec2<f32>) {
+        match (self.labels.as_mut(), SPRITE_ITEMS[item]) {
+            (Some(l), _) => {
+                l.draw(ctx, pos, MENU_KEYS[item], MENU_TEXT_COLOR);
+            }
+            (None, Some((start, w))) => {
+                let clip = Rectangle::new(start, 0.0, w, LBL_HEIGHT);
+                self.menu_tx.draw_region(ctx, clip, DrawParams::new().position(pos));
+            }
+            (None, None) => {
+                self.text.draw(ctx, pos, MENU_KEYS[item], MENU_TEXT_COLOR);
+            }
+        }
+    }
+}
+
 impl Scene for TitleScene {
+    fn music(&self) -> Option<Track> {
+        Some(Track::Menu)
+    }
+
     fn update(&mut self, ctx: &mut Context) -> tetra::Result<Transition> {
         self.animation.advance(ctx);
+        // Exit confirmation: Space and Return exit the game, Escape returns to the menu
+        if self.quit_confirm {
+            if self.keys.is_pressed(ctx, Command::Confirm) {
+                return Ok(Transition::Pop);
+            } else if self.keys.is_pressed(ctx, Command::Back) {
+                self.quit_confirm = false;
+            }
+            return Ok(Transition::None);
+        }
+
         // Key processing:
-        // - Up and Down to select a menu item
+        // - Up and Down (or W and S) to select a menu item
         // - Space and Return to execute the selected menu item
-        // - Left and Right to increase and decrease the starting level number by `1`
+        // - Left and Right (or A and D) to increase and decrease the starting level number by `1`
         //   if the menu item `LVL_MENU_ITEM` is selected
         // - Shift+Left and Shift+Right to increase and decrease the starting level number by `10`
         //   if the menu item `LVL_MENU_ITEM` is selected
-        if input::is_key_pressed(ctx, Key::Up) {
+        // - H to show the best results (hall of fame)
+        // - T to start a speedrun from the first level
+        // - I to show statistics of all levels
+        // - E to play generated levels with a new random seed
+        // - C to play today's daily challenge
+        // - Tab to switch to the next level pack
+        // - Escape to leave the level number (moves the cursor to the first menu item) or
+        //   to ask for exit confirmation if any other menu item is selected
+        if self.keys.is_pressed(ctx, Command::Back) {
+            if self.menu_id == LVL_MENU_ITEM {
+                self.menu_id = 0;
+            } else {
+                self.quit_confirm = true;
+            }
+            Ok(Transition::None)
+        } else if input::is_key_pressed(ctx, Key::Tab) {
+            self.next_pack();
+            Ok(Transition::None)
+        } else if input::is_key_pressed(ctx, Key::H) {
+            Ok(Transition::Push(Box::new(LeaderboardScene::new(
+                self.assets.clone(),
+                self.scores.clone(),
+                self.config.clone(),
+            ))))
+        } else if input::is_key_pressed(ctx, Key::T) {
+            // a speedrun always starts from the first level
+            self.scores.borrow_mut().dec_curr_level(usize::MAX);
+            Ok(Transition::Push(Box::new(PlayScene::new(
+                self.assets.clone(),
+                self.loader.clone(),
+                self.scores.clone(),
+                self.config.clone(),
+                self.stream.clone(),
+                PlayMode::Speedrun,
+            )?)))
+        } else if input::is_key_pressed(ctx, Key::E) {
+            Ok(Transition::Push(Box::new(PlayScene::new_endless(
+                self.assets.clone(),
+                self.config.clone(),
+                random_seed(),
+            )?)))
+        } else if input::is_key_pressed(ctx, Key::C) {
+            Ok(Transition::Push(Box::new(PlayScene::new_daily(
+                self.assets.clone(),
+                self.config.clone(),
+                self.scores.clone(),
+            )?)))
+        } else if input::is_key_pressed(ctx, Key::I) {
+            Ok(Transition::Push(Box::new(StatsScene::new(
+                self.assets.clone(),
+                self.scores.clone(),
+                self.config.clone(),
+            ))))
+        } else if self.keys.is_pressed(ctx, Command::Up) {
             if self.menu_id == 0 {
-                self.menu_id = 3;
+                self.menu_id = MENU_ITEMS - 1;
             } else {
                 self.menu_id -= 1;
             }
             Ok(Transition::None)
-        } else if input::is_key_pressed(ctx, Key::Down) {
-            if self.menu_id == 3 {
+        } else if self.keys.is_pressed(ctx, Command::Down) {
+            if self.menu_id == MENU_ITEMS - 1 {
                 self.menu_id = 0;
             } else {
                 self.menu_id += 1;
             }
             Ok(Transition::None)
-        } else if input::is_key_pressed(ctx, Key::Left) && self.menu_id == 1 {
+        } else if self.keys.is_pressed(ctx, Command::Left) && self.menu_id == 1 {
             let diff = if input::is_key_down(ctx, Key::RightShift) || input::is_key_down(ctx, Key::LeftShift) {
                 10usize
             } else {
@@ -146,7 +340,7 @@ impl Scene for TitleScene {
                 sc.dec_curr_level(diff);
             }
             Ok(Transition::None)
-        } else if input::is_key_pressed(ctx, Key::Right) && self.menu_id == 1 {
+        } else if self.keys.is_pressed(ctx, Command::Right) && self.menu_id == 1 {
             let diff = if input::is_key_down(ctx, Key::RightShift) || input::is_key_down(ctx, Key::LeftShift) {
                 10usize
             } else {
@@ -157,21 +351,31 @@ impl Scene for TitleScene {
                 sc.inc_curr_level(diff);
             }
             Ok(Transition::None)
-        } else if input::is_key_pressed(ctx, Key::Space)
-            || input::is_key_pressed(ctx, Key::Enter)
-            || input::is_key_pressed(ctx, Key::NumPadEnter)
-        {
-            if self.menu_id == 3 {
+        } else if self.keys.is_pressed(ctx, Command::Confirm) {
+            if self.menu_id == EXIT_MENU_ITEM {
                 Ok(Transition::Pop)
             } else if self.menu_id == 0 || self.menu_id == 1 {
-                Ok(Transition::Push(Box::new(PlayScene::new(ctx, self.loader.clone(), self.scores.clone())?)))
-            } else if self.menu_id == 2 {
-                Ok(Transition::Push(Box::new(DemoScene::new(
-                    ctx,
+                Ok(Transition::Push(Box::new(PlayScene::new(
+                    self.assets.clone(),
                     self.loader.clone(),
                     self.scores.clone(),
-                    DEMO_LEVEL,
+                    self.config.clone(),
+                    self.stream.clone(),
+                    PlayMode::Normal,
                 )?)))
+            } else if self.menu_id == 2 {
+                Ok(Transition::Push(Box::new(
+                    DemoScene::new(
+                        self.assets.clone(),
+                        self.std_loader.clone(),
+                        self.scores.clone(),
+                        self.config.clone(),
+                        DEMO_LEVEL,
+                    )?
+                    .with_play(self.loader.clone(), self.stream.clone()),
+                )))
+            } else if self.menu_id == SETTINGS_MENU_ITEM {
+                Ok(Transition::Push(Box::new(SettingsScene::new(self.assets.clone(), self.config.clone()))))
             } else {
                 Ok(Transition::None)
             }
@@ -181,16 +385,11 @@ impl Scene for TitleScene {
     }
 
     fn draw(&mut self, ctx: &mut Context) -> tetra::Result<Transition> {
-        graphics::clear(ctx, Color::rgb(0.094, 0.11, 0.16));
-
-        let mut start: f32 = 0.0;
+        graphics::clear(ctx, self.config.borrow().clear_color());
 
         // show main menu items
-        for i in 0..4 {
-            let clip = Rectangle::new(start, 0.0, self.lbl_width[i], LBL_HEIGHT);
-            let dp = DrawParams::new().position(self.item_pos[i]);
-            self.menu_tx.draw_region(ctx, clip, dp);
-            start += self.lbl_width[i];
+        for i in 0..MENU_ITEMS {
+            self.draw_item(ctx, i, self.item_pos[i]);
         }
 
         // show "arrows" to the left and to the right from the selected menu item
@@ -201,6 +400,11 @@ impl Scene for TitleScene {
         let pos = Vec2::new(self.item_pos[self.menu_id].x + wdth + 5.0, self.item_pos[self.menu_id].y + POINTER_SHIFT);
         self.animation.draw(ctx, DrawParams::new().position(pos).color(Color::rgb(0.0, 1.0, 1.0)));
 
+        // the number of the custom level pack in the top left corner
+        if self.pack != 0 {
+            self.txt_num.draw(ctx, Vec2::new(PACK_POS, PACK_POS), self.pack as u32, TextParams::new());
+        }
+
         // show the level number to start playing from
         let digits = digits(self.scores.borrow().max_avail_level());
         let mx = self.item_pos[LVL_MENU_ITEM].x + self.lbl_gap[LVL_MENU_ITEM] + self.lbl_width[LVL_MENU_ITEM];
@@ -210,7 +414,79 @@ impl Scene for TitleScene {
             self.scores.borrow().curr_level() as u32,
             TextParams::new().with_width(digits).with_leading_zeroes(),
         );
+        // and its rating to the right of the menu item arrow
+        let lvl = self.scores.borrow().curr_level();
+        let info = self.scores.borrow().level_info(lvl);
+        let wdth = self.lbl_width[LVL_MENU_ITEM] + self.lbl_ext_width[LVL_MENU_ITEM] + self.lbl_gap[LVL_MENU_ITEM];
+        let pos = Vec2::new(
+            self.item_pos[LVL_MENU_ITEM].x + wdth + POINTER_W + 15.0 + STAR_SIZE,
+            self.item_pos[LVL_MENU_ITEM].y + LBL_HEIGHT * 0.5,
+        );
+        draw_stars(ctx, &self.assets.star, pos, info.best_stars, mode_color(info.hiscore_mode));
+        // the level difficulty after the rating. Levels without a developer record get "?"
+        let pos = Vec2::new(pos.x + stars_width() + STAR_SIZE * 2.0, pos.y);
+        match developer_record(lvl) {
+            Some(par) => {
+                let bricks = self.loader.level(lvl).puzzle.iter().flatten().filter(|b| **b != BrickKind::None).count();
+                let stars = difficulty(par, bricks);
+                draw_star_row(ctx, &self.assets.star, pos, stars, MAX_DIFFICULTY, DIFFICULTY_COLOR);
+            }
+            None => {
+                let pos = Vec2::new(pos.x - STAR_SIZE, pos.y - self.text.line_height() / 2.0);
+                self.text.draw_text(ctx, pos, "?", DIFFICULTY_COLOR);
+            }
+        }
+
+        // the title and the author of the level if the pack has them
+        let level = self.loader.level(lvl);
+        let mut y = self.item_pos[EXIT_MENU_ITEM].y + LEVEL_NAME_GAP;
+        let lines = [(level.name, Color::WHITE), (level.author, Color::rgb(0.5, 0.5, 0.5))];
+        for (text, color) in lines.iter().filter_map(|(t, c)| t.as_ref().map(|t| (t, *c))) {
+            let x = SCR_W / 2.0 - self.text.text_width(text) / 2.0;
+            self.text.draw_text(ctx, Vec2::new(x, y), text, color);
+            y += self.text.line_height();
+        }
+
+        // exit confirmation: dimmed menu with highlighted `Exit` item in the center of the screen
+        if self.quit_confirm {
+            self.shade.draw(ctx, DrawParams::new().color(Color::rgba(0.0, 0.0, 0.0, 0.8)));
+            let w = self.lbl_width[EXIT_MENU_ITEM];
+            let pos = center_screen(w, LBL_HEIGHT);
+            self.draw_item(ctx, EXIT_MENU_ITEM, pos);
+            let color = Color::rgb(1.0, 0.3, 0.3);
+            let left = Vec2::new(pos.x - POINTER_W - 5.0, pos.y + POINTER_SHIFT);
+            self.animation.draw(ctx, DrawParams::new().position(left).color(color));
+            let right = Vec2::new(pos.x + w + 5.0, pos.y + POINTER_SHIFT);
+            self.animation.draw(ctx, DrawParams::new().position(right).color(color));
+        }
 
         Ok(Transition::None)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the menu is centered vertically for any number of items
+    #[test]
+    fn menu_is_centered() {
+        let line_gap = LBL_HEIGHT * 0.5;
+        for cnt in 1..=8 {
+            let height = cnt as f32 * LBL_HEIGHT + (cnt - 1) as f32 * line_gap;
+            let top = menu_top(cnt, line_gap);
+            assert_eq!(top + height * 0.5, SCR_H * 0.5, "{} items", cnt);
+        }
+    }
+
+    #[test]
+    fn sprite_items_do_not_overlap() {
+        let mut end = 0.0;
+        for (start, w) in SPRITE_ITEMS.iter().flatten() {
+            assert_eq!(*start, end);
+            end += w;
+        }
+        assert!(SPRITE_ITEMS[SETTINGS_MENU_ITEM].is_none());
+        assert!(SPRITE_ITEMS[EXIT_MENU_ITEM].is_some());
+    }
+}
@@ -0,0 +1,167 @@
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::time::Duration;
+
+use log::{error, info};
+
+use crate::replay::Action;
+
+// a slow spectator must not freeze the game for long
+const WRITE_TIMEOUT: Duration = Duration::from_millis(100);
+
+// Spectator stream is a sequence of text lines. A spectator repeats them in the same order:
+//   LEVEL <level No> <board hash> - the player (re)started a level
+//   MOVE <tick> <UP|DOWN|THROW>   - the player made a move
+//   STEP                          - the game field was advanced by one frame
+pub enum Message {
+    Level(usize, u64),
+    Move(u64, Action),
+    Step,
+}
+
+impl Message {
+    fn to_line(&self) -> String {
+        match self {
+            Message::Level(lvl, hash) => format!("LEVEL {} {:016x}\n", lvl, hash),
+            Message::Move(tick, act) => {
+                let act = match act {
+                    Action::Up => "UP",
+                    Action::Down => "DOWN",
+                    Action::Throw => "THROW",
+                };
+                format!("MOVE {} {}\n", tick, act)
+            }
+            Message::Step => "STEP\n".to_string(),
+        }
+    }
+
+    fn parse(line: &str) -> Option<Message> {
+        let mut parts = line.split_whitespace();
+        match parts.next()? {
+            "LEVEL" => {
+                let lvl = parts.next()?.parse().ok()?;
+                let hash = u64::from_str_radix(parts.next()?, 16).ok()?;
+                Some(Message::Level(lvl, hash))
+            }
+            "MOVE" => {
+                let tick = parts.next()?.parse().ok()?;
+                let act = match parts.next()? {
+                    "UP" => Action::Up,
+                    "DOWN" => Action::Down,
+                    "THROW" => Action::Throw,
+                    _ => return None,
+                };
+                Some(Message::Move(tick, act))
+            }
+            "STEP" => Some(Message::Step),
+            _ => None,
+        }
+    }
+}
+
+// Sends the game to all connected spectators
+pub struct StreamServer {
+    listener: TcpListener,
+    clients: Vec<TcpStream>,
+    // everything sent since the current level started. A spectator that connects
+    // in the middle of a level gets it first to catch up with the game
+    history: String,
+}
+
+impl StreamServer {
+    pub fn new(port: u16) -> io::Result<StreamServer> {
+        let listener = TcpListener::bind(("0.0.0.0", port))?;
+        listener.set_nonblocking(true)?;
+        info!("Streaming the game to spectators on port {}", port);
+        Ok(StreamServer { listener, clients: Vec::new(), history: String::new() })
+    }
+
+    fn accept(&mut self) {
+        loop {
+            match self.listener.accept() {
+                Ok((mut stream, addr)) => {
+                    let prepared = stream.set_nonblocking(false).and_then(|_| {
+                        stream.set_nodelay(true)?;
+                        stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+                        stream.write_all(self.history.as_bytes())
+                    });
+                    match prepared {
+                        Ok(_) => {
+                            info!("Spectator {} connected", addr);
+                            self.clients.push(stream);
+                        }
+                        Err(e) => error!("Failed to accept spectator {}: {}", addr, e),
+                    }
+                }
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => return,
+                Err(e) => {
+                    error!("Failed to accept spectator: {}", e);
+                    return;
+                }
+            }
+        }
+    }
+
+    fn send(&mut self, msg: Message) {
+        self.accept();
+        let line = msg.to_line();
+        // a spectator that fails to receive a message is dropped
+        self.clients.retain(|mut c| c.write_all(line.as_bytes()).is_ok());
+        self.history.push_str(&line);
+    }
+
+    pub fn start_level(&mut self, lvl: usize, hash: u64) {
+        self.history.clear();
+        self.send(Message::Level(lvl, hash));
+    }
+
+    pub fn send_move(&mut self, tick: u64, act: Action) {
+        self.send(Message::Move(tick, act));
+    }
+
+    pub fn step(&mut self) {
+        self.send(Message::Step);
+    }
+}
+
+// Receives a game from a streaming instance
+pub struct StreamClient {
+    stream: TcpStream,
+    buf: Vec<u8>, // received bytes that do not make a full line yet
+}
+
+impl StreamClient {
+    pub fn connect(addr: &str) -> io::Result<StreamClient> {
+        let stream = TcpStream::connect(addr)?;
+        stream.set_nonblocking(true)?;
+        Ok(StreamClient { stream, buf: Vec::new() })
+    }
+
+    // returns all messages received since the last call. An error means that
+    // the connection is closed
+    pub fn poll(&mut self) -> io::Result<Vec<Message>> {
+        let mut chunk = [0u8; 4096];
+        loop {
+            match self.stream.read(&mut chunk) {
+                Ok(0) => return Err(io::Error::new(ErrorKind::UnexpectedEof, "the game has closed the stream")),
+                Ok(n) => self.buf.extend_from_slice(&chunk[..n]),
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        let mut msgs = Vec::new();
+        while let Some(pos) = self.buf.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.buf.drain(..=pos).collect();
+            let line = String::from_utf8_lossy(&line);
+            match Message::parse(&line) {
+                Some(msg) => msgs.push(msg),
+                None => {
+                    return Err(io::Error::new(ErrorKind::InvalidData, format!("invalid message: {}", line.trim())))
+                }
+            }
+        }
+        Ok(msgs)
+    }
+}
@@ -1,96 +1,887 @@
 use std::cell::RefCell;
+use std::mem;
 use std::rc::Rc;
 
-use tetra::graphics::{DrawParams, Rectangle, Texture};
-use tetra::input::{self, Key};
+use log::{info, warn};
+use tetra::graphics::mesh::{Mesh, ShapeStyle};
+use tetra::graphics::{self, Color, DrawParams, Rectangle, Texture};
+use tetra::input::{self, MouseButton};
+use tetra::math::Vec2;
 use tetra::Context;
 
+use crate::assets::Assets;
+use crate::audio::Track;
+use crate::bindings::{Command, InputBindings};
+use crate::board::Board;
 use crate::common::center_screen;
-use crate::consts::{NUM_STATES, PLATE_GAME_COMPLETED, PLATE_LEVEL_SOLVED, PLATE_NO_MOVES};
+use crate::config::Config;
+use crate::consts::{
+    BRICK_SIZE, FPS, NUM_STATES, PLATE_GAME_COMPLETED, PLATE_LEVEL_SOLVED, PLATE_NO_MOVES, SCR_H, SCR_W,
+};
 use crate::demo::DemoScene;
 use crate::field::{GameField, GameState};
+use crate::generator::{Generator, MAX_SEED};
+use crate::i18n::Labels;
 use crate::loader::Loader;
-use crate::replay::ReplayEngine;
+use crate::net::StreamServer;
+use crate::replay::{Action, ReplayEngine};
+use crate::run::{RunState, RunTimer};
 use crate::scenes::{Scene, Transition};
-use crate::scores::Scores;
+use crate::scores::{today, ModeFlags, Scores};
+use crate::sim::BoardSim;
+use crate::stars::{draw_stars, mode_color, stars_width, STAR_SIZE};
+use crate::textnum::{TextNumber, TextParams};
+
+// The field restarts a failed level by itself. In hardcore mode the run goes back
+// to the last checkpoint instead
+fn restart_run(field: &mut GameField, run: &RunState) {
+    let lvl = run.restart_level(field.level);
+    if lvl != field.level {
+        field.load(lvl);
+    }
+}
 
 // interrupting a game after making this many throws is considered a fail
 const MIN_THROWS: u32 = 3;
+// duration of the sweep after a level is solved
+const SWEEP_FRAMES: u32 = 45;
+// width of the sweeping band
+const SWEEP_WIDTH: f32 = BRICK_SIZE * 3.0;
+// throws the deadlock check looks ahead
+const DEADLOCK_DEPTH: u32 = 6;
+// size of a bar of the pause sign
+const PAUSE_BAR_W: f32 = 32.0;
+const PAUSE_BAR_H: f32 = 112.0;
+// throws the hint looks ahead
+const HINT_DEPTH: u32 = 6;
+// the hint flashes for two seconds, switching on and off every quarter of a second
+const HINT_FRAMES: u32 = FPS * 2;
+const HINT_BLINK: u32 = FPS / 4;
+// the number of generated levels in a daily challenge
+const DAILY_LEVELS: u32 = 5;
+// the move history shows this many last moves
+const HISTORY_LINES: usize = 4;
+
+// a level put aside by the quick level switch: the game and its replay recorder
+struct ParkedLevel {
+    field: GameField,
+    replay: ReplayEngine,
+    recording_clock: u64,
+}
+
+// The saved replay of the level played on its own board along with the game
+struct Ghost {
+    replay: ReplayEngine,
+    sim: BoardSim,
+    level: usize,
+    tick: u64,  // replay clock of the last update. A smaller clock means the level was restarted
+    auto: bool, // started by `auto_ghost` without a key press, so it is not help
+}
+
+// What the player is playing
+pub enum PlayMode {
+    Normal,
+    Speedrun,           // the whole pack from the first level against the clock
+    Endless(Generator), // generated levels one after another
+}
+
+// generated levels and the labels of the seed and the number of solved levels
+struct Endless {
+    gen: Generator,
+    labels: Labels,
+    daily: Option<Daily>, // None - the levels never end
+}
+
+// The daily challenge: a few generated levels with today's date as the seed. The fewer throws
+// all of them are solved with, the better
+struct Daily {
+    day: i32,
+    scores: Rc<RefCell<Scores>>, // scores of the pack: they keep the daily results
+    throws: u32,                 // throws of all solved levels
+    best: Option<bool>,          // None - the challenge is not finished, true - a new best result
+}
 
 pub struct PlayScene {
+    assets: Rc<Assets>,
     field: GameField,
     state_tx: Texture,
     loader: Rc<Loader>,
     scores: Rc<RefCell<Scores>>,
     replay: ReplayEngine,
-    tick: u64, // internal tick counter for replays
+    // replay clock. It counts only frames the player can make moves in: it stops while
+    // blocks fly back, while the level is over, and it is not updated while another scene
+    // (e.g, the F1 replay) is on top of this one. Playback runs the same clock
+    recording_clock: u64,
+    // board hashes and rows before every throw of the saved replay (loaded while
+    // the solution preview key is held)
+    hints: Vec<(u64, usize)>,
+    stream: Option<Rc<RefCell<StreamServer>>>, // spectators of the game
+    run: RunState,
+    config: Rc<RefCell<Config>>,
+    // frames left of the band sweeping over the play area after the level is solved
+    sweep: u32,
+    sweep_mesh: Option<(Board, Mesh)>, // created at first draw call and when the board changes
+    // result of the deadlock check after the last throw. None - the board has changed
+    // and it has not been checked yet
+    deadlock: Option<bool>,
+    deadlock_mesh: Option<(Board, Mesh)>, // created at first draw call and when the board changes
+    keys: InputBindings,
+    paused: bool,
+    pause_meshes: Option<(Mesh, Mesh)>, // dimming shade and a bar of the pause sign
+    mouse: Vec2<f32>,                   // mouse position in the game screen coordinates
+    mouse_moved: bool,                  // the mouse has moved since the previous update
+    prev_level: Option<usize>,          // the level played before the current one
+    parked: Option<Box<ParkedLevel>>,   // the previous level with its progress after a switch
+    ghost: Option<Ghost>,               // None - the ghost is off
+    auto_ghost_level: Option<usize>,    // the last level the ghost was started for automatically
+    hint: Option<(u64, usize, u32)>,    // the board hash, the row to throw from, and frames left
+    timer: Option<RunTimer>,            // None - not a speedrun
+    endless: Option<Endless>,           // None - levels of the pack are played
+    labels: Option<Labels>,             // None - state plates are drawn from `state_tx`
+    txt_num: TextNumber,
+    history: Labels,    // font of the move history
+    show_history: bool, // the last moves of the attempt are listed in the info panel
 }
 
 impl PlayScene {
-    pub fn new(ctx: &mut Context, ld: Rc<Loader>, sc: Rc<RefCell<Scores>>) -> tetra::Result<Self> {
+    pub fn new(
+        assets: Rc<Assets>,
+        ld: Rc<Loader>,
+        sc: Rc<RefCell<Scores>>,
+        config: Rc<RefCell<Config>>,
+        stream: Option<Rc<RefCell<StreamServer>>>,
+        mode: PlayMode,
+    ) -> tetra::Result<Self> {
         let s = sc.clone();
         let l = ld.clone();
         let lvl = sc.borrow().curr_level();
-        let state_image = include_bytes!("../assets/all_plates.png");
+        let (timer, endless) = match mode {
+            PlayMode::Normal => (None, None),
+            PlayMode::Speedrun => (Some(RunTimer::default()), None),
+            PlayMode::Endless(gen) => {
+                let labels = Labels::new(assets.font.clone(), &config.borrow().language);
+                (None, Some(Endless { gen, labels, daily: None }))
+            }
+        };
+        // generated levels do not have a hardcore session, so a fail just restarts the level
+        let run = if endless.is_some() { RunState::casual() } else { RunState::new(&config.borrow(), lvl) };
+        let keys = InputBindings::new(&config.borrow());
+        let txt_num = TextNumber::new(assets.numbers.clone());
+        let labels = Labels::for_language(assets.font.clone(), &config.borrow().language);
+        let history = Labels::new(assets.font.clone(), &config.borrow().language);
         let mut p = PlayScene {
             loader: l,
             scores: s,
-            field: GameField::new(ctx, ld, sc, false)?,
-            state_tx: Texture::from_encoded(ctx, state_image)?,
+            field: GameField::new(&assets, ld, sc, false)?,
+            state_tx: assets.plates.clone(),
+            assets,
             replay: ReplayEngine::new(),
-            tick: 0,
+            recording_clock: 0,
+            hints: Vec::new(),
+            stream,
+            run,
+            config,
+            sweep: 0,
+            sweep_mesh: None,
+            deadlock: None,
+            deadlock_mesh: None,
+            keys,
+            paused: false,
+            pause_meshes: None,
+            mouse: Vec2::new(-1.0, -1.0),
+            mouse_moved: false,
+            prev_level: None,
+            parked: None,
+            ghost: None,
+            auto_ghost_level: None,
+            hint: None,
+            timer,
+            endless,
+            txt_num,
+            labels,
+            history,
+            show_history: false,
         };
-        p.field.load(lvl);
+        Self::configure_field(&mut p.field, &p.config.borrow(), p.run.hardcore, lvl);
         p.replay.rec_start();
+        p.start_auto_ghost();
+        p.stream_level();
         Ok(p)
     }
 
-    fn draw_deco(&mut self, ctx: &mut Context) {
-        let w = self.state_tx.width() as f32;
-        let h = (self.state_tx.height() / NUM_STATES) as f32;
+    // Endless mode: the scene plays levels generated from the seed with their own scores that
+    // are not saved. Generated levels do not have saved replays, so replays, hints and the
+    // ghost are off, and the game is not streamed
+    pub fn new_endless(assets: Rc<Assets>, config: Rc<RefCell<Config>>, seed: u32) -> tetra::Result<Self> {
+        let mut gen = Generator::new(seed);
+        info!("Endless mode, seed {}", gen.seed());
+        let ld = Rc::new(gen.next_loader());
+        let sc = Rc::new(RefCell::new(Scores::detached(ld.level_count())));
+        Self::new(assets, ld, sc, config, None, PlayMode::Endless(gen))
+    }
+
+    // Daily challenge: the same levels for everyone during the day. The result is saved to the
+    // pack scores
+    pub fn new_daily(
+        assets: Rc<Assets>,
+        config: Rc<RefCell<Config>>,
+        scores: Rc<RefCell<Scores>>,
+    ) -> tetra::Result<Self> {
+        let day = today();
+        let mut scene = Self::new_endless(assets, config, day as u32 % (MAX_SEED + 1))?;
+        if let Some(endless) = scene.endless.as_mut() {
+            endless.daily = Some(Daily { day, scores, throws: 0, best: None });
+        }
+        Ok(scene)
+    }
+
+    // the current level is the last one of the daily challenge
+    fn is_last_daily(&self) -> bool {
+        match &self.endless {
+            Some(Endless { gen, daily: Some(_), .. }) => gen.solved + 1 >= DAILY_LEVELS,
+            _ => false,
+        }
+    }
+
+    // the last level of the daily challenge is solved: the result is saved if it is the best one
+    fn finish_daily(&mut self) {
+        if !self.is_last_daily() {
+            return;
+        }
+        if let Some(daily) = self.endless.as_mut().and_then(|e| e.daily.as_mut()) {
+            daily.throws += self.field.score;
+            daily.best = Some(daily.scores.borrow_mut().set_daily(daily.day, daily.throws));
+        }
+    }
+
+    // saved replays belong to levels of the pack
+    fn has_replays(&self) -> bool {
+        self.endless.is_none()
+    }
+
+    // Endless mode: a solved level is replaced with a freshly generated one.
+    // Returns false if it is not endless mode, the level is not solved, or it is the last
+    // level of the daily challenge
+    fn next_generated(&mut self) -> bool {
+        let solved = self.field.state == GameState::Winner || self.field.state == GameState::Completed;
+        if self.is_last_daily() {
+            return false;
+        }
+        let endless = match self.endless.as_mut() {
+            Some(e) if solved => e,
+            _ => return false,
+        };
+        if let Some(daily) = endless.daily.as_mut() {
+            daily.throws += self.field.score;
+        }
+        endless.gen.solved += 1;
+        let ld = Rc::new(endless.gen.next_loader());
+        self.loader = ld.clone();
+        self.field.set_loader(ld);
+        self.field.score = 0;
+        self.field.load(self.field.level);
+        self.replay.rec_start();
+        self.recording_clock = 0;
+        self.deadlock = None;
+        self.sweep = 0;
+        true
+    }
+
+    // the seed and the number of solved generated levels under the level info. The daily
+    // challenge shows the throws so far (green if the finished challenge is the best result of
+    // the day) and today's best result instead of the seed
+    fn draw_endless(&mut self, ctx: &mut Context) {
+        let endless = match self.endless.as_mut() {
+            Some(e) => e,
+            None => return,
+        };
+        let x = (self.field.board().play_width() as f32 + 0.75) * BRICK_SIZE;
+        let grey = Color::rgb(0.5, 0.5, 0.5);
+        let step = self.txt_num.digit_size().y + 10.0;
+        let mut y = BRICK_SIZE * 9.0;
+        let mut lines = vec![("endless_solved", endless.gen.solved, TextParams::new())];
+        match &endless.daily {
+            Some(daily) => {
+                let (throws, tp) = match daily.best {
+                    None => (daily.throws + self.field.score, TextParams::new()),
+                    Some(true) => (daily.throws, TextParams::new().with_color(Color::rgb(0.0, 0.8, 0.3))),
+                    Some(false) => (daily.throws, TextParams::new()),
+                };
+                lines.push(("daily_throws", throws, tp));
+                if let Some(best) = daily.scores.borrow().daily_best(daily.day) {
+                    lines.push(("daily_best", best, TextParams::new().with_color(grey)));
+                }
+            }
+            None => lines.insert(0, ("endless_seed", endless.gen.seed(), TextParams::new())),
+        }
+        for (key, n, tp) in lines {
+            endless.labels.draw(ctx, Vec2::new(x, y), key, grey);
+            y += endless.labels.line_height();
+            self.txt_num.draw(ctx, Vec2::new(x, y), n, tp);
+            y += step;
+        }
+    }
+
+    // only throws that have really started are recorded, so an undo removes the right one
+    fn throw(&mut self) {
+        if self.field.throw_brick() {
+            self.add_action(Action::Throw);
+            if let Some(timer) = self.timer.as_mut() {
+                timer.start();
+            }
+        }
+    }
+
+    // Takes back the last throw. The replay forgets the throw as well. Undo is disabled in
+    // hardcore mode and while the game is streamed to spectators
+    fn undo(&mut self) {
+        if self.run.hardcore || self.stream.is_some() {
+            return;
+        }
+        if self.field.undo() {
+            self.replay.undo_throw();
+            self.deadlock = None;
+        }
+    }
+
+    // Starts the level again. Unlike leaving the level, it does not count as a failed
+    // attempt, so it is disabled in hardcore mode. Returns false if the level is not restarted
+    fn restart(&mut self) -> bool {
+        if self.run.hardcore {
+            return false;
+        }
+        self.field.load(self.field.level);
+        self.field.score = 0;
+        self.replay.rec_start();
+        self.recording_clock = 0;
+        self.deadlock = None;
+        self.stream_level();
+        true
+    }
+
+    // the row under the mouse cursor if the cursor is over the play area
+    fn mouse_row(&self) -> Option<usize> {
+        let board = self.field.board();
+        // the board may be drawn scaled down and scrolled
+        let mouse = self.field.screen_to_board(self.mouse);
+        let area = board.play_width() as f32 * BRICK_SIZE;
+        if mouse.x < 0.0 || mouse.x >= area || mouse.y < BRICK_SIZE {
+            return None;
+        }
+        let row = (mouse.y / BRICK_SIZE) as usize;
+        if row > board.bottom() {
+            None
+        } else {
+            Some(row)
+        }
+    }
+
+    // moves player's block to the row. The moves are recorded one by one as if they were
+    // made with keys, so replays do not need to know about the mouse
+    fn aim_at(&mut self, row: usize) {
+        if !self.field.can_move() {
+            return;
+        }
+        let curr = self.field.player_y();
+        let act = if row < curr { Action::Up } else { Action::Down };
+        for _ in 0..curr.abs_diff(row) {
+            self.add_action(act);
+        }
+        self.field.set_player_row(row);
+    }
+
+    // Moves player's block a row up or down. The field ignores moves while the block is flying,
+    // so such moves are not recorded either: a replay must contain only the moves that were
+    // made, otherwise its headless simulation plays a different game
+    fn move_player(&mut self, act: Action) {
+        if !self.field.can_move() {
+            return;
+        }
+        self.add_action(act);
+        match act {
+            Action::Up => self.field.player_up(),
+            Action::Down => self.field.player_down(),
+            Action::Throw => {}
+        }
+    }
+
+    // applies the settings to a field and loads the level
+    fn configure_field(field: &mut GameField, cfg: &Config, hardcore: bool, lvl: usize) {
+        field.show_throwable = cfg.show_throwable;
+        field.show_all_arrows = cfg.show_all_arrows;
+        field.deadlock_warning = cfg.deadlock_warning;
+        field.idle_animation = cfg.idle_animation;
+        field.celebration = cfg.celebration;
+        field.sound = true;
+        field.throw_cooldown = cfg.throw_cooldown * FPS / 1000;
+        field.set_undo_budget(cfg.undo_memory * 1024);
+        field.keys = InputBindings::new(cfg);
+        field.clear_color = cfg.clear_color();
+        field.board_scroll = cfg.board_scroll;
+        if hardcore {
+            field.mode.set(ModeFlags::HARDCORE);
+        }
+        field.load(lvl);
+    }
+
+    // Switches between the current and the previous level. The progress of the level
+    // being left is kept, so switching back continues it. The switch is disabled in
+    // hardcore mode and while the game is streamed to spectators
+    fn switch_level(&mut self) -> tetra::Result {
+        if self.run.hardcore || self.stream.is_some() || !self.field.is_interactive() {
+            return Ok(());
+        }
+        let other = match self.parked.take() {
+            Some(p) => p,
+            None => {
+                let lvl = match self.prev_level {
+                    Some(l) if l <= self.scores.borrow().max_avail_level() => l,
+                    _ => return Ok(()),
+                };
+                let mut field = GameField::new(&self.assets, self.loader.clone(), self.scores.clone(), false)?;
+                Self::configure_field(&mut field, &self.config.borrow(), false, lvl);
+                let mut replay = ReplayEngine::new();
+                replay.rec_start();
+                Box::new(ParkedLevel { field, replay, recording_clock: 0 })
+            }
+        };
+        let ParkedLevel { field, replay, recording_clock } = *other;
+        let left = ParkedLevel {
+            field: mem::replace(&mut self.field, field),
+            replay: mem::replace(&mut self.replay, replay),
+            recording_clock: mem::replace(&mut self.recording_clock, recording_clock),
+        };
+        self.prev_level = Some(left.field.level);
+        self.parked = Some(Box::new(left));
+        self.hints.clear();
+        self.deadlock = None;
+        Ok(())
+    }
+
+    // a bright band passes over the play area from left to right
+    fn draw_sweep(&mut self, ctx: &mut Context) -> tetra::Result {
+        let board = self.field.board();
+        if self.sweep_mesh.as_ref().map(|(b, _)| *b) != Some(board) {
+            let rect = Rectangle::new(0.0, BRICK_SIZE, SWEEP_WIDTH, BRICK_SIZE * board.bottom() as f32);
+            self.sweep_mesh = Some((board, Mesh::rectangle(ctx, ShapeStyle::Fill, rect)?));
+        }
+        if let Some((_, mesh)) = &self.sweep_mesh {
+            let area = board.play_width() as f32 * BRICK_SIZE;
+            let passed = (SWEEP_FRAMES - self.sweep) as f32 / SWEEP_FRAMES as f32;
+            let x = passed * (area + SWEEP_WIDTH) - SWEEP_WIDTH;
+            mesh.draw(ctx, DrawParams::new().position(Vec2::new(x, 0.0)).color(Color::rgba(1.0, 0.9, 0.5, 0.35)));
+        }
+        Ok(())
+    }
+
+    // The board is checked once after every throw when all blocks stop
+    fn check_deadlock(&mut self, reloaded: bool) {
+        if !self.field.deadlock_warning {
+            return;
+        }
+        if reloaded || !self.field.is_settled() || self.field.state != GameState::Unfinished {
+            self.deadlock = None;
+        } else if self.deadlock.is_none() {
+            self.deadlock = Some(!self.field.is_currently_solvable(DEADLOCK_DEPTH));
+        }
+    }
+
+    // a red frame around the play area warns that the level cannot be solved anymore
+    fn draw_deadlock(&mut self, ctx: &mut Context) -> tetra::Result {
+        if self.deadlock != Some(true) {
+            return Ok(());
+        }
+        let board = self.field.board();
+        if self.deadlock_mesh.as_ref().map(|(b, _)| *b) != Some(board) {
+            let w = (board.play_width() - 1) as f32 * BRICK_SIZE;
+            let rect = Rectangle::new(BRICK_SIZE, BRICK_SIZE, w, BRICK_SIZE * board.bottom() as f32);
+            self.deadlock_mesh = Some((board, Mesh::rectangle(ctx, ShapeStyle::Stroke(4.0), rect)?));
+        }
+        if let Some((_, mesh)) = &self.deadlock_mesh {
+            mesh.draw(ctx, DrawParams::new().color(Color::rgba(0.9, 0.1, 0.1, 0.8)));
+        }
+        Ok(())
+    }
+
+    // the dimmed screen with a pause sign in the middle
+    fn draw_pause(&mut self, ctx: &mut Context) -> tetra::Result {
+        if !self.paused {
+            return Ok(());
+        }
+        if self.pause_meshes.is_none() {
+            let shade = Mesh::rectangle(ctx, ShapeStyle::Fill, Rectangle::new(0.0, 0.0, SCR_W, SCR_H))?;
+            let bar = Mesh::rectangle(ctx, ShapeStyle::Fill, Rectangle::new(0.0, 0.0, PAUSE_BAR_W, PAUSE_BAR_H))?;
+            self.pause_meshes = Some((shade, bar));
+        }
+        if let Some((shade, bar)) = &self.pause_meshes {
+            shade.draw(ctx, DrawParams::new().color(Color::rgba(0.0, 0.0, 0.0, 0.6)));
+            let pos = center_screen(PAUSE_BAR_W * 3.0, PAUSE_BAR_H);
+            bar.draw(ctx, DrawParams::new().position(pos));
+            bar.draw(ctx, DrawParams::new().position(Vec2::new(pos.x + PAUSE_BAR_W * 2.0, pos.y)));
+        }
+        Ok(())
+    }
+
+    fn draw_deco(&mut self, ctx: &mut Context) -> tetra::Result {
+        // the sweep is drawn with the board
+        if self.sweep > 0 {
+            return Ok(());
+        }
+
+        if self.field.state == GameState::Unfinished {
+            return Ok(());
+        }
+        // draw a plate that describes game state (if the game is over). Generated levels
+        // never end, so the last one is just solved. Only the daily challenge can be completed
+        let state = match self.field.state {
+            GameState::Completed if self.endless.is_some() && !self.is_last_daily() => GameState::Winner,
+            s => s,
+        };
+        let (pos, w, h) = match self.labels.as_mut() {
+            Some(labels) => {
+                let (title, hint, color) = match state {
+                    GameState::Winner => ("plate_solved", "plate_solved_hint", Color::rgb(0.1, 0.7, 0.1)),
+                    GameState::Looser => ("plate_failed", "plate_failed_hint", Color::rgb(0.9, 0.1, 0.1)),
+                    _ => ("plate_completed", "plate_completed_hint", Color::rgb(1.0, 0.4, 0.1)),
+                };
+                let (pos, size) = labels.draw_plate(ctx, title, hint, color)?;
+                (pos, size.x, size.y)
+            }
+            None => {
+                let w = self.state_tx.width() as f32;
+                let h = (self.state_tx.height() / NUM_STATES) as f32;
+                let y = match state {
+                    GameState::Winner => h * PLATE_LEVEL_SOLVED,
+                    GameState::Looser => h * PLATE_NO_MOVES,
+                    _ => h * PLATE_GAME_COMPLETED,
+                };
+                let pos = center_screen(w, h);
+                let dp = DrawParams::new().position(pos);
+                self.state_tx.draw_region(ctx, Rectangle::new(0.0, y, w, h), dp);
+                (pos, w, h)
+            }
+        };
+
+        // rating of the solution under the plate
+        if self.field.state == GameState::Winner || self.field.state == GameState::Completed {
+            let x = pos.x + (w - stars_width()) / 2.0 + STAR_SIZE;
+            let y = pos.y + h + STAR_SIZE * 2.0;
+            let color = mode_color(self.field.result_mode());
+            draw_stars(ctx, &self.assets.star, Vec2::new(x, y), self.field.stars(), color);
+        }
+        Ok(())
+    }
+    // records a move and sends it to spectators
+    fn add_action(&mut self, act: Action) {
+        self.replay.add_action(self.recording_clock, act);
+        if let Some(stream) = &self.stream {
+            stream.borrow_mut().send_move(self.recording_clock, act);
+        }
+    }
+
+    // tells spectators that the current level has been (re)started
+    fn stream_level(&self) {
+        if let Some(stream) = &self.stream {
+            stream.borrow_mut().start_level(self.field.level, self.field.to_sim().board_hash());
+        }
+    }
+
+    // Loads the saved replay and remembers where its throws were made. Using the preview
+    // counts as using help
+    fn load_hints(&mut self) {
+        let mut replay = ReplayEngine::new();
+        replay.load(self.field.level);
+        if !replay.is_loaded() {
+            return;
+        }
+        self.hints = replay.throw_hints(&self.loader.level(self.field.level));
+        if !self.hints.is_empty() {
+            self.field.hint_used = true;
+            let mut sc = self.field.scores.borrow_mut();
+            sc.set_help_used(self.field.level);
+        }
+    }
+
+    // Starts the ghost of the saved replay from the beginning of the current level. Like the
+    // solution preview, watching the ghost counts as using help unless the ghost is `auto`
+    fn new_ghost(&mut self, auto: bool) -> Ghost {
+        let level = self.field.level;
+        let mut replay = ReplayEngine::new();
+        replay.load(level);
+        replay.replay_start();
+        if replay.is_loaded() && !auto {
+            self.field.hint_used = true;
+            self.field.scores.borrow_mut().set_help_used(level);
+        }
+        Ghost { replay, sim: BoardSim::new(&self.loader.level(level)), level, tick: 0, auto }
+    }
 
-        // draw a plate that describes game state (if the game is over)
-        let clip_rect = match self.field.state {
-            GameState::Unfinished => return,
-            GameState::Winner => Rectangle::new(0.0, h * PLATE_LEVEL_SOLVED, w, h),
-            GameState::Looser => Rectangle::new(0.0, h * PLATE_NO_MOVES, w, h),
-            GameState::Completed => Rectangle::new(0.0, h * PLATE_GAME_COMPLETED, w, h),
+    // With `auto_ghost` on, the ghost of the best saved replay starts by itself once for every
+    // level entered. It is passive: the level is not marked as solved with help. A ghost that
+    // is already on, or was turned off by the player on this level, is left as is
+    fn start_auto_ghost(&mut self) {
+        let level = self.field.level;
+        if !self.config.borrow().auto_ghost || !self.has_replays() || self.auto_ghost_level == Some(level) {
+            return;
+        }
+        self.auto_ghost_level = Some(level);
+        if self.ghost.is_some() {
+            return;
+        }
+        let ghost = self.new_ghost(true);
+        if ghost.replay.is_loaded() {
+            self.ghost = Some(ghost);
+        }
+    }
+
+    // turns the ghost on and off. The ghost is not turned on if the level has no saved replay
+    fn toggle_ghost(&mut self) {
+        if self.ghost.take().is_some() {
+            return;
+        }
+        let ghost = self.new_ghost(false);
+        if ghost.replay.is_loaded() {
+            self.ghost = Some(ghost);
+        } else {
+            warn!("Level {} does not have a saved replay", self.field.level);
+        }
+    }
+
+    // plays the ghost's replay up to the current replay clock
+    fn update_ghost(&mut self) {
+        self.start_auto_ghost();
+        let (stale, auto) = match &self.ghost {
+            None => return,
+            Some(g) => (g.level != self.field.level || g.tick > self.recording_clock, g.auto),
         };
+        if stale {
+            self.ghost = Some(self.new_ghost(auto));
+        }
+        if let Some(g) = self.ghost.as_mut() {
+            g.tick = self.recording_clock;
+            while let Some(act) = g.replay.next_replay_action(g.tick) {
+                g.sim.apply(act);
+            }
+        }
+    }
 
-        let pos = center_screen(w, h);
-        let dp = DrawParams::new().position(pos);
-        self.state_tx.draw_region(ctx, clip_rect, dp);
+    // the last level is solved: the speedrun time is saved if it is the best one
+    fn finish_speedrun(&mut self) {
+        if let Some(timer) = self.timer.as_mut() {
+            timer.finish();
+            let secs = timer.elapsed().as_secs() as u32;
+            timer.best = self.scores.borrow_mut().set_run_time(secs);
+        }
+    }
+
+    // speedrun time under the level info: the current time (green if the finished run is the
+    // best one) and the best time of the pack below it
+    fn draw_timer(&mut self, ctx: &mut Context) {
+        let timer = match &self.timer {
+            Some(t) => t,
+            None => return,
+        };
+        let x = (self.field.board().play_width() as f32 + 0.75) * BRICK_SIZE;
+        let y = BRICK_SIZE * 9.0 + 10.0;
+        let tp = if timer.best { TextParams::new().with_color(Color::rgb(0.0, 0.8, 0.3)) } else { TextParams::new() };
+        let secs = timer.elapsed().as_secs() as u32;
+        self.txt_num.draw_time(ctx, Vec2::new(x, y), secs, tp);
+        if let Some(best) = self.scores.borrow().best_time() {
+            let y = y + self.txt_num.digit_size().y + 10.0;
+            let tp = TextParams::new().with_color(Color::rgb(0.5, 0.5, 0.5));
+            self.txt_num.draw_time(ctx, Vec2::new(x, y), best, tp);
+        }
+    }
+
+    // The last moves of the current attempt at the bottom of the info panel, throws are brighter.
+    // The moves are taken from the replay being recorded, so the list starts over with it.
+    // The info panel of endless mode is full, so there is no history
+    fn draw_history(&mut self, ctx: &mut Context) {
+        if !self.show_history || self.endless.is_some() {
+            return;
+        }
+        let board = self.field.board();
+        let x = (board.play_width() as f32 + 0.25) * BRICK_SIZE;
+        let step = self.history.line_height();
+        let mut y = self.field.info_bottom() - step * HISTORY_LINES as f32;
+        let moves = self.replay.moves();
+        for (idx, m) in moves.iter().enumerate().skip(moves.len().saturating_sub(HISTORY_LINES)) {
+            let color = match m.action() {
+                Action::Throw => Color::WHITE,
+                _ => Color::rgb(0.5, 0.5, 0.5),
+            };
+            self.history.draw_text(ctx, Vec2::new(x, y), &format!("{:>3} {}", idx + 1, m.action()), color);
+            y += step;
+        }
+    }
+
+    // Finds a throw that does not lead to a dead end and flashes its row. Like the saved replay
+    // help, a hint marks the level as solved with help
+    fn show_hint(&mut self) {
+        if let Some(row) = self.field.hint_throw(HINT_DEPTH) {
+            self.field.hint_used = true;
+            self.field.scores.borrow_mut().set_help_used(self.field.level);
+            self.hint = Some((self.field.to_sim().board_hash(), row, HINT_FRAMES));
+        }
+    }
+
+    // the hint is shown while the board stays the same
+    fn update_hint(&mut self) {
+        let hash = self.field.to_sim().board_hash();
+        self.hint = match self.hint {
+            Some((h, row, frames)) if h == hash && frames > 1 && self.field.state == GameState::Unfinished => {
+                Some((h, row, frames - 1))
+            }
+            _ => None,
+        };
+    }
+
+    // the block and the aim arrow of the hinted row blink
+    fn draw_hint_throw(&mut self, ctx: &mut Context) {
+        if let Some((_, row, frames)) = self.hint {
+            if frames % (HINT_BLINK * 2) >= HINT_BLINK {
+                let sim = self.field.to_sim();
+                self.field.draw_ghost(ctx, row, sim.player, sim.aim(row));
+            }
+        }
+    }
+
+    fn draw_ghost(&mut self, ctx: &mut Context) {
+        if self.field.state != GameState::Unfinished {
+            return;
+        }
+        if let Some(g) = &self.ghost {
+            if g.replay.is_loaded() && g.sim.state == GameState::Unfinished {
+                self.field.draw_ghost(ctx, g.sim.row, g.sim.player, g.sim.aim(g.sim.row));
+            }
+        }
+    }
+
+    // Saves the result of the level when the game is left in the middle: a solved level is a
+    // win, and a failed one or one with a few throws is a fail. The recorded replay is
+    // discarded: an unfinished replay is saved only on user's request
+    fn save_result(&mut self) {
+        // the game is left after the level is solved or failed - must save info anyway
+        if self.field.state == GameState::Completed || self.field.state == GameState::Winner {
+            let mut sc = self.field.scores.borrow_mut();
+            sc.set_win(self.field.level, self.field.score, self.field.result_mode());
+        } else if self.field.state == GameState::Looser
+            || (self.field.score >= MIN_THROWS && self.field.state == GameState::Unfinished)
+        {
+            let mut sc = self.field.scores.borrow_mut();
+            sc.set_fail(self.field.level);
+        }
+    }
+
+    // Saves the recorded replay. It rewrites a previously saved replay for this level unless the
+    // old one is a solution with fewer throws. A replay of a solved level is saved without
+    // redundant moves
+    fn save_replay(&mut self) {
+        let level = self.loader.level(self.field.level);
+        self.replay.optimize(&level);
+        let max_delay = self.config.borrow().replay_max_delay();
+        self.replay.save_with_options(self.field.level, &level, max_delay != 0, max_delay);
+    }
+
+    // the row of the next throw of the saved replay if the replay has passed the current board
+    fn hint_row(&self) -> Option<usize> {
+        if self.hints.is_empty() || !self.field.is_interactive() {
+            return None;
+        }
+        let hash = self.field.to_sim().board_hash();
+        self.hints.iter().find(|(h, _)| *h == hash).map(|(_, row)| *row)
     }
 }
 
 impl Scene for PlayScene {
+    fn set_mouse_position(&mut self, pos: Vec2<f32>) {
+        self.mouse_moved = pos != self.mouse;
+        self.mouse = pos;
+    }
+
+    fn music(&self) -> Option<Track> {
+        Some(Track::Game)
+    }
+
+    fn on_quit(&mut self) {
+        self.save_result();
+    }
+
     fn update(&mut self, ctx: &mut Context) -> tetra::Result<Transition> {
-        if input::is_key_pressed(ctx, Key::Escape) {
-            // Escape is pressed after the level is solved or failed - must save info anyway
-            if self.field.state == GameState::Completed || self.field.state == GameState::Winner {
-                let mut sc = self.field.scores.borrow_mut();
-                sc.set_win(self.field.level, self.field.score);
-            } else if self.field.state == GameState::Looser
-                || (self.field.score >= MIN_THROWS && self.field.state == GameState::Unfinished)
-            {
-                let mut sc = self.field.scores.borrow_mut();
-                sc.set_fail(self.field.level);
-            }
+        if self.keys.is_pressed(ctx, Command::Back) {
+            self.save_result();
             return Ok(Transition::Pop);
         }
-        self.tick += 1;
+        // everything is frozen while the game is paused: the field, the replay clock, and
+        // spectators wait. Only leaving, restarting, and resuming work
+        if self.keys.is_pressed(ctx, Command::Pause) && self.field.state == GameState::Unfinished {
+            self.paused = !self.paused;
+            if let Some(timer) = self.timer.as_mut() {
+                if self.paused {
+                    timer.pause();
+                } else {
+                    timer.resume();
+                }
+            }
+        }
+        if self.paused {
+            if self.keys.is_pressed(ctx, Command::Restart) && self.restart() {
+                self.paused = false;
+            }
+            return Ok(Transition::None);
+        }
+        if self.replay.clock_runs(&self.field) {
+            self.recording_clock += 1;
+        }
+        if self.keys.is_pressed(ctx, Command::Ghost) && self.has_replays() {
+            self.toggle_ghost();
+        }
+        if self.keys.is_pressed(ctx, Command::History) {
+            self.show_history = !self.show_history;
+        }
+        self.update_ghost();
+        if self.keys.is_pressed(ctx, Command::Hint) && self.field.is_interactive() {
+            self.show_hint();
+        }
+        self.update_hint();
+        // show the next step of the saved solution while the key is held
+        if self.keys.is_pressed(ctx, Command::Preview) && self.field.is_interactive() && self.has_replays() {
+            self.load_hints();
+        } else if self.keys.is_released(ctx, Command::Preview) {
+            self.hints.clear();
+        }
+        // a lost level can be taken back as well, so undo works after `no moves` is shown
+        if self.keys.is_pressed(ctx, Command::Undo) {
+            self.undo();
+            return Ok(Transition::None);
+        }
         if self.field.is_interactive() {
-            if input::is_key_pressed(ctx, Key::Space) {
-                self.replay.add_action(self.tick, Key::Space);
-                self.field.throw_brick();
+            // the mouse aims when it moves over the play area, and a left click throws
+            let clicked = input::is_mouse_button_pressed(ctx, MouseButton::Left);
+            if let Some(row) = self.mouse_row() {
+                if self.mouse_moved || clicked {
+                    self.aim_at(row);
+                }
+                if clicked {
+                    self.throw();
+                    return Ok(Transition::None);
+                }
+            }
+            // replays record commands, not keys, so they do not depend on key bindings
+            if self.keys.is_pressed(ctx, Command::Throw) {
+                self.throw();
                 return Ok(Transition::None);
-            } else if input::is_key_pressed(ctx, Key::Up) {
-                self.replay.add_action(self.tick, Key::Up);
-                self.field.player_up();
-            } else if input::is_key_pressed(ctx, Key::Down) {
-                self.replay.add_action(self.tick, Key::Down);
-                self.field.player_down();
-            } else if input::is_key_pressed(ctx, Key::F1) {
+            } else if self.keys.is_pressed(ctx, Command::Up) {
+                self.move_player(Action::Up);
+            } else if self.keys.is_pressed(ctx, Command::Down) {
+                self.move_player(Action::Down);
+            } else if self.keys.is_pressed(ctx, Command::Restart) {
+                self.restart();
+                return Ok(Transition::None);
+            } else if self.keys.is_pressed(ctx, Command::SwitchLevel) {
+                self.switch_level()?;
+                return Ok(Transition::None);
+            } else if self.keys.is_pressed(ctx, Command::Help) && self.has_replays() {
                 // try to load a replay for the level. If there is no replay, do nothing
                 let mut replay = ReplayEngine::new();
                 replay.load(self.field.level);
@@ -101,9 +892,10 @@ impl Scene for PlayScene {
                         sc.set_help_used(self.field.level);
                     }
                     return Ok(Transition::Push(Box::new(DemoScene::new(
-                        ctx,
+                        self.assets.clone(),
                         self.loader.clone(),
                         self.scores.clone(),
+                        self.config.clone(),
                         self.field.level,
                     )?)));
                 }
@@ -111,23 +903,110 @@ impl Scene for PlayScene {
         }
 
         assert!(!self.field.demoing);
-        // save replay. It rewrites any previously saved replay for this level
-        if input::is_key_pressed(ctx, Key::F5) {
-            self.replay.save(self.field.level);
+        // a failed attempt is not worth saving
+        if self.keys.is_pressed(ctx, Command::SaveReplay) && self.has_replays() && self.field.state != GameState::Looser
+        {
+            self.save_replay();
+        }
+
+        if self.keys.is_pressed(ctx, Command::Confirm) && self.next_generated() {
+            return Ok(Transition::None);
         }
 
-        // if the level is failed, reset replay recorder
+        let (level, state) = (self.field.level, self.field.state);
         let field_res = self.field.update(ctx);
-        if self.field.state == GameState::Looser {
+        if let Some(stream) = &self.stream {
+            stream.borrow_mut().step();
+        }
+        // the next level is loaded or the failed level is restarted.
+        // In hardcore mode a fail throws the player back to the last checkpoint
+        let restarted = state == GameState::Looser && self.field.state == GameState::Unfinished;
+        if restarted {
+            // the failed attempt is kept until the restart, so its last throw can be undone
             self.replay.rec_start();
-            self.tick = 0;
+            self.recording_clock = 0;
+            restart_run(&mut self.field, &self.run);
+        } else if level != self.field.level {
+            self.run.reached(self.field.level);
+        }
+        if level != self.field.level || restarted {
+            self.stream_level();
+        }
+        // the level is solved: play the sweep before showing the plate. Any key that
+        // advances to the next level skips it
+        let solved = self.field.state == GameState::Winner || self.field.state == GameState::Completed;
+        // a winning attempt is kept without pressing F5. The replay clock stops when the level
+        // is solved, so the recorded ticks match the game up to the last throw
+        if state == GameState::Unfinished && solved && self.has_replays() {
+            self.save_replay();
+        }
+        if state == GameState::Unfinished && solved && self.config.borrow().win_sweep {
+            self.sweep = SWEEP_FRAMES;
+        } else if solved && self.sweep > 0 {
+            self.sweep -= 1;
+        } else {
+            self.sweep = 0;
+        }
+        // a replay must contain only moves of the level it is saved for
+        if level != self.field.level {
+            self.replay.rec_start();
+            self.recording_clock = 0;
+            self.prev_level = Some(level);
+            self.parked = None;
+        }
+        self.check_deadlock(level != self.field.level || restarted);
+        if state != GameState::Completed && self.field.state == GameState::Completed {
+            self.run.finish();
+            self.finish_speedrun();
+            self.finish_daily();
         }
         field_res
     }
 
     fn draw(&mut self, ctx: &mut Context) -> tetra::Result<Transition> {
         let _ = self.field.draw(ctx)?;
-        self.draw_deco(ctx);
+        // everything over the board is scaled and scrolled with it, and the info panel is
+        // scaled only. Plates and the pause sign are drawn as is
+        graphics::set_transform_matrix(ctx, self.field.play_transform());
+        self.draw_ghost(ctx);
+        self.draw_hint_throw(ctx);
+        if let Some(row) = self.hint_row() {
+            self.field.draw_hint(ctx, row);
+        }
+        self.draw_deadlock(ctx)?;
+        if self.sweep > 0 {
+            self.draw_sweep(ctx)?;
+        }
+        graphics::set_transform_matrix(ctx, self.field.info_transform());
+        self.draw_timer(ctx);
+        self.draw_endless(ctx);
+        self.draw_history(ctx);
+        graphics::reset_transform_matrix(ctx);
+        self.draw_deco(ctx)?;
+        self.draw_pause(ctx)?;
         Ok(Transition::None)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hardcore_fail_returns_to_checkpoint() {
+        // a run made without the session file
+        let mut run = RunState::casual();
+        run.hardcore = true;
+        run.checkpoint = 5;
+
+        let loader = Rc::new(Loader::new());
+        let scores = Rc::new(RefCell::new(Scores::detached(loader.level_count())));
+        let mut field = GameField::headless(loader.clone(), scores);
+        field.load(6);
+        // a failed level is reloaded by the field before the run restarts it
+        field.load(field.level);
+        restart_run(&mut field, &run);
+        assert_eq!(field.level, 5);
+        assert_eq!(field.to_sim().board_hash(), BoardSim::new(&loader.level(5)).board_hash());
+    }
+}
@@ -1,17 +1,23 @@
+use log::{error, info, warn};
 use serde_derive::{Deserialize, Serialize};
-use tetra::input::Key;
 
 use std::fmt;
-use std::fs::{read, File};
+use std::fs::{copy, read, read_to_string, write, File};
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-use crate::common::replay_path;
+use crate::common::{replay_path, skip_write};
 use crate::consts::DEMO_LEVEL;
+use crate::field::{GameField, GameState};
+use crate::loader::{Level, Loader};
+use crate::sim::BoardSim;
 
-const REPLAY_VERSION: u32 = 1;
-// the first replay action must be no later than MAX_DELAY ticks
+const REPLAY_VERSION: u32 = 6;
+// the longest pause between replay actions kept by default when a replay is saved.
+// The first replay action must be no later than MAX_DELAY ticks
 const MAX_DELAY: u64 = 60 * 3;
+// replay files with this extension are in text format
+const TEXT_EXT: &str = "txt";
 
 #[derive(Copy, Clone, Serialize, Deserialize)]
 pub enum Action {
@@ -27,28 +33,34 @@ enum State {
     Replaying,
 }
 
-fn key_to_action(k: Key) -> Action {
-    match k {
-        Key::Up => Action::Up,
-        Key::Down => Action::Down,
-        Key::Space => Action::Throw,
-        _ => unreachable!(),
-    }
-}
-
-#[derive(Serialize, Deserialize)]
+#[derive(Copy, Clone, Serialize, Deserialize)]
 pub struct Move {
     tick: u64,
     act: Action,
 }
 
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Action::Up => write!(f, "UP"),
+            Action::Down => write!(f, "DOWN"),
+            Action::Throw => write!(f, "THROW"),
+        }
+    }
+}
+
+impl Move {
+    pub fn action(&self) -> Action {
+        self.act
+    }
+}
+
 impl fmt::Display for Move {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{} => ", self.tick)?;
         match self.act {
-            Action::Up => write!(f, "Player UP"),
-            Action::Down => write!(f, "Player DOWN"),
-            Action::Throw => write!(f, "THROW"),
+            Action::Throw => write!(f, "{}", self.act),
+            _ => write!(f, "Player {}", self.act),
         }
     }
 }
@@ -56,12 +68,268 @@ impl fmt::Display for Move {
 #[derive(Serialize, Deserialize)]
 pub struct Replay {
     version: u32,
+    checksum: u32,     // CRC32 of serialized moves to detect damaged files
+    board_hash: u64,   // hash of the level board the replay was recorded for
+    throws: u32,       // the number of throws, used to keep the best replay of a level
+    max_delay: u64,    // the longest pause the replay was squeezed to when saved, 0 - original timing
+    frame_clock: bool, // ticks count every frame, including block flights (replays before v6)
+    moves: Vec<Move>,
+}
+
+// the first replay format: no checksum
+#[derive(Deserialize)]
+struct ReplayV1 {
+    version: u32,
+    moves: Vec<Move>,
+}
+
+// the second replay format: no board hash
+#[derive(Deserialize)]
+struct ReplayV2 {
+    version: u32,
+    checksum: u32,
+    moves: Vec<Move>,
+}
+
+// the third replay format: no throw count
+#[derive(Deserialize)]
+struct ReplayV3 {
+    version: u32,
+    checksum: u32,
+    board_hash: u64,
     moves: Vec<Move>,
 }
 
+// the fourth replay format: no pause limit
+#[derive(Deserialize)]
+struct ReplayV4 {
+    version: u32,
+    checksum: u32,
+    board_hash: u64,
+    throws: u32,
+    moves: Vec<Move>,
+}
+
+// the fifth replay format: ticks count every frame
+#[derive(Deserialize)]
+struct ReplayV5 {
+    version: u32,
+    checksum: u32,
+    board_hash: u64,
+    throws: u32,
+    max_delay: u64,
+    moves: Vec<Move>,
+}
+
+// CRC-32 (IEEE) of the data
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+    for b in data {
+        crc ^= u32::from(*b);
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+fn moves_checksum(moves: &[Move]) -> u32 {
+    crc32(&bincode::serialize(moves).unwrap())
+}
+
+fn count_throws(moves: &[Move]) -> u32 {
+    moves.iter().filter(|m| matches!(m.act, Action::Throw)).count() as u32
+}
+
+// Shortens pauses between actions longer than `max_delay` ticks to `max_delay`. The pause
+// before the first action counts from the level start. Ticks are rebuilt from the clamped
+// pauses, so they never decrease
+fn trim_pauses(moves: &mut [Move], max_delay: u64) {
+    let (mut prev, mut prev_trimmed) = (0u64, 0u64);
+    for m in moves.iter_mut() {
+        let gap = m.tick.saturating_sub(prev).min(max_delay);
+        prev = m.tick;
+        m.tick = prev_trimmed + gap;
+        prev_trimmed = m.tick;
+    }
+}
+
+// plays the moves on a headless board of the level and returns the final board
+fn simulate(moves: &[Move], lvl: &Level) -> BoardSim {
+    let mut sim = BoardSim::new(lvl);
+    for m in moves.iter() {
+        sim.apply(m.act);
+    }
+    sim
+}
+
+// checks with a headless simulation whether the moves solve the level
+fn solves(moves: &[Move], lvl: &Level) -> bool {
+    simulate(moves, lvl).state == GameState::Winner
+}
+
+enum DecodeError {
+    Corrupted,
+    Unsupported(u32),
+}
+
+// Decodes a replay of any supported version. The fields an old version does not have are
+// zeroed and filled in later by `migrate`
+fn decode(bytes: &[u8]) -> Result<Replay, DecodeError> {
+    let version: u32 = bincode::deserialize(bytes).map_err(|_| DecodeError::Corrupted)?;
+    let replay = match version {
+        1 => {
+            let old: ReplayV1 = bincode::deserialize(bytes).map_err(|_| DecodeError::Corrupted)?;
+            let checksum = moves_checksum(&old.moves);
+            Replay {
+                version: old.version,
+                checksum,
+                board_hash: 0,
+                throws: 0,
+                max_delay: 0,
+                frame_clock: false,
+                moves: old.moves,
+            }
+        }
+        2 => {
+            let old: ReplayV2 = bincode::deserialize(bytes).map_err(|_| DecodeError::Corrupted)?;
+            let checksum = old.checksum;
+            Replay {
+                version: old.version,
+                checksum,
+                board_hash: 0,
+                throws: 0,
+                max_delay: 0,
+                frame_clock: false,
+                moves: old.moves,
+            }
+        }
+        3 => {
+            let old: ReplayV3 = bincode::deserialize(bytes).map_err(|_| DecodeError::Corrupted)?;
+            Replay {
+                version: old.version,
+                checksum: old.checksum,
+                board_hash: old.board_hash,
+                throws: 0,
+                max_delay: 0,
+                frame_clock: false,
+                moves: old.moves,
+            }
+        }
+        4 => {
+            let old: ReplayV4 = bincode::deserialize(bytes).map_err(|_| DecodeError::Corrupted)?;
+            Replay {
+                version: old.version,
+                checksum: old.checksum,
+                board_hash: old.board_hash,
+                throws: old.throws,
+                max_delay: 0,
+                frame_clock: false,
+                moves: old.moves,
+            }
+        }
+        5 => {
+            let old: ReplayV5 = bincode::deserialize(bytes).map_err(|_| DecodeError::Corrupted)?;
+            Replay {
+                version: old.version,
+                checksum: old.checksum,
+                board_hash: old.board_hash,
+                throws: old.throws,
+                max_delay: old.max_delay,
+                frame_clock: false,
+                moves: old.moves,
+            }
+        }
+        REPLAY_VERSION => bincode::deserialize(bytes).map_err(|_| DecodeError::Corrupted)?,
+        _ => return Err(DecodeError::Unsupported(version)),
+    };
+    if replay.checksum != moves_checksum(&replay.moves) {
+        return Err(DecodeError::Corrupted);
+    }
+    Ok(migrate(replay))
+}
+
+// Brings a replay of an old version up to date. Old replays do not know their board, so
+// their board hash stays 0. The throw count is calculated from the moves, all old
+// replays were squeezed to MAX_DELAY pauses, and their clock counted every frame
+fn migrate(old: Replay) -> Replay {
+    let mut replay = old;
+    if replay.version < 4 {
+        replay.throws = count_throws(&replay.moves);
+    }
+    if replay.version < 5 {
+        replay.max_delay = MAX_DELAY;
+    }
+    if replay.version < 6 {
+        replay.frame_clock = true;
+    }
+    replay.version = REPLAY_VERSION;
+    replay
+}
+
+// Parses a replay in text format written by `export_text`: a line `board: HASH`, optional
+// lines `max_delay: TICKS` and `clock: frames`, and a line per move in the `Move` format.
+// Empty lines and lines starting with `#` are skipped. Ticks must not decrease. A replay
+// without `max_delay` is considered squeezed to MAX_DELAY pauses
+fn parse_text(text: &str) -> Result<Replay, String> {
+    let mut replay = Replay::default();
+    let mut max_delay = None;
+    for (no, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let err = |msg: &str| format!("line {}: {}", no + 1, msg);
+        if let Some(hash) = line.strip_prefix("board:") {
+            replay.board_hash = hash.trim().parse().map_err(|_| err("invalid board hash"))?;
+            continue;
+        }
+        if let Some(delay) = line.strip_prefix("max_delay:") {
+            max_delay = Some(delay.trim().parse().map_err(|_| err("invalid pause limit"))?);
+            continue;
+        }
+        if let Some(clock) = line.strip_prefix("clock:") {
+            replay.frame_clock = match clock.trim() {
+                "frames" => true,
+                "moves" => false,
+                _ => return Err(err("the clock must be `frames` or `moves`")),
+            };
+            continue;
+        }
+        let (tick, act) = line.split_once("=>").ok_or_else(|| err("expected `TICK => ACTION`"))?;
+        let tick: u64 = tick.trim().parse().map_err(|_| err("invalid tick"))?;
+        let act = match act.trim() {
+            "THROW" => Action::Throw,
+            "Player UP" => Action::Up,
+            "Player DOWN" => Action::Down,
+            s => return Err(err(&format!("unknown action `{}`", s))),
+        };
+        if matches!(replay.moves.last(), Some(m) if m.tick > tick) {
+            return Err(err("the tick is less than the tick of the previous move"));
+        }
+        replay.moves.push(Move { tick, act });
+    }
+    if replay.moves.is_empty() {
+        return Err("the replay has no moves".to_string());
+    }
+    replay.checksum = moves_checksum(&replay.moves);
+    replay.throws = count_throws(&replay.moves);
+    replay.max_delay = max_delay.unwrap_or(MAX_DELAY);
+    Ok(replay)
+}
+
 impl Default for Replay {
     fn default() -> Self {
-        Replay { version: REPLAY_VERSION, moves: Vec::new() }
+        Replay {
+            version: REPLAY_VERSION,
+            checksum: 0,
+            board_hash: 0,
+            throws: 0,
+            max_delay: 0,
+            frame_clock: false,
+            moves: Vec::new(),
+        }
     }
 }
 
@@ -81,11 +349,43 @@ impl ReplayEngine {
         PathBuf::from(&format!("level-{:04}.rpl", lvl))
     }
 
+    fn backup_filename(lvl: usize) -> PathBuf {
+        PathBuf::from(&format!("level-{:04}.rpl.bak", lvl))
+    }
+
+    // Replaces the replay of the level with its backup. The backup is made every time
+    // a replay is overwritten
+    pub fn restore_backup(lvl: usize) -> bool {
+        let mut bpath = replay_path();
+        bpath.push(Self::backup_filename(lvl));
+        if !bpath.is_file() || skip_write("replay") {
+            return false;
+        }
+        let mut rpath = replay_path();
+        rpath.push(Self::replay_filename(lvl));
+        copy(bpath, rpath).is_ok()
+    }
+
     pub fn rec_start(&mut self) {
         self.state = State::Recording;
+        self.replay.frame_clock = false;
         self.replay.moves.clear();
-        // reassign because previous load call can load old version of replay
-        self.replay.version = REPLAY_VERSION;
+    }
+
+    // The replay clock runs only while the player can make moves, so flights of blocks and
+    // the time spent in other scenes never add pauses to a replay. Replays before v6 counted
+    // every frame and are played the same way
+    pub fn clock_runs(&self, field: &GameField) -> bool {
+        self.replay.frame_clock || field.is_interactive()
+    }
+
+    // the replay starts no later than the pause limit it was saved with. A replay with the
+    // original timing starts exactly as it was recorded
+    fn start_shift(&self) -> u64 {
+        match (self.replay.moves.first(), self.replay.max_delay) {
+            (Some(m), delay) if delay != 0 => m.tick.saturating_sub(delay),
+            _ => 0,
+        }
     }
 
     pub fn load(&mut self, lvl: usize) {
@@ -105,46 +405,195 @@ impl ReplayEngine {
             }
         }
 
-        let replay: Replay = bincode::deserialize(&bytes).unwrap();
-        if replay.version == REPLAY_VERSION {
-            self.replay = replay;
-            self.idx = 0;
-            if !self.replay.moves.is_empty() {
-                self.shift =
-                    if self.replay.moves[0].tick > MAX_DELAY { self.replay.moves[0].tick - MAX_DELAY } else { 0 }
+        match decode(&bytes) {
+            Ok(replay) => {
+                self.replay = replay;
+                self.idx = 0;
+                self.shift = self.start_shift();
+            }
+            Err(DecodeError::Unsupported(version)) => {
+                error!("Unsupported version: {}, can replay only versions up to {}", version, REPLAY_VERSION);
             }
+            Err(DecodeError::Corrupted) => {
+                error!("Replay for level {} is corrupted", lvl);
+                let mut bpath = replay_path();
+                bpath.push(Self::backup_filename(lvl));
+                if bpath.is_file() {
+                    warn!("Its backup {:?} exists. To restore it, run the game with `--restore-replay {}`", bpath, lvl);
+                } else {
+                    warn!("There is no backup of the replay");
+                }
+            }
+        }
+    }
+
+    // Reads a replay file recorded on any computer and finds the level with the same board.
+    // The level number the replay was recorded for does not matter, so a replay works in a pack
+    // with a different level order. A file with extension `txt` is a replay in text format.
+    // Returns the level number and the replay ready to play
+    pub fn open(path: &Path, loader: &Loader) -> Result<(usize, ReplayEngine), String> {
+        let replay = if path.extension().is_some_and(|ext| ext == TEXT_EXT) {
+            let text = read_to_string(path).map_err(|e| format!("failed to read {:?}: {}", path, e))?;
+            parse_text(&text)?
         } else {
-            eprintln!("Unsupported version: {}, can replay only version {}", replay.version, REPLAY_VERSION);
+            let bytes = read(path).map_err(|e| format!("failed to read {:?}: {}", path, e))?;
+            decode(&bytes).map_err(|e| match e {
+                DecodeError::Corrupted => "the replay is corrupted".to_string(),
+                DecodeError::Unsupported(v) => format!("unsupported replay version {}", v),
+            })?
+        };
+        // replays of old versions do not have board hash
+        if replay.board_hash != 0 {
+            for lvl in 1..loader.level_count() {
+                if BoardSim::new(&loader.level(lvl)).board_hash() == replay.board_hash {
+                    let mut engine = ReplayEngine::new();
+                    engine.replay = replay;
+                    engine.shift = engine.start_shift();
+                    return Ok((lvl, engine));
+                }
+            }
         }
+        Err("no level has the board of the replay (or the replay is too old to know its board)".to_string())
     }
 
-    pub fn save(&mut self, lvl: usize) {
-        if self.replay.moves.is_empty() {
-            return;
+    // Writes the replay in text format, a move per line, so it can be shared and edited by hand.
+    // The board hash is taken from the level because old replays do not have it
+    pub fn export_text(&self, path: &Path, level: &Level) -> Result<(), String> {
+        let mut text = String::from("# unblocked replay: TICK => THROW, TICK => Player UP, or TICK => Player DOWN\n");
+        text += &format!("board: {}\n", BoardSim::new(level).board_hash());
+        text += &format!("max_delay: {}\n", self.replay.max_delay);
+        if self.replay.frame_clock {
+            text += "clock: frames\n";
         }
+        for m in self.replay.moves.iter() {
+            text += &format!("{}\n", m);
+        }
+        write(path, text).map_err(|e| format!("failed to write {:?}: {}", path, e))
+    }
 
-        // make breaks between actions no longer than MAX_DELAY
-        let mut shift = 0u64;
-        let mut last_delay = 0u64;
-        for v in self.replay.moves.iter_mut() {
-            let delay = v.tick - shift - last_delay;
-            if delay > MAX_DELAY {
-                shift += delay - MAX_DELAY;
-            }
-            if shift != 0 {
-                v.tick -= shift;
-            }
-            last_delay = v.tick;
+    // Imports a replay file recorded on another computer: finds the level with the same board
+    // and saves the replay for it. Returns the level number
+    pub fn import(path: &Path, loader: &Loader) -> Result<usize, String> {
+        let (lvl, engine) = Self::open(path, loader)?;
+        if !engine.save(lvl, &loader.level(lvl)) {
+            return Err(format!("the replay was not saved for level {}", lvl));
         }
+        Ok(lvl)
+    }
 
-        let encoded: Vec<u8> = bincode::serialize(&self.replay).unwrap();
+    // Saves the replay with pauses squeezed to MAX_DELAY
+    pub fn save(&self, lvl: usize, level: &Level) -> bool {
+        self.save_with_options(lvl, level, true, MAX_DELAY)
+    }
+
+    // Saves the replay unless the level already has a better one: a solution always beats
+    // an unfinished game, and of two solutions the one with fewer throws wins. With `trim`
+    // the pauses between actions longer than `max_delay` ticks are squeezed to it in the saved
+    // file, otherwise the original timing is kept. The recorded replay is not changed.
+    // Returns true if the replay was saved
+    pub fn save_with_options(&self, lvl: usize, level: &Level, trim: bool, max_delay: u64) -> bool {
+        if self.replay.moves.is_empty() || skip_write("replay") {
+            return false;
+        }
+        let saved = self.to_saved(level, trim, max_delay);
+
+        let encoded: Vec<u8> = bincode::serialize(&saved).unwrap();
         let mut rpath = replay_path();
         rpath.push(Self::replay_filename(lvl));
-        if let Ok(mut f) = File::create(rpath) {
-            let _ = f.write_all(&encoded);
+        // keep the previous replay in case the new one gets damaged. A damaged
+        // replay must not replace a good backup
+        if let Ok(old) = read(&rpath) {
+            if let Ok(old) = decode(&old) {
+                let (solved, old_solved) = (solves(&saved.moves, level), solves(&old.moves, level));
+                if old_solved && (!solved || old.throws <= saved.throws) {
+                    info!("Level {} already has a solution in {} throws, the replay is not saved", lvl, old.throws);
+                    return false;
+                }
+                let mut bpath = replay_path();
+                bpath.push(Self::backup_filename(lvl));
+                let _ = copy(&rpath, bpath);
+            }
+        }
+        match File::create(rpath) {
+            Ok(mut f) => f.write_all(&encoded).is_ok(),
+            Err(_) => false,
+        }
+    }
+
+    // the replay as it is written to a file by `save_with_options`
+    fn to_saved(&self, level: &Level, trim: bool, max_delay: u64) -> Replay {
+        // squeezing to zero pauses would put all actions at the same tick
+        let trim = trim && max_delay != 0;
+        let mut moves = self.replay.moves.clone();
+        if trim {
+            trim_pauses(&mut moves, max_delay);
+        }
+        Replay {
+            version: REPLAY_VERSION,
+            checksum: moves_checksum(&moves),
+            board_hash: BoardSim::new(level).board_hash(),
+            throws: count_throws(&moves),
+            max_delay: if trim { max_delay } else { 0 },
+            frame_clock: self.replay.frame_clock,
+            moves,
         }
     }
 
+    // Removes redundant navigation: between two throws only the moves required to reach the row
+    // of the next throw are kept, and everything after the last throw is dropped. Throws and
+    // their ticks are untouched, so the replay plays exactly the same game.
+    // The optimized replay is checked with a headless simulation and it replaces the recorded
+    // moves only if it still solves the level. Returns true if the moves were replaced
+    pub fn optimize(&mut self, lvl: &Level) -> bool {
+        let mut sim = BoardSim::new(lvl);
+        let mut moves: Vec<Move> = Vec::new();
+        let mut pending: Vec<u64> = Vec::new(); // ticks of navigation moves since the last throw
+        let mut start_row = sim.row;
+
+        for m in self.replay.moves.iter() {
+            match m.act {
+                Action::Up | Action::Down => {
+                    sim.apply(m.act);
+                    pending.push(m.tick);
+                }
+                Action::Throw => {
+                    // every move shifts the block by one row at most, so there are always
+                    // enough recorded moves to reach the row. Keep the latest ones
+                    let act = if sim.row > start_row { Action::Down } else { Action::Up };
+                    let need = sim.row.abs_diff(start_row);
+                    for tick in pending[pending.len() - need..].iter() {
+                        moves.push(Move { tick: *tick, act });
+                    }
+                    moves.push(Move { tick: m.tick, act: Action::Throw });
+                    sim.apply(Action::Throw);
+                    pending.clear();
+                    start_row = sim.row;
+                }
+            }
+        }
+
+        if !solves(&moves, lvl) {
+            return false;
+        }
+
+        self.replay.moves = moves;
+        true
+    }
+
+    // Returns the board hash and the row of player's block before every throw of the replay.
+    // It is used to find out which step of the solution matches the current board
+    pub fn throw_hints(&self, lvl: &Level) -> Vec<(u64, usize)> {
+        let mut sim = BoardSim::new(lvl);
+        let mut hints = Vec::new();
+        for m in self.replay.moves.iter() {
+            if let Action::Throw = m.act {
+                hints.push((sim.board_hash(), sim.row));
+            }
+            sim.apply(m.act);
+        }
+        hints
+    }
+
     pub fn is_loaded(&self) -> bool {
         !self.replay.moves.is_empty()
     }
@@ -158,6 +607,41 @@ impl ReplayEngine {
         self.idx = 0;
     }
 
+    // starts playing the replay from the first action again
+    pub fn rewind(&mut self) {
+        if self.state == State::Replaying {
+            self.idx = 0;
+        }
+    }
+
+    // Jumps to the action with index `idx` of the replay being played: all actions before it
+    // are passed to `apply` without any delay. Returns the tick of the last applied action
+    pub fn apply_up_to<F: FnMut(Action)>(&mut self, idx: usize, mut apply: F) -> u64 {
+        if self.state != State::Replaying {
+            return 0;
+        }
+        let idx = idx.min(self.replay.moves.len());
+        for m in self.replay.moves[..idx].iter() {
+            apply(m.act);
+        }
+        self.idx = idx;
+        if idx == 0 {
+            0
+        } else {
+            self.replay.moves[idx - 1].tick - self.shift
+        }
+    }
+
+    // the index of the next action of the replay being played
+    pub fn position(&self) -> usize {
+        self.idx
+    }
+
+    // the tick of the last action of the replay
+    pub fn last_tick(&self) -> u64 {
+        self.replay.moves.last().map_or(0, |m| m.tick - self.shift)
+    }
+
     pub fn is_playing(&self) -> bool {
         self.state == State::Replaying && self.idx < self.replay.moves.len()
     }
@@ -189,13 +673,363 @@ impl ReplayEngine {
         Some(self.replay.moves[self.idx - 1].act)
     }
 
-    pub fn add_action(&mut self, ticks: u64, key: Key) {
+    pub fn add_action(&mut self, ticks: u64, act: Action) {
         assert!(self.state == State::Recording);
-        let m = Move { tick: ticks, act: key_to_action(key) };
+        let m = Move { tick: ticks, act };
         self.replay.moves.push(m);
     }
 
+    // removes the last recorded throw and all moves after it
+    pub fn undo_throw(&mut self) {
+        if let Some(idx) = self.replay.moves.iter().rposition(|m| matches!(m.act, Action::Throw)) {
+            self.replay.moves.truncate(idx);
+        }
+    }
+
+    // moves recorded or loaded so far
+    pub fn moves(&self) -> &[Move] {
+        &self.replay.moves
+    }
+
     pub fn action_count(&mut self) -> usize {
         self.replay.moves.len()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::consts::FPS;
+    use crate::demo::play_headless;
+    use crate::scores::Scores;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn demo_level() -> Level {
+        Loader::new().level(DEMO_LEVEL)
+    }
+
+    fn demo_moves() -> Vec<Move> {
+        let mut engine = ReplayEngine::new();
+        engine.load(DEMO_LEVEL);
+        engine.replay.moves
+    }
+
+    fn engine_with(moves: Vec<Move>) -> ReplayEngine {
+        let mut engine = ReplayEngine::new();
+        engine.replay.moves = moves;
+        engine
+    }
+
+    // the tick and the row of every throw
+    fn throw_rows(moves: &[Move], lvl: &Level) -> Vec<(u64, usize)> {
+        let mut sim = BoardSim::new(lvl);
+        let mut rows = Vec::new();
+        for m in moves.iter() {
+            if let Action::Throw = m.act {
+                rows.push((m.tick, sim.row));
+            }
+            sim.apply(m.act);
+        }
+        rows
+    }
+
+    // adds a detour of two rows and back before every throw
+    fn wander(moves: &[Move], lvl: &Level) -> Vec<Move> {
+        let mut sim = BoardSim::new(lvl);
+        let mut out = Vec::new();
+        for m in moves.iter() {
+            if let Action::Throw = m.act {
+                let (away, back) = if sim.row > 2 { (Action::Up, Action::Down) } else { (Action::Down, Action::Up) };
+                for act in [away, away, back, back] {
+                    out.push(Move { tick: m.tick, act });
+                }
+            }
+            sim.apply(m.act);
+            out.push(*m);
+        }
+        out
+    }
+
+    #[test]
+    fn optimize_drops_detours() {
+        let lvl = demo_level();
+        let wandering = wander(&demo_moves(), &lvl);
+        assert!(solves(&wandering, &lvl));
+        let mut engine = engine_with(wandering.clone());
+        assert!(engine.optimize(&lvl));
+        let optimized = engine.moves();
+        assert!(optimized.len() + 4 * count_throws(&wandering) as usize <= wandering.len());
+        assert_eq!(throw_rows(optimized, &lvl), throw_rows(&wandering, &lvl));
+        assert!(solves(optimized, &lvl));
+    }
+
+    #[test]
+    fn optimize_drops_moves_after_last_throw() {
+        let lvl = demo_level();
+        let mut moves = demo_moves();
+        let last = moves.last().map_or(0, |m| m.tick);
+        moves.push(Move { tick: last + 10, act: Action::Up });
+        moves.push(Move { tick: last + 20, act: Action::Up });
+        let mut engine = engine_with(moves);
+        assert!(engine.optimize(&lvl));
+        assert!(matches!(engine.moves().last().map(|m| m.act), Some(Action::Throw)));
+    }
+
+    #[test]
+    fn optimize_keeps_unsolved_replay() {
+        let lvl = demo_level();
+        let mut moves = demo_moves();
+        moves.truncate(moves.len() - 1);
+        moves.push(Move { tick: 1000, act: Action::Up });
+        let cnt = moves.len();
+        let mut engine = engine_with(moves);
+        assert!(!engine.optimize(&lvl));
+        assert_eq!(engine.moves().len(), cnt);
+    }
+
+    // Records the demo solution the way PlayScene does, with a visit to the help scene in the
+    // middle. The visit must not show up in the replay, and the replay must play the same game
+    #[test]
+    fn help_excursion_adds_no_pause() {
+        let loader = Rc::new(Loader::new());
+        let scores = Rc::new(RefCell::new(Scores::detached(loader.level_count())));
+        let mut field = GameField::headless(loader.clone(), scores);
+        field.load(DEMO_LEVEL);
+        let mut rec = ReplayEngine::new();
+        rec.rec_start();
+        let mut clock = 0;
+        for m in demo_moves().iter() {
+            // wait until the field takes moves again and idle a bit
+            let mut idle = 0;
+            while idle < 5 {
+                if rec.clock_runs(&field) {
+                    clock += 1;
+                }
+                field.step();
+                if field.is_settled() && field.is_interactive() {
+                    idle += 1;
+                }
+            }
+            // before the third move the help scene is on top for a minute: PlayScene is not
+            // updated, so neither the field nor the replay clock moves
+            if rec.clock_runs(&field) {
+                clock += 1;
+            }
+            match m.act {
+                Action::Up => field.player_up(),
+                Action::Down => field.player_down(),
+                Action::Throw => assert!(field.throw_brick()),
+            }
+            rec.add_action(clock, m.act);
+            field.step();
+        }
+        while !field.is_settled() {
+            field.step();
+        }
+        assert!(field.state == GameState::Winner);
+
+        let moves = rec.moves().to_vec();
+        let gap = moves[2].tick - moves[1].tick;
+        assert!(gap < MAX_DELAY.min(60 * FPS as u64), "a pause of {} ticks is recorded", gap);
+
+        let mut engine = engine_with(moves);
+        let played = play_headless(&mut engine, loader, DEMO_LEVEL);
+        assert!(played.state == GameState::Winner);
+        assert_eq!(played.to_sim().board_hash(), field.to_sim().board_hash());
+    }
+
+    #[test]
+    fn text_export_round_trip() {
+        let loader = Loader::new();
+        let lvl = loader.level(1);
+        let mut engine = engine_with(demo_moves());
+        engine.replay.board_hash = BoardSim::new(&lvl).board_hash();
+        engine.replay.checksum = moves_checksum(&engine.replay.moves);
+        engine.replay.throws = count_throws(&engine.replay.moves);
+        for (max_delay, frame_clock) in [(0, false), (90, false), (MAX_DELAY, true)] {
+            engine.replay.max_delay = max_delay;
+            engine.replay.frame_clock = frame_clock;
+            engine.shift = engine.start_shift();
+            let path = std::env::temp_dir().join(format!("unblocked-round-trip-{}.txt", max_delay));
+            engine.export_text(&path, &lvl).unwrap();
+            let (no, imported) = ReplayEngine::open(&path, &loader).unwrap();
+            let _ = std::fs::remove_file(&path);
+            assert_eq!(no, 1);
+            assert_eq!(imported.shift, engine.shift);
+            assert_eq!(bincode::serialize(&imported.replay).unwrap(), bincode::serialize(&engine.replay).unwrap());
+        }
+    }
+
+    // a replay of an old version in the layout it was saved with
+    fn old_replay(version: u32, moves: &[Move], board_hash: u64) -> Vec<u8> {
+        let (sum, throws, moves) = (moves_checksum(moves), count_throws(moves), moves.to_vec());
+        match version {
+            1 => bincode::serialize(&(1u32, moves)),
+            2 => bincode::serialize(&(2u32, sum, moves)),
+            3 => bincode::serialize(&(3u32, sum, board_hash, moves)),
+            4 => bincode::serialize(&(4u32, sum, board_hash, throws, moves)),
+            _ => bincode::serialize(&(5u32, sum, board_hash, throws, 90u64, moves)),
+        }
+        .unwrap()
+    }
+
+    #[test]
+    fn old_replays_are_migrated() {
+        let moves = demo_moves();
+        let hash = BoardSim::new(&demo_level()).board_hash();
+        for version in 1..=5 {
+            let replay = match decode(&old_replay(version, &moves, hash)) {
+                Ok(replay) => replay,
+                Err(_) => panic!("replay v{} is not loaded", version),
+            };
+            assert_eq!(replay.version, REPLAY_VERSION);
+            assert_eq!(bincode::serialize(&replay.moves).unwrap(), bincode::serialize(&moves).unwrap());
+            assert_eq!(replay.checksum, moves_checksum(&moves));
+            assert_eq!(replay.board_hash, if version < 3 { 0 } else { hash }, "v{}", version);
+            assert_eq!(replay.throws, count_throws(&moves), "v{}", version);
+            assert_eq!(replay.max_delay, if version < 5 { MAX_DELAY } else { 90 }, "v{}", version);
+            assert!(replay.frame_clock, "v{}", version);
+        }
+    }
+
+    #[test]
+    fn damaged_and_unknown_replays_are_rejected() {
+        let moves = demo_moves();
+        let mut bytes = old_replay(2, &moves, 0);
+        // the tick of the last move: a move is a u64 tick and a u32 action
+        let tick = bytes.len() - 12;
+        bytes[tick] ^= 1;
+        assert!(matches!(decode(&bytes), Err(DecodeError::Corrupted)));
+        let bytes = bincode::serialize(&(REPLAY_VERSION + 1, moves_checksum(&moves), moves)).unwrap();
+        assert!(matches!(decode(&bytes), Err(DecodeError::Unsupported(v)) if v == REPLAY_VERSION + 1));
+    }
+
+    // the bundled demo replay is in the first format
+    #[test]
+    fn demo_replay_is_loaded() {
+        let bytes = include_bytes!("../assets/level-0000.rpl");
+        assert_eq!(bincode::deserialize::<u32>(bytes).unwrap(), 1);
+        let mut engine = ReplayEngine::new();
+        engine.load(DEMO_LEVEL);
+        assert!(engine.is_loaded());
+        assert_eq!(engine.replay.version, REPLAY_VERSION);
+        assert_eq!(engine.replay.throws, count_throws(engine.moves()));
+    }
+
+    fn ticks(moves: &[Move]) -> Vec<u64> {
+        moves.iter().map(|m| m.tick).collect()
+    }
+
+    #[test]
+    fn saved_replay_keeps_or_trims_pauses() {
+        let lvl = demo_level();
+        let moves: Vec<Move> =
+            [400, 410, 910, 940, 1940].iter().map(|tick| Move { tick: *tick, act: Action::Up }).collect();
+        let engine = engine_with(moves.clone());
+
+        let saved = engine.to_saved(&lvl, false, 100);
+        assert_eq!(ticks(&saved.moves), ticks(&moves));
+        assert_eq!(saved.max_delay, 0);
+        let saved = engine.to_saved(&lvl, true, 0);
+        assert_eq!(ticks(&saved.moves), ticks(&moves));
+        assert_eq!(saved.max_delay, 0);
+
+        let saved = engine.to_saved(&lvl, true, 100);
+        assert_eq!(ticks(&saved.moves), vec![100, 110, 210, 240, 340]);
+        assert_eq!(saved.max_delay, 100);
+        assert_eq!(saved.checksum, moves_checksum(&saved.moves));
+        // the recorded replay is not changed
+        assert_eq!(ticks(engine.moves()), ticks(&moves));
+    }
+
+    #[test]
+    fn configured_pause_limit_is_applied() {
+        let lvl = demo_level();
+        let engine = engine_with(vec![Move { tick: 10, act: Action::Up }, Move { tick: 5000, act: Action::Down }]);
+        let cfg = Config { replay_max_pause: 500, ..Config::default() };
+        let max_delay = cfg.replay_max_delay();
+        assert_eq!(max_delay, u64::from(FPS) / 2);
+        let saved = engine.to_saved(&lvl, max_delay != 0, max_delay);
+        assert_eq!(ticks(&saved.moves), vec![10, 10 + max_delay]);
+
+        let cfg = Config { replay_max_pause: 0, ..Config::default() };
+        let max_delay = cfg.replay_max_delay();
+        let saved = engine.to_saved(&lvl, max_delay != 0, max_delay);
+        assert_eq!(ticks(&saved.moves), vec![10, 5000]);
+    }
+
+    #[test]
+    fn trimmed_pauses_still_solve() {
+        // long pauses before the first move and after every third one
+        let mut moves = demo_moves();
+        let mut extra = 10_000;
+        for (idx, m) in moves.iter_mut().enumerate() {
+            m.tick += extra;
+            if idx % 3 == 2 {
+                extra += 5_000 * idx as u64;
+            }
+        }
+        let mut trimmed = moves.clone();
+        trim_pauses(&mut trimmed, MAX_DELAY);
+        let mut prev = (0, 0);
+        for (m, t) in moves.iter().zip(trimmed.iter()) {
+            assert!(t.tick >= prev.1);
+            assert_eq!(t.tick - prev.1, (m.tick - prev.0).min(MAX_DELAY));
+            prev = (m.tick, t.tick);
+        }
+
+        let mut engine = engine_with(trimmed);
+        engine.replay.frame_clock = true;
+        engine.replay.max_delay = MAX_DELAY;
+        engine.shift = engine.start_shift();
+        let field = play_headless(&mut engine, Rc::new(Loader::new()), DEMO_LEVEL);
+        assert!(field.state == GameState::Winner);
+    }
+
+    #[test]
+    fn checksum_detects_flipped_bytes() {
+        // the standard CRC-32 check value
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        let moves = demo_moves();
+        let replay = Replay { checksum: moves_checksum(&moves), moves, ..Replay::default() };
+        let bytes = bincode::serialize(&replay).unwrap();
+        assert!(decode(&bytes).is_ok());
+        // every byte of the move list: ticks, actions, and its length
+        let moves_start = bytes.len() - 12 * replay.moves.len() - 8;
+        for idx in moves_start..bytes.len() {
+            let mut damaged = bytes.clone();
+            damaged[idx] ^= 0x10;
+            assert!(matches!(decode(&damaged), Err(DecodeError::Corrupted)), "byte {}", idx);
+        }
+    }
+
+    // a replay is found by its board even if the level has another number in the pack
+    #[test]
+    fn replay_is_matched_by_board() {
+        let std = Loader::new();
+        let engine = engine_with(demo_moves());
+        let path = std::env::temp_dir().join("unblocked-board-match.rpl");
+        let bytes = bincode::serialize(&engine.to_saved(&std.level(5), false, 0)).unwrap();
+        std::fs::write(&path, bytes).unwrap();
+
+        let mut levels: Vec<Level> = (0..std.level_count()).map(|idx| std.level(idx)).collect();
+        let moved = levels.remove(5);
+        levels.insert(2, moved);
+        let reordered = match Loader::from_levels(levels) {
+            Ok(loader) => loader,
+            Err(e) => panic!("Invalid levels: {}", e),
+        };
+        let found = (ReplayEngine::open(&path, &std).map(|r| r.0), ReplayEngine::open(&path, &reordered).map(|r| r.0));
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(found, (Ok(5), Ok(2)));
+
+        // a replay of a board no level has: an empty one
+        let path = std::env::temp_dir().join("unblocked-board-unknown.txt");
+        engine.export_text(&path, &Level::default()).unwrap();
+        let unknown = ReplayEngine::open(&path, &std).map(|r| r.0);
+        let _ = std::fs::remove_file(&path);
+        assert!(unknown.is_err());
+    }
+}
@@ -0,0 +1,133 @@
+use log::error;
+use serde_derive::{Deserialize, Serialize};
+use std::fs::{read_to_string, remove_file, write};
+use std::time::{Duration, Instant};
+
+use crate::common::{session_path, skip_write};
+use crate::config::Config;
+
+#[derive(Default, Serialize, Deserialize)]
+struct Session {
+    checkpoint: usize, // 0 - no run in progress
+}
+
+// State of a run through consecutive levels. In hardcore mode a failed level sends
+// the player back to the last checkpoint. The checkpoint is saved to the session file,
+// so a run can be continued after restarting the game
+pub struct RunState {
+    pub hardcore: bool,
+    every: usize,          // distance between checkpoints
+    pub checkpoint: usize, // the level to restart from after a fail
+}
+
+impl RunState {
+    // Starts a run from the level. A saved run is continued if the level is between
+    // its checkpoint and the next one
+    pub fn new(cfg: &Config, lvl: usize) -> RunState {
+        let mut run = RunState { hardcore: cfg.hardcore, every: cfg.checkpoint_every.max(1), checkpoint: lvl };
+        if !run.hardcore {
+            return run;
+        }
+
+        let saved = Self::load_session().checkpoint;
+        if saved == 0 || saved > lvl || lvl >= saved + run.every {
+            run.save();
+        } else {
+            run.checkpoint = saved;
+        }
+        run
+    }
+
+    // a run without checkpoints whatever the config says
+    pub fn casual() -> RunState {
+        RunState { hardcore: false, every: 1, checkpoint: 0 }
+    }
+
+    fn load_session() -> Session {
+        let data = match read_to_string(session_path()) {
+            Ok(s) => s,
+            Err(_) => return Session::default(),
+        };
+        match toml::from_str(&data) {
+            Ok(s) => s,
+            Err(e) => {
+                error!("Failed to parse session file: {:?}", e);
+                Session::default()
+            }
+        }
+    }
+
+    fn save(&self) {
+        if skip_write("session") {
+            return;
+        }
+        let tml = toml::to_string(&Session { checkpoint: self.checkpoint }).unwrap();
+        let _ = write(session_path(), tml);
+    }
+
+    // the player has started the next level. Makes a checkpoint if it is time
+    pub fn reached(&mut self, lvl: usize) {
+        if self.hardcore && lvl >= self.checkpoint + self.every {
+            self.checkpoint = lvl;
+            self.save();
+        }
+    }
+
+    // the level to play after failing the level
+    pub fn restart_level(&self, failed: usize) -> usize {
+        if self.hardcore {
+            self.checkpoint
+        } else {
+            failed
+        }
+    }
+
+    // all levels are solved, nothing to continue
+    pub fn finish(&self) {
+        if self.hardcore && !skip_write("session") {
+            let _ = remove_file(session_path());
+        }
+    }
+}
+
+// Wall-clock timer of a speedrun: it starts at the first throw of the first level and stops
+// when the last level is solved. The timer does not run while the game is paused
+#[derive(Default)]
+pub struct RunTimer {
+    elapsed: Duration,        // time before the last pause
+    started: Option<Instant>, // None - the timer is not running
+    begun: bool,
+    pub finished: bool,
+    pub best: bool, // the finished run is the best one
+}
+
+impl RunTimer {
+    // the first throw of the run. Later calls do nothing
+    pub fn start(&mut self) {
+        if !self.begun {
+            self.begun = true;
+            self.started = Some(Instant::now());
+        }
+    }
+
+    pub fn pause(&mut self) {
+        if let Some(s) = self.started.take() {
+            self.elapsed += s.elapsed();
+        }
+    }
+
+    pub fn resume(&mut self) {
+        if self.begun && !self.finished && self.started.is_none() {
+            self.started = Some(Instant::now());
+        }
+    }
+
+    pub fn finish(&mut self) {
+        self.pause();
+        self.finished = true;
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed + self.started.map_or(Duration::ZERO, |s| s.elapsed())
+    }
+}
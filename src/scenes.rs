@@ -1,52 +1,307 @@
-use tetra::graphics::scaling::{ScalingMode, ScreenScaler};
-use tetra::graphics::{self, Color};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use log::{error, warn};
+use tetra::audio;
+use tetra::graphics::mesh::{Mesh, ShapeStyle};
+use tetra::graphics::scaling::ScreenScaler;
+use tetra::graphics::{self, Color, DrawParams, Rectangle};
+use tetra::input::{self, Key};
+use tetra::math::Vec2;
 use tetra::window;
 use tetra::{Context, Event, State};
 
-use crate::consts::{SCR_H, SCR_W};
+use crate::args::Args;
+use crate::assets::Assets;
+use crate::audio::{Music, Sfx, Track};
+use crate::config::Config;
+use crate::consts::{FPS, SCR_H, SCR_W};
+use crate::demo::DemoScene;
+use crate::loader::Loader;
 use crate::mainmenu::TitleScene;
+use crate::net::{StreamClient, StreamServer};
+use crate::play::{PlayMode, PlayScene};
+use crate::replay::ReplayEngine;
+use crate::scores::Scores;
+use crate::watch::WatchScene;
 
 pub trait Scene {
+    // mouse position in the game screen coordinates. It is called before every update
+    fn set_mouse_position(&mut self, _pos: Vec2<f32>) {}
+    // background music of the scene. None - the music of the scene below it goes on
+    fn music(&self) -> Option<Track> {
+        None
+    }
     fn update(&mut self, ctx: &mut Context) -> tetra::Result<Transition>;
     fn draw(&mut self, ctx: &mut Context) -> tetra::Result<Transition>;
+    // the game is closing without leaving the scene (e.g, the window is closed)
+    fn on_quit(&mut self) {}
 }
 
 pub enum Transition {
     None,
     Push(Box<dyn Scene>),
     Pop,
+    // pops the active scene and pushes the new one, so the stack does not grow
+    Replace(Box<dyn Scene>),
+}
+
+// A scene change fades the screen to black and back
+enum Fade {
+    Idle,
+    // the active scene is frozen while the screen darkens. The transition is applied at the end
+    Out { frame: u32, next: Transition },
+    // the new active scene runs while the screen lightens
+    In { frame: u32 },
 }
 
 pub struct SceneManager {
     scaler: ScreenScaler,
     scenes: Vec<Box<dyn Scene>>,
+    letterbox: Color, // color of the window area outside of the game screen
+    fade: Fade,
+    fade_frames: u32,      // duration of each half of the fade. 0 - scenes change instantly
+    overlay: Option<Mesh>, // black screen-sized rectangle, created at first draw call
+    config: Rc<RefCell<Config>>,
+    resized: bool, // the window settings in config have changed and must be saved on exit
+    // the fullscreen mode in config when it was applied last time. The window can differ from
+    // it at start (e.g, `--windowed`), so it is not compared with the window itself
+    fullscreen: bool,
+    music: Music,
+    sfx: Sfx, // a copy of the sound effects of all scenes to set their volume
 }
 
 impl SceneManager {
-    pub fn new(ctx: &mut Context) -> tetra::Result<SceneManager> {
-        let ts = TitleScene::new(ctx)?;
+    // `replay` is a replay file from the command line and the level it was recorded for
+    pub fn new(ctx: &mut Context, args: &Args, replay: Option<(usize, ReplayEngine)>) -> tetra::Result<SceneManager> {
+        // all embedded images are decoded once and shared by all scenes
+        let config = Rc::new(RefCell::new(Config::load_or_create()));
+        let assets = Rc::new(Assets::new(ctx, &config.borrow().theme)?);
+        let audio = assets.audio;
+        let sfx = assets.sfx.clone();
+        let loader = Rc::new(Loader::new());
+        let scores = Rc::new(RefCell::new(Scores::new(loader.level_count())));
+
+        // networking problems are not fatal: the game just runs without spectators
+        let stream = match args.serve {
+            None => None,
+            Some(port) => match StreamServer::new(port) {
+                Ok(srv) => Some(Rc::new(RefCell::new(srv))),
+                Err(e) => {
+                    error!("Failed to start streaming on port {}: {}", port, e);
+                    None
+                }
+            },
+        };
+
+        let letterbox = config.borrow().letterbox_color();
+        let scaling = config.borrow().scaling_mode();
+        let fade_frames = config.borrow().fade_ms * FPS / 1000;
+        let ts = TitleScene::new(ctx, assets.clone(), loader.clone(), scores.clone(), config.clone(), stream.clone())?;
+        let mut scenes: Vec<Box<dyn Scene>> = vec![Box::new(ts)];
+        if let Some(lvl) = args.level {
+            let curr = scores.borrow_mut().set_curr_level(lvl);
+            if curr != lvl {
+                warn!("Level {} is not available yet, level {} is started", lvl, curr);
+            }
+            let mode = PlayMode::Normal;
+            let ps = PlayScene::new(assets.clone(), loader.clone(), scores.clone(), config.clone(), stream, mode)?;
+            scenes.push(Box::new(ps));
+        }
+        if let Some((lvl, engine)) = replay {
+            let ds = DemoScene::new(assets.clone(), loader.clone(), scores.clone(), config.clone(), lvl)?;
+            scenes.push(Box::new(ds.with_replay(engine)));
+        }
+        if let Some(seed) = args.endless {
+            scenes.push(Box::new(PlayScene::new_endless(assets.clone(), config.clone(), seed)?));
+        }
+        if let Some(addr) = &args.watch {
+            match StreamClient::connect(addr) {
+                Ok(client) => scenes.push(Box::new(WatchScene::new(assets, loader, scores, &config.borrow(), client)?)),
+                Err(e) => error!("Failed to connect to {}: {}", addr, e),
+            }
+        }
+        let fullscreen = config.borrow().fullscreen;
+        let mut music = Music::new(audio);
+        music.request(scene_music(&scenes));
         Ok(SceneManager {
             // with this scaling the drawn area is scaled with the window.
             // So a user can make game window fullscreen and all sprites are scaled as well
-            scaler: ScreenScaler::with_window_size(ctx, SCR_W as i32, SCR_H as i32, ScalingMode::ShowAll)?,
-            scenes: vec![Box::new(ts)],
+            scaler: ScreenScaler::with_window_size(ctx, SCR_W as i32, SCR_H as i32, scaling)?,
+            scenes,
+            letterbox,
+            fade: Fade::Idle,
+            fade_frames,
+            overlay: None,
+            config,
+            resized: false,
+            fullscreen,
+            music,
+            sfx,
         })
     }
+
+    // Switches between borderless fullscreen and the window of the size from config. The size
+    // in config is not changed while the game is fullscreen, so it is the one to restore
+    fn set_fullscreen(&mut self, ctx: &mut Context, fullscreen: bool) -> tetra::Result {
+        window::set_fullscreen(ctx, fullscreen)?;
+        if !fullscreen {
+            let (width, height) = self.config.borrow().window_size();
+            window::set_size(ctx, width, height)?;
+        }
+        let (width, height) = window::get_size(ctx);
+        self.scaler.set_outer_size(width, height);
+        self.config.borrow_mut().fullscreen = fullscreen;
+        self.fullscreen = fullscreen;
+        self.resized = true;
+        Ok(())
+    }
+
+    fn apply(&mut self, tr: Transition) {
+        apply_transition(&mut self.scenes, tr);
+        self.music.request(scene_music(&self.scenes));
+    }
+
+    // starts fading out before the transition. A transition requested while the screen is
+    // lightening starts from the current darkness; while the screen is darkening the
+    // transition is applied at once
+    fn switch(&mut self, tr: Transition) {
+        if let Transition::None = tr {
+            return;
+        }
+        if self.fade_frames == 0 {
+            self.apply(tr);
+            return;
+        }
+        match self.fade {
+            Fade::Idle => self.fade = Fade::Out { frame: 0, next: tr },
+            Fade::In { frame } => self.fade = Fade::Out { frame: self.fade_frames - frame, next: tr },
+            Fade::Out { .. } => self.apply(tr),
+        }
+    }
+
+    // moves the fade one frame forward. Returns true if the active scene is frozen
+    fn update_fade(&mut self) -> bool {
+        match std::mem::replace(&mut self.fade, Fade::Idle) {
+            Fade::Idle => false,
+            Fade::Out { frame, next } => {
+                if frame + 1 >= self.fade_frames {
+                    self.apply(next);
+                    self.fade = Fade::In { frame: 0 };
+                } else {
+                    self.fade = Fade::Out { frame: frame + 1, next };
+                }
+                true
+            }
+            Fade::In { frame } => {
+                if frame + 1 < self.fade_frames {
+                    self.fade = Fade::In { frame: frame + 1 };
+                }
+                false
+            }
+        }
+    }
+
+    // darkness of the screen: 0.0 - transparent, 1.0 - black
+    fn fade_alpha(&self) -> f32 {
+        let frames = self.fade_frames.max(1) as f32;
+        match self.fade {
+            Fade::Idle => 0.0,
+            Fade::Out { frame, .. } => frame as f32 / frames,
+            Fade::In { frame } => 1.0 - frame as f32 / frames,
+        }
+    }
+
+    fn draw_fade(&mut self, ctx: &mut Context) -> tetra::Result {
+        let alpha = self.fade_alpha();
+        if alpha <= 0.0 {
+            return Ok(());
+        }
+        if self.overlay.is_none() {
+            let rect = Rectangle::new(0.0, 0.0, SCR_W, SCR_H);
+            self.overlay = Some(Mesh::rectangle(ctx, ShapeStyle::Fill, rect)?);
+        }
+        if let Some(overlay) = &self.overlay {
+            overlay.draw(ctx, DrawParams::new().color(Color::rgba(0.0, 0.0, 0.0, alpha)));
+        }
+        Ok(())
+    }
+}
+
+// the music of the topmost scene that has its own music
+fn scene_music(scenes: &[Box<dyn Scene>]) -> Option<Track> {
+    scenes.iter().rev().find_map(|s| s.music())
+}
+
+fn apply_transition(scenes: &mut Vec<Box<dyn Scene>>, tr: Transition) {
+    match tr {
+        Transition::None => {}
+        Transition::Push(s) => {
+            scenes.push(s);
+        }
+        Transition::Pop => {
+            scenes.pop();
+        }
+        Transition::Replace(s) => {
+            scenes.pop();
+            scenes.push(s);
+        }
+    }
+}
+
+// Tetra stops the game loop on window close without notifying the state, so the scenes that
+// are still on the stack flush their data when the manager is dropped
+impl Drop for SceneManager {
+    fn drop(&mut self) {
+        self.music.stop();
+        while let Some(mut scene) = self.scenes.pop() {
+            scene.on_quit();
+        }
+        if self.resized {
+            self.config.borrow().save();
+        }
+    }
 }
 
 impl State for SceneManager {
     fn update(&mut self, ctx: &mut Context) -> tetra::Result {
+        // F10 works in any scene: it cycles ways to scale the game screen to the window
+        if input::is_key_pressed(ctx, Key::F10) {
+            let mode = self.config.borrow_mut().next_scaling();
+            self.scaler.set_mode(mode);
+            self.resized = true;
+        }
+        // F11 works in any scene: it toggles borderless fullscreen
+        if input::is_key_pressed(ctx, Key::F11) {
+            self.set_fullscreen(ctx, !window::is_fullscreen(ctx))?;
+        }
+        // the settings scene changes the mode in config only
+        let fullscreen = self.config.borrow().fullscreen;
+        if fullscreen != self.fullscreen {
+            self.set_fullscreen(ctx, fullscreen)?;
+        }
+        // volumes are applied every frame, so a change in the settings is heard at once
+        {
+            let cfg = self.config.borrow();
+            audio::set_master_volume(ctx, cfg.master_volume);
+            self.music.set_volume(cfg.music_volume);
+            self.sfx.set_volume(cfg.sfx_volume);
+        }
+        self.music.update(ctx);
+        if self.update_fade() {
+            return Ok(());
+        }
         match self.scenes.last_mut() {
-            Some(active_scene) => match active_scene.update(ctx)? {
-                Transition::None => {}
-                Transition::Push(s) => {
-                    self.scenes.push(s);
-                }
-                Transition::Pop => {
-                    self.scenes.pop();
-                }
-            },
-            None => window::quit(ctx),
+            Some(active_scene) => {
+                // the game screen is scaled, so the mouse position must be converted
+                active_scene.set_mouse_position(self.scaler.mouse_position(ctx));
+                let tr = active_scene.update(ctx)?;
+                self.switch(tr);
+            }
+            None => {
+                self.music.stop();
+                window::quit(ctx);
+            }
         }
 
         Ok(())
@@ -56,17 +311,11 @@ impl State for SceneManager {
         match self.scenes.last_mut() {
             Some(active_scene) => {
                 graphics::set_canvas(ctx, self.scaler.canvas());
-                match active_scene.draw(ctx)? {
-                    Transition::None => {}
-                    Transition::Push(s) => {
-                        self.scenes.push(s);
-                    }
-                    Transition::Pop => {
-                        self.scenes.pop();
-                    }
-                }
+                let tr = active_scene.draw(ctx)?;
+                self.draw_fade(ctx)?;
+                self.switch(tr);
                 graphics::reset_canvas(ctx);
-                graphics::clear(ctx, Color::BLACK);
+                graphics::clear(ctx, self.letterbox);
                 self.scaler.draw(ctx);
             }
             None => window::quit(ctx),
@@ -75,10 +324,89 @@ impl State for SceneManager {
         Ok(())
     }
 
-    fn event(&mut self, _: &mut Context, event: Event) -> tetra::Result {
+    fn event(&mut self, ctx: &mut Context, event: Event) -> tetra::Result {
         if let Event::Resized { width, height } = event {
             self.scaler.set_outer_size(width, height);
+            if window::is_fullscreen(ctx) {
+                return Ok(());
+            }
+            // the size is remembered for the next start. Resizing a window produces a lot of
+            // events, so the config is written only once when the game is closed
+            let mut cfg = self.config.borrow_mut();
+            if cfg.window_width != width || cfg.window_height != height {
+                cfg.window_width = width;
+                cfg.window_height = height;
+                self.resized = true;
+            }
         }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a scene that only remembers its name
+    struct Named(&'static str);
+
+    // a scene with music
+    struct Musical(Track);
+
+    impl Scene for Musical {
+        fn music(&self) -> Option<Track> {
+            Some(self.0)
+        }
+        fn update(&mut self, _ctx: &mut Context) -> tetra::Result<Transition> {
+            Ok(Transition::None)
+        }
+        fn draw(&mut self, _ctx: &mut Context) -> tetra::Result<Transition> {
+            Ok(Transition::None)
+        }
+    }
+
+    impl Scene for Named {
+        fn update(&mut self, _ctx: &mut Context) -> tetra::Result<Transition> {
+            Ok(Transition::None)
+        }
+        fn draw(&mut self, _ctx: &mut Context) -> tetra::Result<Transition> {
+            Ok(Transition::None)
+        }
+        fn on_quit(&mut self) {
+            panic!("{} is dropped as if the game was closing", self.0);
+        }
+    }
+
+    #[test]
+    fn replace_keeps_stack_depth() {
+        let mut scenes: Vec<Box<dyn Scene>> = vec![Box::new(Named("menu"))];
+        apply_transition(&mut scenes, Transition::Push(Box::new(Named("demo"))));
+        assert_eq!(scenes.len(), 2);
+
+        // the finished demo starts the game in its place: menu -> game
+        apply_transition(&mut scenes, Transition::Replace(Box::new(Named("game"))));
+        assert_eq!(scenes.len(), 2);
+        apply_transition(&mut scenes, Transition::None);
+        assert_eq!(scenes.len(), 2);
+
+        // leaving the game returns to the menu, not to the demo
+        apply_transition(&mut scenes, Transition::Pop);
+        assert_eq!(scenes.len(), 1);
+        apply_transition(&mut scenes, Transition::Replace(Box::new(Named("title"))));
+        assert_eq!(scenes.len(), 1);
+        apply_transition(&mut scenes, Transition::Pop);
+        assert!(scenes.is_empty());
+    }
+
+    #[test]
+    fn scene_below_keeps_its_music() {
+        let mut scenes: Vec<Box<dyn Scene>> = vec![Box::new(Named("help"))];
+        assert_eq!(scene_music(&scenes), None);
+        scenes.insert(0, Box::new(Musical(Track::Menu)));
+        assert_eq!(scene_music(&scenes), Some(Track::Menu));
+        apply_transition(&mut scenes, Transition::Push(Box::new(Musical(Track::Game))));
+        assert_eq!(scene_music(&scenes), Some(Track::Game));
+        apply_transition(&mut scenes, Transition::Pop);
+        assert_eq!(scene_music(&scenes), Some(Track::Menu));
+    }
+}
@@ -1,11 +1,32 @@
 use serde_derive::{Deserialize, Serialize};
-use std::fs::{read_to_string, write};
-use std::path::PathBuf;
+use std::fs::{read_dir, read_to_string, remove_file, rename, write};
+use std::io;
+use std::path::{Path, PathBuf};
 
 use chrono::prelude::*;
-use chrono::NaiveDateTime;
+use chrono::{NaiveDate, NaiveDateTime};
+use log::{error, info};
 
-use crate::common::score_path;
+use crate::common::{replay_path, score_path, skip_write};
+use crate::field::developer_record;
+use crate::stars::{level_goal, stars_for};
+
+// Modes and assistance a result was achieved with
+#[derive(Copy, Clone, Serialize, Deserialize, Default, PartialEq)]
+pub struct ModeFlags(pub u8);
+
+impl ModeFlags {
+    pub const HARDCORE: u8 = 1; // a fail restarts the run from a checkpoint
+    pub const ASSISTED: u8 = 2; // throwable rows were highlighted or the solution was previewed
+
+    pub fn has(self, flag: u8) -> bool {
+        self.0 & flag != 0
+    }
+
+    pub fn set(&mut self, flag: u8) {
+        self.0 |= flag;
+    }
+}
 
 // a lever score info
 #[derive(Copy, Clone, Serialize, Deserialize, Default)]
@@ -15,12 +36,65 @@ pub struct Score {
     pub hiscore: u32,    // best score
     pub first_win: i32,  // date of the first win
     pub help_used: bool, // help was used before any win
+    #[serde(default)]
+    pub best_stars: u8, // the best rating of the level solutions
+    #[serde(default)]
+    pub hiscore_mode: ModeFlags, // modes the best score was achieved in
+}
+
+impl Score {
+    // the date of the first win in ISO-8601 format (YYYY-MM-DD)
+    pub fn first_win_date(&self) -> Option<String> {
+        if self.first_win <= 0 {
+            return None;
+        }
+        NaiveDate::from_num_days_from_ce_opt(self.first_win).map(|d| d.format("%Y-%m-%d").to_string())
+    }
+
+    // rating of the best result. `record` is the goal for the level
+    pub fn stars(&self, record: u32) -> u8 {
+        if self.wins == 0 {
+            0
+        } else {
+            stars_for(self.hiscore, record)
+        }
+    }
 }
 
-#[derive(Clone, Serialize, Deserialize)]
+// the number of days the daily challenge results are kept for
+const DAILY_HISTORY: usize = 30;
+
+// the best result of a daily challenge
+#[derive(Copy, Clone, Serialize, Deserialize, Default)]
+pub struct DailyScore {
+    pub day: i32,    // date of the challenge, the same format as `Score::first_win`
+    pub throws: u32, // the fewest total throws the challenge was completed with
+}
+
+impl DailyScore {
+    // the date in ISO-8601 format (YYYY-MM-DD)
+    pub fn date(&self) -> String {
+        NaiveDate::from_num_days_from_ce_opt(self.day).map_or(String::new(), |d| d.format("%Y-%m-%d").to_string())
+    }
+}
+
+// today's local date as the number of days from the Common Era
+pub fn today() -> i32 {
+    let dt: NaiveDateTime = Local::now().naive_local();
+    dt.num_days_from_ce()
+}
+
+#[derive(Clone, Serialize, Deserialize, Default)]
+// toml cannot write a plain value after an array of tables, so all plain values must go
+// before `levels` and `daily`. An empty `daily` would be written as a plain value `[]`, so
+// it is skipped
 pub struct ScoreVec {
     max_level: usize,
+    #[serde(default)]
+    best_time: u32, // the best speedrun time in seconds, 0 - no speedrun has been finished
     levels: Vec<Score>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    daily: Vec<DailyScore>, // daily challenge results, the latest day is the last one
 }
 
 pub struct Scores {
@@ -28,20 +102,48 @@ pub struct Scores {
     curr_level: usize,  // current level a player plays (used by main menu and play scene)
     lvl_cnt: usize,     // total number of levels
     file_path: PathBuf, // file path to save/load hiscores
+    persistent: bool,   // false - the scores live only in memory (e.g, in endless mode)
 }
 
 impl Scores {
     pub fn new(lvl_cnt: usize) -> Scores {
+        Scores::from_file(lvl_cnt, score_path())
+    }
+
+    // loads the scores from the file and saves them back to it
+    fn from_file(lvl_cnt: usize, file_path: PathBuf) -> Scores {
         let mut sc = Scores {
-            scores: ScoreVec { levels: Vec::new(), max_level: 1 },
+            scores: ScoreVec { max_level: 1, ..ScoreVec::default() },
             curr_level: 1,
             lvl_cnt,
-            file_path: score_path(),
+            file_path,
+            persistent: true,
         };
         sc.load();
         sc
     }
 
+    // Scores that are never loaded from or saved to disk. Used for generated levels
+    pub fn detached(lvl_cnt: usize) -> Scores {
+        Scores {
+            scores: ScoreVec { levels: vec![Score::default()], max_level: 1, ..ScoreVec::default() },
+            curr_level: 1,
+            lvl_cnt,
+            file_path: PathBuf::new(),
+            persistent: false,
+        }
+    }
+
+    // The developer best result for the level. Detached scores belong to generated levels
+    // that never have records, even though their numbers are the same as built-in ones
+    pub fn record(&self, lvl: usize) -> Option<u32> {
+        if self.persistent {
+            developer_record(lvl)
+        } else {
+            None
+        }
+    }
+
     pub fn load(&mut self) {
         if !self.file_path.exists() {
             // first start - no file, so initialize the scores with a default score info
@@ -57,12 +159,22 @@ impl Scores {
         let scores: ScoreVec = match toml::from_str(&data) {
             Ok(v) => v,
             Err(e) => {
-                eprintln!("Failed to parse config file: {:?}", e);
+                // the game starts from scratch, but the damaged file is kept aside because the next
+                // save would overwrite it
+                error!("Failed to parse hiscores file {:?}: {:?}", self.file_path, e);
+                self.backup_damaged();
+                self.scores.levels.push(Score::default());
                 return;
             }
         };
 
         self.scores = scores;
+        // hiscores saved by older versions of the game do not have star ratings
+        for (lvl, score) in self.scores.levels.iter_mut().enumerate() {
+            if score.wins != 0 && score.best_stars == 0 {
+                score.best_stars = score.stars(developer_record(lvl).unwrap_or(score.hiscore));
+            }
+        }
         // Set the current level to the maximum level a user has reached
         self.curr_level = self.scores.max_level;
         if self.scores.levels.is_empty() {
@@ -70,10 +182,85 @@ impl Scores {
         }
     }
 
+    // renames the hiscores file to `<name>.bak`
+    fn backup_damaged(&self) {
+        if skip_write("hiscores backup") {
+            return;
+        }
+        let mut bak = self.file_path.clone().into_os_string();
+        bak.push(".bak");
+        let bak = PathBuf::from(bak);
+        match rename(&self.file_path, &bak) {
+            Ok(()) => error!("The damaged hiscores file is saved as {:?}", bak),
+            Err(e) => error!("Failed to back up the damaged hiscores file to {:?}: {}", bak, e),
+        }
+    }
+
     pub fn save(&self) {
+        if !self.persistent || skip_write("hiscores") {
+            return;
+        }
         let tml = toml::to_string(&self.scores).unwrap();
-        let name = score_path();
-        let _ = write(name, tml);
+        let name = &self.file_path;
+        // the scores are written to a temporary file that replaces the old one only when it is
+        // complete: a crash in the middle of writing must not leave a truncated file
+        let mut tmp = name.clone().into_os_string();
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
+        if let Err(e) = write(&tmp, tml) {
+            error!("Failed to save hiscores to {:?}: {}", tmp, e);
+            return;
+        }
+        if let Err(e) = rename(&tmp, name) {
+            error!("Failed to replace hiscores file {:?}: {}", name, e);
+        }
+    }
+
+    // Writes the current scores to `<name>-backup-<timestamp>.toml` next to the hiscores file,
+    // e.g. `hiscores-backup-20240131-235959.toml`. Returns the path of the backup
+    pub fn backup(&self) -> io::Result<PathBuf> {
+        let tml = toml::to_string(&self.scores).map_err(io::Error::other)?;
+        let stem = self.file_path.file_stem().map_or(String::new(), |s| s.to_string_lossy().to_string());
+        let name = format!("{}-backup-{}.toml", stem, Local::now().format("%Y%m%d-%H%M%S"));
+        let path = self.file_path.with_file_name(name);
+        write(&path, tml)?;
+        Ok(path)
+    }
+
+    // Resets scores to the first start state. The old scores are backed up first, and if the
+    // backup fails, nothing is reset. Returns false if the scores are kept
+    fn reset_scores(&mut self) -> bool {
+        if self.persistent && !skip_write("hiscores backup") {
+            match self.backup() {
+                Ok(path) => info!("Hiscores are backed up to {:?}", path),
+                Err(e) => {
+                    error!("Failed to back up hiscores, the progress is not reset: {}", e);
+                    return false;
+                }
+            }
+        }
+        self.scores = ScoreVec { levels: vec![Score::default()], max_level: 1, ..ScoreVec::default() };
+        self.curr_level = 1;
+        self.save();
+        true
+    }
+
+    // Wipes all progress of the current pack: scores are reset to the first start state, and
+    // all saved replays and their backups are removed, so no level has help available
+    pub fn reset(&mut self) {
+        if !self.reset_scores() || skip_write("replays") {
+            return;
+        }
+        if let Ok(entries) = read_dir(replay_path()) {
+            for path in entries.filter_map(|e| e.ok().map(|e| e.path())) {
+                let name = path.file_name().map_or(String::new(), |n| n.to_string_lossy().to_string());
+                if path.is_file() && (name.ends_with(".rpl") || name.ends_with(".rpl.bak")) {
+                    if let Err(e) = remove_file(&path) {
+                        error!("Failed to remove {:?}: {}", path, e);
+                    }
+                }
+            }
+        }
     }
 
     pub fn level_info(&self, lvl_no: usize) -> Score {
@@ -84,8 +271,9 @@ impl Scores {
         }
     }
 
-    // save info about winning the level by a user. If it is the first time, save the date as well
-    pub fn set_win(&mut self, lvl_no: usize, throws: u32) {
+    // save info about winning the level by a user. If it is the first time, save the date as well.
+    // A result achieved with assistance never replaces a clean one
+    pub fn set_win(&mut self, lvl_no: usize, throws: u32, mode: ModeFlags) {
         if self.lvl_cnt <= lvl_no || self.scores.levels.len() + 1 < lvl_no {
             unreachable!()
         }
@@ -97,16 +285,20 @@ impl Scores {
         }
 
         let mut curr = self.scores.levels[lvl_no];
+        let goal = level_goal(self.record(lvl_no), curr.hiscore, throws);
+        curr.best_stars = curr.best_stars.max(stars_for(throws, goal));
         curr.wins += 1;
         curr.attempts += 1;
         if curr.wins == 1 {
             // first win - remember the date
-            let dt: NaiveDateTime = Local::now().naive_local();
-            let days = dt.num_days_from_ce();
-            curr.first_win = days;
+            curr.first_win = today();
         }
-        if curr.hiscore == 0 || curr.hiscore > throws {
+        let assisted = mode.has(ModeFlags::ASSISTED);
+        let curr_assisted = curr.hiscore_mode.has(ModeFlags::ASSISTED);
+        if curr.hiscore == 0 || (curr.hiscore > throws && (!assisted || curr_assisted)) || (curr_assisted && !assisted)
+        {
             curr.hiscore = if throws > 999 { 999 } else { throws };
+            curr.hiscore_mode = mode;
         }
         self.scores.levels[lvl_no] = curr;
 
@@ -152,6 +344,110 @@ impl Scores {
         }
     }
 
+    // Best results of all solved levels as (level, throws, throws minus developer record)
+    // sorted by how far under the record they are. Levels without records are excluded
+    pub fn leaderboard(&self) -> Vec<(usize, u32, i32)> {
+        let mut board: Vec<(usize, u32, i32)> = self
+            .scores
+            .levels
+            .iter()
+            .enumerate()
+            .filter(|(_, sc)| sc.wins != 0 && sc.hiscore != 0)
+            .filter_map(|(lvl, sc)| developer_record(lvl).map(|r| (lvl, sc.hiscore, sc.hiscore as i32 - r as i32)))
+            .collect();
+        board.sort_by_key(|(lvl, _, delta)| (*delta, *lvl));
+        board
+    }
+
+    // Writes statistics of all played levels to a CSV file. The date of the first win is
+    // in YYYY-MM-DD format. Levels without developer records have empty `record` column
+    pub fn export_csv(&self, path: &Path) -> io::Result<()> {
+        let mut csv = String::from("level,attempts,wins,hiscore,record,first_win,help_used\n");
+        for (lvl, sc) in self.scores.levels.iter().enumerate().skip(1) {
+            let record = developer_record(lvl).map_or(String::new(), |r| r.to_string());
+            let first_win = sc.first_win_date().unwrap_or_default();
+            csv += &format!(
+                "{},{},{},{},{},{},{}\n",
+                lvl, sc.attempts, sc.wins, sc.hiscore, record, first_win, sc.help_used
+            );
+        }
+        write(path, csv)
+    }
+
+    // Writes the same statistics as `export_csv` to a JSON file: an array with an object
+    // per level. Missing records and dates are `null`
+    pub fn export_json(&self, path: &Path) -> io::Result<()> {
+        let rows: Vec<String> = self
+            .scores
+            .levels
+            .iter()
+            .enumerate()
+            .skip(1)
+            .map(|(lvl, sc)| {
+                let record = developer_record(lvl).map_or("null".to_string(), |r| r.to_string());
+                let first_win = sc.first_win_date().map_or("null".to_string(), |d| format!("\"{}\"", d));
+                format!(
+                    concat!(
+                        "  {{\"level\": {}, \"attempts\": {}, \"wins\": {}, \"hiscore\": {}, ",
+                        "\"record\": {}, \"first_win\": {}, \"help_used\": {}}}",
+                    ),
+                    lvl, sc.attempts, sc.wins, sc.hiscore, record, first_win, sc.help_used
+                )
+            })
+            .collect();
+        write(path, format!("[\n{}\n]\n", rows.join(",\n")))
+    }
+
+    // scores of all levels that have been played at least once with their level numbers
+    pub fn levels(&self) -> impl Iterator<Item = (usize, &Score)> {
+        self.scores.levels.iter().enumerate()
+    }
+
+    pub fn best_time(&self) -> Option<u32> {
+        if self.scores.best_time == 0 {
+            None
+        } else {
+            Some(self.scores.best_time)
+        }
+    }
+
+    // saves the time of a finished speedrun if it is the best one. Returns true if it is
+    pub fn set_run_time(&mut self, secs: u32) -> bool {
+        let secs = secs.max(1);
+        if self.scores.best_time != 0 && self.scores.best_time <= secs {
+            return false;
+        }
+        self.scores.best_time = secs;
+        self.save();
+        true
+    }
+
+    pub fn daily_best(&self, day: i32) -> Option<u32> {
+        self.scores.daily.iter().find(|d| d.day == day).map(|d| d.throws)
+    }
+
+    // saves the result of the daily challenge if it is the best one for the day. Only the
+    // last `DAILY_HISTORY` days are kept. Returns true if the result is the best one
+    pub fn set_daily(&mut self, day: i32, throws: u32) -> bool {
+        match self.scores.daily.iter_mut().find(|d| d.day == day) {
+            Some(d) if d.throws <= throws => return false,
+            Some(d) => d.throws = throws,
+            None => {
+                self.scores.daily.push(DailyScore { day, throws });
+                self.scores.daily.sort_by_key(|d| d.day);
+                let extra = self.scores.daily.len().saturating_sub(DAILY_HISTORY);
+                self.scores.daily.drain(..extra);
+            }
+        }
+        self.save();
+        true
+    }
+
+    // daily challenge results starting from the latest day
+    pub fn daily_history(&self) -> impl Iterator<Item = &DailyScore> {
+        self.scores.daily.iter().rev()
+    }
+
     pub fn max_avail_level(&self) -> usize {
         self.scores.max_level
     }
@@ -170,13 +466,175 @@ impl Scores {
         self.curr_level
     }
 
+    // selects the level to play. A level the player has not reached yet is not available,
+    // so the last reached one is selected instead
+    pub fn set_curr_level(&mut self, lvl: usize) -> usize {
+        self.curr_level = lvl.clamp(1, self.scores.max_level.max(1));
+        self.curr_level
+    }
+
     // used by main menu
     pub fn dec_curr_level(&mut self, delta: usize) -> usize {
-        if self.curr_level - 1 > delta {
-            self.curr_level -= delta
-        } else {
-            self.curr_level = 1;
-        }
+        // level 0 is the demo, so the first playable level is 1
+        self.curr_level = self.curr_level.saturating_sub(delta).max(1);
         self.curr_level
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::{set_read_only, WRITE_LOCK};
+    use std::env::temp_dir;
+    use std::fs::{create_dir_all, remove_dir_all};
+
+    #[test]
+    fn scores_survive_save_and_load() {
+        let mut sc = Scores::detached(3);
+        sc.set_win(1, 10, ModeFlags::default());
+        sc.set_run_time(300);
+        sc.set_daily(today(), 25);
+        let tml = match toml::to_string(&sc.scores) {
+            Ok(tml) => tml,
+            Err(e) => panic!("Failed to save hiscores: {}", e),
+        };
+        let loaded: ScoreVec = toml::from_str(&tml).unwrap();
+        assert_eq!(loaded.best_time, 300);
+        assert_eq!(loaded.max_level, 2);
+        assert_eq!(loaded.levels[1].hiscore, 10);
+        assert_eq!(loaded.daily.len(), 1);
+        assert_eq!(loaded.daily[0].throws, 25);
+    }
+
+    #[test]
+    fn scores_without_daily_results_are_saved() {
+        let mut sc = Scores::detached(3);
+        sc.set_win(1, 10, ModeFlags::default());
+        let tml = match toml::to_string(&sc.scores) {
+            Ok(tml) => tml,
+            Err(e) => panic!("Failed to save hiscores: {}", e),
+        };
+        let loaded: ScoreVec = toml::from_str(&tml).unwrap();
+        assert!(loaded.daily.is_empty());
+        assert_eq!(loaded.levels[1].hiscore, 10);
+    }
+
+    #[test]
+    fn read_only_scores_are_not_written() {
+        let _lock = WRITE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let path = temp_dir().join("unblocked-read-only.toml");
+        let _ = remove_file(&path);
+        set_read_only(true);
+        let mut sc = Scores::from_file(3, path.clone());
+        sc.set_win(1, 10, ModeFlags::default());
+        sc.set_fail(2);
+        set_read_only(false);
+        let written = path.exists();
+        // the progress still advances for the session
+        assert_eq!((sc.level_info(1).wins, sc.level_info(2).attempts), (1, 1));
+        sc.save();
+        let saved = path.exists();
+        let _ = remove_file(&path);
+        assert!(!written);
+        assert!(saved);
+    }
+
+    #[test]
+    fn csv_has_a_row_per_level() {
+        let mut sc = Scores::detached(5);
+        sc.set_win(1, 12, ModeFlags::default());
+        sc.set_fail(2);
+        sc.set_help_used(2);
+        let path = temp_dir().join("unblocked-stats.csv");
+        sc.export_csv(&path).unwrap();
+        let csv = read_to_string(&path).unwrap();
+        let _ = remove_file(&path);
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], "level,attempts,wins,hiscore,record,first_win,help_used");
+        let record = |lvl| developer_record(lvl).map_or(String::new(), |r| r.to_string());
+        let first_win = sc.level_info(1).first_win_date().unwrap();
+        assert_eq!(lines[1], format!("1,1,1,12,{},{},false", record(1), first_win));
+        assert_eq!(lines[2], format!("2,1,0,0,{},,true", record(2)));
+    }
+
+    #[test]
+    fn json_has_an_object_per_level() {
+        let mut sc = Scores::detached(5);
+        sc.set_fail(1);
+        sc.set_help_used(1);
+        let path = temp_dir().join("unblocked-stats.json");
+        sc.export_json(&path).unwrap();
+        let json = read_to_string(&path).unwrap();
+        let _ = remove_file(&path);
+        let record = developer_record(1).map_or("null".to_string(), |r| r.to_string());
+        let row = concat!(
+            r#"  {"level": 1, "attempts": 1, "wins": 0, "hiscore": 0, "record": RECORD, "#,
+            r#""first_win": null, "help_used": true}"#,
+        )
+        .replace("RECORD", &record);
+        assert_eq!(json, format!("[\n{}\n]\n", row));
+    }
+
+    #[test]
+    fn current_level_stays_playable() {
+        let mut sc = Scores::detached(10);
+        for lvl in 1..6 {
+            sc.set_win(lvl, 10, ModeFlags::default());
+        }
+        assert_eq!(sc.curr_level(), 6);
+        assert_eq!(sc.dec_curr_level(2), 4);
+        // never below the first playable level, even when the delta is bigger than the level
+        assert_eq!(sc.dec_curr_level(3), 1);
+        assert_eq!(sc.dec_curr_level(10), 1);
+        assert_eq!(sc.inc_curr_level(100), 6);
+    }
+
+    #[test]
+    fn damaged_scores_are_backed_up() {
+        let _lock = WRITE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let path = temp_dir().join("unblocked-damaged.toml");
+        let bak = temp_dir().join("unblocked-damaged.toml.bak");
+        let _ = remove_file(&bak);
+        write(&path, "max_level = [not toml").unwrap();
+        let mut sc = Scores::from_file(3, path.clone());
+        let (moved, kept) = (!path.exists(), read_to_string(&bak).unwrap_or_default());
+        // the game starts from scratch and the next save does not touch the backup
+        assert_eq!((sc.max_avail_level(), sc.curr_level()), (1, 1));
+        sc.set_win(1, 10, ModeFlags::default());
+        let backup = read_to_string(&bak).unwrap_or_default();
+        let _ = remove_file(&path);
+        let _ = remove_file(&bak);
+        assert!(moved);
+        assert_eq!(kept, "max_level = [not toml");
+        assert_eq!(backup, kept);
+    }
+
+    #[test]
+    fn reset_backs_up_old_scores() {
+        let _lock = WRITE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        set_read_only(false);
+        let dir = temp_dir().join("unblocked-reset");
+        let _ = remove_dir_all(&dir);
+        create_dir_all(&dir).unwrap();
+        let path = dir.join("hiscores.toml");
+        let mut sc = Scores::from_file(3, path.clone());
+        sc.set_win(1, 10, ModeFlags::default());
+        let before = read_to_string(&path).unwrap_or_default();
+
+        let reset = sc.reset_scores();
+        let after = read_to_string(&path).unwrap_or_default();
+        let backups: Vec<PathBuf> = read_dir(&dir).unwrap().filter_map(|e| e.ok().map(|e| e.path())).collect();
+        let backup_names: Vec<String> =
+            backups.iter().filter_map(|p| p.file_name().map(|n| n.to_string_lossy().to_string())).collect();
+        let backup = backups.iter().find(|p| *p != &path).map(|p| read_to_string(p).unwrap_or_default());
+        let _ = remove_dir_all(&dir);
+
+        assert!(reset);
+        assert_eq!((sc.max_avail_level(), sc.level_info(1).hiscore), (1, 0));
+        assert_ne!(after, before);
+        assert_eq!(backups.len(), 2, "{:?}", backup_names);
+        assert!(backup_names.iter().any(|n| n.starts_with("hiscores-backup-") && n.ends_with(".toml")));
+        assert_eq!(backup, Some(before));
+    }
+}
@@ -0,0 +1,138 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use tetra::graphics::{self, Color};
+use tetra::math::Vec2;
+use tetra::Context;
+
+use crate::assets::Assets;
+use crate::bindings::{Command, InputBindings};
+use crate::config::Config;
+use crate::consts::SCR_W;
+use crate::i18n::Labels;
+use crate::scenes::{Scene, Transition};
+use crate::textnum::{TextNumber, TextParams};
+
+// rows of the settings in the order they are shown
+const ROW_KEYS: [&str; 4] = ["settings_master", "settings_music", "settings_sfx", "settings_fullscreen"];
+const FULLSCREEN_ROW: usize = 3;
+// a volume changes by 1/VOLUME_STEPS of the full volume with one key press
+const VOLUME_STEPS: f32 = 10.0;
+// layout of the screen
+const TITLE_Y: f32 = 80.0;
+const TOP_Y: f32 = 200.0;
+const ROW_H: f32 = 64.0;
+const LABEL_X: f32 = 220.0;
+const VALUE_X: f32 = 680.0;
+const HINT_Y: f32 = 640.0;
+const TITLE_COLOR: Color = Color::rgb(0.5, 1.0, 0.5);
+const ROW_COLOR: Color = Color::rgb(0.5, 0.5, 0.5);
+const SELECTED_COLOR: Color = Color::WHITE;
+
+// moves a volume by `steps` steps up or down and keeps it between 0.0 and 1.0. The volume is
+// rounded to a step, so a value from the config file lands on the step grid
+fn step_volume(volume: f32, steps: i32) -> f32 {
+    ((volume * VOLUME_STEPS).round() + steps as f32).clamp(0.0, VOLUME_STEPS) / VOLUME_STEPS
+}
+
+// Audio volumes and fullscreen mode. Changes are applied at once and written to the config
+// file when the scene is closed
+pub struct SettingsScene {
+    config: Rc<RefCell<Config>>,
+    labels: Labels,
+    txt_num: TextNumber,
+    keys: InputBindings,
+    row: usize, // the selected row
+}
+
+impl SettingsScene {
+    pub fn new(assets: Rc<Assets>, config: Rc<RefCell<Config>>) -> Self {
+        let keys = InputBindings::new(&config.borrow());
+        let labels = Labels::new(assets.font.clone(), &config.borrow().language);
+        SettingsScene { config, labels, txt_num: TextNumber::new(assets.numbers.clone()), keys, row: 0 }
+    }
+
+    // changes the value of the selected row: `dir` is -1 for Left and 1 for Right
+    fn change(&mut self, dir: i32) {
+        let mut cfg = self.config.borrow_mut();
+        match self.row {
+            0 => cfg.master_volume = step_volume(cfg.master_volume, dir),
+            1 => cfg.music_volume = step_volume(cfg.music_volume, dir),
+            2 => cfg.sfx_volume = step_volume(cfg.sfx_volume, dir),
+            _ => cfg.fullscreen = !cfg.fullscreen,
+        }
+    }
+}
+
+impl Scene for SettingsScene {
+    fn update(&mut self, ctx: &mut Context) -> tetra::Result<Transition> {
+        // Key processing:
+        // - Up and Down to select a row
+        // - Left and Right to change the value of the row
+        // - Escape, Space, and Return to save the settings and return to the main menu
+        if self.keys.is_pressed(ctx, Command::Back) || self.keys.is_pressed(ctx, Command::Confirm) {
+            self.config.borrow().save();
+            return Ok(Transition::Pop);
+        }
+        if self.keys.is_pressed(ctx, Command::Up) {
+            self.row = (self.row + ROW_KEYS.len() - 1) % ROW_KEYS.len();
+        } else if self.keys.is_pressed(ctx, Command::Down) {
+            self.row = (self.row + 1) % ROW_KEYS.len();
+        } else if self.keys.is_pressed(ctx, Command::Left) {
+            self.change(-1);
+        } else if self.keys.is_pressed(ctx, Command::Right) {
+            self.change(1);
+        }
+        Ok(Transition::None)
+    }
+
+    fn draw(&mut self, ctx: &mut Context) -> tetra::Result<Transition> {
+        graphics::clear(ctx, self.config.borrow().clear_color());
+        self.labels.draw_centered(ctx, SCR_W / 2.0, TITLE_Y, "settings_title", TITLE_COLOR);
+
+        let cfg = self.config.borrow().clone();
+        let volumes = [cfg.master_volume, cfg.music_volume, cfg.sfx_volume];
+        for (idx, key) in ROW_KEYS.iter().enumerate() {
+            let color = if idx == self.row { SELECTED_COLOR } else { ROW_COLOR };
+            let y = TOP_Y + idx as f32 * ROW_H;
+            self.labels.draw(ctx, Vec2::new(LABEL_X, y), key, color);
+            if idx == FULLSCREEN_ROW {
+                let value = if cfg.fullscreen { "settings_on" } else { "settings_off" };
+                self.labels.draw(ctx, Vec2::new(VALUE_X, y), value, color);
+            } else {
+                // volumes are shown in percent
+                let percent = (volumes[idx] * 100.0).round() as u32;
+                let tp = TextParams::new().with_width(3).with_right_align().with_color(color);
+                self.txt_num.draw(ctx, Vec2::new(VALUE_X, y), percent, tp);
+            }
+        }
+
+        self.labels.draw_centered(ctx, SCR_W / 2.0, HINT_Y, "settings_hint", ROW_COLOR);
+        Ok(Transition::None)
+    }
+
+    fn on_quit(&mut self) {
+        self.config.borrow().save();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn volume_steps_stay_in_range() {
+        assert_eq!(step_volume(0.5, 1), 0.6);
+        assert_eq!(step_volume(0.5, -1), 0.4);
+        assert_eq!(step_volume(1.0, 1), 1.0);
+        assert_eq!(step_volume(0.0, -1), 0.0);
+        // a hand-edited volume snaps to the nearest step
+        assert_eq!(step_volume(0.73, 0), 0.7);
+        assert_eq!(step_volume(0.73, 1), 0.8);
+        let mut v = 0.0;
+        for _ in 0..10 {
+            v = step_volume(v, 1);
+        }
+        assert_eq!(v, 1.0);
+    }
+}
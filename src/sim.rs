@@ -0,0 +1,341 @@
+use crate::board::Board;
+use crate::consts::INFO_WIDTH;
+use crate::field::{BrickKind, GameState};
+use crate::loader::Level;
+use crate::replay::Action;
+
+// Headless model of a level. It follows the same rules as `GameField` but resolves a throw
+// instantly instead of animating blocks frame by frame, so it does not need any `Context`.
+// Used by tools that have to "play" a level without showing it (e.g, replay optimizer)
+#[derive(Clone)]
+pub struct BoardSim {
+    pub board: Board,
+    walls: Vec<bool>,
+    bricks: Vec<BrickKind>,
+    pub player: BrickKind, // current kind of player's block
+    pub row: usize,        // current row of player's block
+    pub throws: u32,       // the number of throws so far
+    pub state: GameState,
+    match_n: usize, // matching blocks in a row a falling block needs to clear them
+}
+
+impl BoardSim {
+    // builds the board in the same way `GameField::load` does it
+    pub fn new(lvl: &Level) -> Self {
+        let board = lvl.board;
+        let mut sim = BoardSim {
+            board,
+            walls: vec![false; board.cells()],
+            bricks: vec![BrickKind::None; board.cells()],
+            player: lvl.first,
+            row: board.bottom(),
+            throws: 0,
+            state: GameState::Unfinished,
+            match_n: lvl.match_n,
+        };
+
+        // top and bottom lines
+        for i in 0..board.width {
+            sim.walls[board.pos(i, 0)] = true;
+            sim.walls[board.pos(i, board.height - 1)] = true;
+        }
+        // info panel
+        for i in 1..board.height - 1 {
+            sim.walls[board.pos(0, i)] = true;
+            for p in 0..INFO_WIDTH {
+                sim.walls[board.pos(board.width - p - 1, i)] = true;
+            }
+        }
+
+        // corner
+        if lvl.corner.is_empty() {
+            for i in 1..=board.max_size {
+                for j in 1..=(board.max_size - i) {
+                    sim.walls[board.pos(j, i)] = true;
+                }
+            }
+        } else {
+            for (y, line_len) in (1usize..).zip(lvl.corner.iter()) {
+                for x in 1..=*line_len as usize {
+                    sim.walls[board.pos(x, y)] = true;
+                }
+            }
+        }
+
+        let cnt = lvl.puzzle.len();
+        for (yidx, bricks) in lvl.puzzle.iter().enumerate() {
+            for (xidx, brick) in bricks.iter().enumerate() {
+                if *brick == BrickKind::None {
+                    continue;
+                }
+                sim.bricks[board.pos(xidx + 1, board.height - cnt + yidx - 1)] = *brick;
+            }
+        }
+
+        sim
+    }
+
+    // builds the board from a running game: `walls` are non-zero cells of the puzzle and
+    // `bricks` are positions and kinds of all blocks
+    pub fn from_parts(
+        board: Board,
+        walls: &[u32],
+        bricks: &[(usize, usize, BrickKind)],
+        player: BrickKind,
+        row: usize,
+        throws: u32,
+        match_n: usize,
+    ) -> Self {
+        let mut sim = BoardSim {
+            board,
+            walls: vec![false; board.cells()],
+            bricks: vec![BrickKind::None; board.cells()],
+            player,
+            row,
+            throws,
+            state: GameState::Unfinished,
+            match_n,
+        };
+        for (idx, w) in walls.iter().enumerate().take(board.cells()) {
+            sim.walls[idx] = *w != 0;
+        }
+        for (x, y, kind) in bricks.iter() {
+            sim.bricks[board.pos(*x, *y)] = *kind;
+        }
+        sim.state = sim.calc_state();
+        sim
+    }
+
+    // Hash of block positions and the kind of player's block (FNV-1a). It identifies a board
+    // position regardless of the row of player's block, and it is the same on every machine
+    pub fn board_hash(&self) -> u64 {
+        const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+        const FNV_PRIME: u64 = 0x0100_0000_01b3;
+        let mut hash = FNV_OFFSET;
+        for b in self.bricks.iter().chain(std::iter::once(&self.player)) {
+            hash ^= *b as u64;
+            hash = hash.wrapping_mul(FNV_PRIME);
+        }
+        hash
+    }
+
+    fn brick(&self, x: usize, y: usize) -> BrickKind {
+        self.bricks[self.board.pos(x, y)]
+    }
+
+    // removes a block and drops all blocks that were on top of it
+    fn remove(&mut self, x: usize, y: usize) {
+        for yy in (1..=y).rev() {
+            self.bricks[self.board.pos(x, yy)] = self.bricks[self.board.pos(x, yy - 1)];
+        }
+    }
+
+    // the number of blocks of the same kind in a row in the column starting from (x, y) down
+    fn column_run(&self, x: usize, y: usize) -> usize {
+        let kind = self.brick(x, y);
+        (y..self.board.height - 1).take_while(|yy| self.brick(x, *yy) == kind).count()
+    }
+
+    // kind of a block that player's block would hit first after throwing from the row
+    pub fn first_brick(&self, row: usize) -> BrickKind {
+        let b = self.board;
+        let down = row < b.puzzle_top() || !(1..b.width).any(|x| self.brick(x, row) != BrickKind::None);
+        if down {
+            let bx = if row >= b.puzzle_top() {
+                1
+            } else {
+                match (0..b.max_size + 4).find(|x| !self.walls[b.pos(*x, row)]) {
+                    Some(x) => x,
+                    None => return BrickKind::None,
+                }
+            };
+            (row..b.height).map(|y| self.brick(bx, y)).find(|k| *k != BrickKind::None).unwrap_or(BrickKind::None)
+        } else {
+            (1..b.width).rev().map(|x| self.brick(x, row)).find(|k| *k != BrickKind::None).unwrap_or(BrickKind::None)
+        }
+    }
+
+    // direction of a throw from the row and the cell where player's block stops before hitting
+    // the first block, in the same way `GameField` aims its arrow. None if the row is malformed
+    pub fn aim(&self, row: usize) -> Option<(bool, usize, usize)> {
+        let b = self.board;
+        let down = row < b.puzzle_top() || !(1..b.width).any(|x| self.brick(x, row) != BrickKind::None);
+        if down {
+            let bx =
+                if row >= b.puzzle_top() { 1 } else { (1..b.max_size + 4).find(|x| !self.walls[b.pos(*x, row)])? };
+            let by = (row..b.height - 1).find(|y| self.brick(bx, *y) != BrickKind::None).unwrap_or(b.height - 1);
+            Some((true, bx, by - 1))
+        } else {
+            let bx = (1..b.width).rev().find(|x| self.brick(*x, row) != BrickKind::None).unwrap_or(0);
+            Some((false, bx + 1, row))
+        }
+    }
+
+    pub fn can_throw(&self) -> bool {
+        let first = self.first_brick(self.row);
+        self.state == GameState::Unfinished
+            && first != BrickKind::None
+            && (self.player == BrickKind::Joker || self.player == first)
+    }
+
+    pub fn player_up(&mut self) {
+        if self.row > 1 {
+            self.row -= 1;
+        }
+    }
+
+    pub fn player_down(&mut self) {
+        if self.row < self.board.bottom() {
+            self.row += 1;
+        }
+    }
+
+    // Throws player's block from the current row and resolves the throw completely.
+    // Returns false if the throw was impossible
+    pub fn throw_brick(&mut self) -> bool {
+        if !self.can_throw() {
+            return false;
+        }
+        self.throws += 1;
+
+        let mut y = self.row;
+        let mut x = (1..self.board.width).filter(|x| self.brick(*x, y) != BrickKind::None).max().unwrap_or(0);
+        if x == 0 {
+            for i in 0..self.board.max_size + 4 {
+                if self.walls[self.board.pos(i, y)] {
+                    x = i;
+                }
+            }
+        }
+        x += 1;
+
+        let mut kind = self.player;
+        // player's block is moving horizontally until it hits a wall or a non-matching block
+        while !self.walls[self.board.pos(x - 1, y)] {
+            let b = self.brick(x - 1, y);
+            if b != BrickKind::None {
+                let matched = b == kind || kind == BrickKind::Joker;
+                kind = b;
+                self.remove(x - 1, y);
+                if !matched {
+                    self.finish_throw(kind);
+                    return true;
+                }
+            }
+            x -= 1;
+        }
+        // hit wall -> block falls down until it hits the floor or a non-matching block.
+        // With match-N rules too short a run of matching blocks stops the block as well
+        let mut chain = 0;
+        while y < self.board.bottom() {
+            let b = self.brick(x, y + 1);
+            if b != BrickKind::None {
+                let matched = b == kind || kind == BrickKind::Joker;
+                if matched && chain + self.column_run(x, y + 1) < self.match_n {
+                    break;
+                }
+                chain += 1;
+                kind = b;
+                self.remove(x, y + 1);
+                if !matched {
+                    break;
+                }
+            }
+            y += 1;
+        }
+        self.finish_throw(kind);
+        true
+    }
+
+    fn finish_throw(&mut self, kind: BrickKind) {
+        self.player = kind;
+        self.state = self.calc_state();
+    }
+
+    fn calc_state(&self) -> GameState {
+        if self.bricks.iter().all(|b| *b == BrickKind::None) {
+            return GameState::Winner;
+        }
+        if self.player == BrickKind::Joker {
+            return GameState::Unfinished;
+        }
+        if (1..self.board.height - 1).any(|y| self.first_brick(y) == self.player) {
+            return GameState::Unfinished;
+        }
+        GameState::Looser
+    }
+
+    // applies a replay action. Returns false if the action changed nothing
+    pub fn apply(&mut self, act: Action) -> bool {
+        let row = self.row;
+        match act {
+            Action::Up => self.player_up(),
+            Action::Down => self.player_down(),
+            Action::Throw => return self.throw_brick(),
+        }
+        row != self.row
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::field::GameField;
+    use crate::loader::Loader;
+    use crate::scores::Scores;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    // two blocks of kind 1 on top of a block of kind 3 in the first column
+    fn column_level(match_n: usize) -> Level {
+        let (k1, k2, k3) = (BrickKind::K1, BrickKind::K2, BrickKind::K3);
+        Level { puzzle: vec![vec![k1, k2], vec![k1, k2], vec![k3, k2]], first: k1, match_n, ..Level::default() }
+    }
+
+    // throws player's block down the first column from the row above the puzzle
+    fn throw_down(lvl: &Level) -> BoardSim {
+        let mut sim = BoardSim::new(lvl);
+        sim.row = sim.board.bottom() - lvl.puzzle.len();
+        assert!(sim.throw_brick());
+        sim
+    }
+
+    fn brick_count(sim: &BoardSim) -> usize {
+        sim.bricks.iter().filter(|b| **b != BrickKind::None).count()
+    }
+
+    #[test]
+    fn match_n_stops_short_runs() {
+        let sim = throw_down(&column_level(3));
+        assert_eq!(brick_count(&sim), 6);
+        assert!(sim.player == BrickKind::K1);
+
+        for match_n in [1, 2] {
+            let sim = throw_down(&column_level(match_n));
+            assert_eq!(brick_count(&sim), 3, "match:{}", match_n);
+            assert!(sim.player == BrickKind::K3);
+        }
+    }
+
+    // the animated field must end up with the same board as the simulation
+    #[test]
+    fn field_follows_match_n() {
+        for match_n in [1, 2, 3] {
+            let lvl = column_level(match_n);
+            let loader = match Loader::from_levels(vec![lvl.clone(), lvl.clone()]) {
+                Ok(loader) => Rc::new(loader),
+                Err(e) => panic!("Invalid level: {}", e),
+            };
+            let scores = Rc::new(RefCell::new(Scores::detached(loader.level_count())));
+            let mut field = GameField::headless(loader, scores);
+            field.load(1);
+            let sim = throw_down(&lvl);
+            field.set_player_row(sim.row);
+            assert!(field.throw_brick());
+            while !field.is_settled() {
+                field.step();
+            }
+            assert_eq!(field.to_sim().board_hash(), sim.board_hash(), "match:{}", match_n);
+        }
+    }
+}
@@ -0,0 +1,126 @@
+use std::collections::{HashSet, VecDeque};
+
+use crate::field::GameState;
+use crate::loader::Level;
+use crate::replay::Action;
+use crate::sim::BoardSim;
+
+// the solver gives up after checking this many different boards
+pub const MAX_BOARDS: usize = 500_000;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Search {
+    Solved(u32), // the minimal total number of throws to solve the level
+    Unsolvable,  // every sequence of throws ends with no moves
+    Unknown,     // the search hit a limit before the answer was found
+}
+
+// Breadth-first search over the boards reachable with throws from `start`. The row of
+// player's block does not matter between throws because the block can move to any row
+// for free. The search looks at most `max_depth` throws ahead and checks at most
+// `max_boards` different boards
+pub fn search(start: BoardSim, max_depth: u32, max_boards: usize) -> Search {
+    if start.state == GameState::Winner {
+        return Search::Solved(start.throws);
+    }
+    let first = start.throws;
+    let mut limited = false;
+    let mut seen: HashSet<u64> = HashSet::new();
+    let mut queue: VecDeque<BoardSim> = VecDeque::new();
+    seen.insert(start.board_hash());
+    queue.push_back(start);
+
+    while let Some(sim) = queue.pop_front() {
+        if sim.throws - first >= max_depth {
+            limited = true;
+            continue;
+        }
+        for row in 1..sim.board.height - 1 {
+            let mut next = sim.clone();
+            next.row = row;
+            if !next.throw_brick() {
+                continue;
+            }
+            if next.state == GameState::Winner {
+                return Search::Solved(next.throws);
+            }
+            if next.state == GameState::Looser || !seen.insert(next.board_hash()) {
+                continue;
+            }
+            if seen.len() > max_boards {
+                return Search::Unknown;
+            }
+            queue.push_back(next);
+        }
+    }
+    if limited {
+        Search::Unknown
+    } else {
+        Search::Unsolvable
+    }
+}
+
+// Finds the minimal number of throws that solves the level. Returns None if the level
+// cannot be solved or the search exceeds `max_boards` boards
+pub fn min_throws(lvl: &Level, max_boards: usize) -> Option<u32> {
+    match search(BoardSim::new(lvl), u32::MAX, max_boards) {
+        Search::Solved(n) => Some(n),
+        _ => None,
+    }
+}
+
+// Finds the shortest solution from `start` in the same way `search` does, but remembers how
+// every board was reached. Returns the moves and throws that solve the level, or None if the
+// level cannot be solved or the search exceeds `max_boards` boards
+pub fn solve(start: BoardSim, max_boards: usize) -> Option<Vec<Action>> {
+    // every checked board with the index of the board it was reached from and the row of the throw
+    let mut boards: Vec<(BoardSim, usize, usize)> = Vec::new();
+    let mut seen: HashSet<u64> = HashSet::new();
+    let mut queue: VecDeque<usize> = VecDeque::new();
+    seen.insert(start.board_hash());
+    let mut solved = if start.state == GameState::Winner { Some(0) } else { None };
+    boards.push((start, 0, 0));
+    queue.push_back(0);
+
+    while let (None, Some(idx)) = (solved, queue.pop_front()) {
+        for row in 1..boards[idx].0.board.height - 1 {
+            let mut next = boards[idx].0.clone();
+            next.row = row;
+            if !next.throw_brick() || next.state == GameState::Looser || !seen.insert(next.board_hash()) {
+                continue;
+            }
+            let won = next.state == GameState::Winner;
+            boards.push((next, idx, row));
+            if won {
+                solved = Some(boards.len() - 1);
+                break;
+            }
+            if seen.len() > max_boards {
+                return None;
+            }
+            queue.push_back(boards.len() - 1);
+        }
+    }
+
+    // collect the rows of throws from the end and turn them into moves
+    let mut rows = Vec::new();
+    let mut idx = solved?;
+    while idx != 0 {
+        rows.push(boards[idx].2);
+        idx = boards[idx].1;
+    }
+    let mut actions = Vec::new();
+    let mut curr = boards[0].0.row;
+    for row in rows.into_iter().rev() {
+        while curr > row {
+            actions.push(Action::Up);
+            curr -= 1;
+        }
+        while curr < row {
+            actions.push(Action::Down);
+            curr += 1;
+        }
+        actions.push(Action::Throw);
+    }
+    Some(actions)
+}
@@ -0,0 +1,93 @@
+use std::f32::consts::PI;
+
+use tetra::graphics::mesh::{Mesh, ShapeStyle};
+use tetra::graphics::{Color, DrawParams};
+use tetra::math::Vec2;
+use tetra::Context;
+
+use crate::scores::ModeFlags;
+
+// the best rating of a solved level
+pub const MAX_STARS: u8 = 3;
+// the hardest level difficulty
+pub const MAX_DIFFICULTY: u8 = 5;
+// outer radius of a star
+pub const STAR_SIZE: f32 = 8.0;
+// distance between centers of two neighbor stars
+const STAR_STEP: f32 = STAR_SIZE * 2.5;
+
+// Rating of a solution: 3 stars for matching or beating the goal, 2 stars for
+// no more than 150% of the goal, and 1 star for just solving the level
+pub fn stars_for(throws: u32, goal: u32) -> u8 {
+    if throws <= goal {
+        MAX_STARS
+    } else if throws * 2 <= goal * 3 {
+        2
+    } else {
+        1
+    }
+}
+
+// Difficulty of a level from 1 to MAX_DIFFICULTY. A level is harder if it needs more throws
+// and if it needs more throws per block: the rating grows with `par * par / bricks`.
+// The limits are chosen so the built-in levels use the whole range
+pub fn difficulty(par: u32, bricks: usize) -> u8 {
+    const LIMITS: [u32; 4] = [15, 30, 45, 60];
+    let score = par * par * 10 / bricks.max(1) as u32;
+    1 + LIMITS.iter().filter(|l| score >= **l).count() as u8
+}
+
+// The goal for a level: the developer record if the level has one. Custom levels
+// do not have records, so the player competes with their own best result
+pub fn level_goal(record: Option<u32>, best: u32, throws: u32) -> u32 {
+    match record {
+        Some(r) => r,
+        None if best == 0 => throws,
+        None => best,
+    }
+}
+
+// five-pointed star centered at (0, 0)
+pub fn star_mesh(ctx: &mut Context) -> tetra::Result<Mesh> {
+    let points: Vec<Vec2<f32>> = (0..10)
+        .map(|i| {
+            let r = if i % 2 == 0 { STAR_SIZE } else { STAR_SIZE * 0.45 };
+            let angle = PI * i as f32 / 5.0 - PI / 2.0;
+            Vec2::new(r * angle.cos(), r * angle.sin())
+        })
+        .collect();
+    Mesh::polygon(ctx, ShapeStyle::Fill, &points)
+}
+
+// Color of lit stars shows the mode the result was achieved in: gold - normal game,
+// red - hardcore mode, cyan - with assistance (the same color as the date of a level
+// solved with help)
+pub fn mode_color(mode: ModeFlags) -> Color {
+    if mode.has(ModeFlags::ASSISTED) {
+        Color::rgb(0.0, 0.7, 0.7)
+    } else if mode.has(ModeFlags::HARDCORE) {
+        Color::rgb(1.0, 0.3, 0.2)
+    } else {
+        Color::rgb(1.0, 0.8, 0.0)
+    }
+}
+
+// Draws a row of MAX_STARS stars starting from `pos` (the center of the first star).
+// The first `count` stars are lit with `lit` color
+pub fn draw_stars(ctx: &mut Context, star: &Mesh, pos: Vec2<f32>, count: u8, lit: Color) {
+    draw_star_row(ctx, star, pos, count, MAX_STARS, lit);
+}
+
+// the same as `draw_stars` but for a row of `total` stars
+pub fn draw_star_row(ctx: &mut Context, star: &Mesh, pos: Vec2<f32>, count: u8, total: u8, lit: Color) {
+    for i in 0..total {
+        let color = if i < count { lit } else { Color::rgba(1.0, 1.0, 1.0, 0.2) };
+        let p = Vec2::new(pos.x + f32::from(i) * STAR_STEP, pos.y);
+        star.draw(ctx, DrawParams::new().position(p).color(color));
+    }
+}
+
+// width of a row of stars
+pub fn stars_width() -> f32 {
+    STAR_STEP * f32::from(MAX_STARS - 1) + STAR_SIZE * 2.0
+}
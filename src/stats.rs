@@ -0,0 +1,178 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use tetra::graphics::{self, Color, DrawParams, Texture};
+use tetra::input::{self, Key};
+use tetra::math::Vec2;
+use tetra::Context;
+
+use crate::assets::Assets;
+use crate::bindings::{Command, InputBindings};
+use crate::config::Config;
+use crate::consts::SCR_W;
+use crate::field::developer_record;
+use crate::i18n::Labels;
+use crate::scenes::{Scene, Transition};
+use crate::scores::Scores;
+use crate::textnum::{TextNumber, TextParams};
+
+// column positions
+const LABEL_X: f32 = 220.0;
+const FIRST_X: f32 = 480.0;
+const SECOND_X: f32 = 600.0;
+const TOP_Y: f32 = 100.0;
+const LINE_H: f32 = 80.0;
+const DIGIT_SPACING: f32 = 2.0;
+const TITLE_Y: f32 = 30.0;
+const RESET_Y: f32 = 400.0;
+const RESET_COLOR: Color = Color::rgb(1.0, 0.3, 0.3);
+// the daily challenge results: the title and the lines of the latest days
+const DAILY_Y: f32 = 470.0;
+const DAILY_LINE_H: f32 = 40.0;
+const DAILY_DAYS: usize = 5;
+
+// Totals across all levels of the current pack
+#[derive(Default)]
+struct Totals {
+    attempts: u32,
+    wins: u32,
+    solved: u32,      // levels solved at least once
+    help_used: u32,   // solved levels that were solved with help
+    throws: u32,      // sum of hiscores of solved levels
+    beat_record: u32, // solved levels with hiscore under the developer record
+}
+
+impl Totals {
+    // all totals are 0 if nothing has been played yet
+    fn new(scores: &Scores) -> Self {
+        let mut t = Totals::default();
+        for (lvl, sc) in scores.levels() {
+            t.attempts += sc.attempts;
+            t.wins += sc.wins;
+            if sc.wins == 0 {
+                continue;
+            }
+            t.solved += 1;
+            t.throws += sc.hiscore;
+            if sc.help_used {
+                t.help_used += 1;
+            }
+            if developer_record(lvl).is_some_and(|r| sc.hiscore < r) {
+                t.beat_record += 1;
+            }
+        }
+        t
+    }
+}
+
+// Statistics of all levels. Every line is a label and two numbers:
+// attempts - total attempts and wins; solved - solved levels and how many of them were solved
+// with help (blue); throws - total throws of the best solutions and how many beat my records (green)
+pub struct StatsScene {
+    scores: Rc<RefCell<Scores>>,
+    config: Rc<RefCell<Config>>,
+    txt_num: TextNumber,
+    labels: Labels,
+    keys: InputBindings,
+    attempts_tx: Texture,
+    solved_tx: Texture,
+    throws_tx: Texture,
+    totals: Totals,
+    // Delete was pressed and the scene waits for the user to confirm the progress reset
+    reset_confirm: bool,
+}
+
+impl StatsScene {
+    pub fn new(assets: Rc<Assets>, sc: Rc<RefCell<Scores>>, config: Rc<RefCell<Config>>) -> Self {
+        let keys = InputBindings::new(&config.borrow());
+        let totals = Totals::new(&sc.borrow());
+        // the statistics do not have images with text, so they are always drawn with the font
+        let labels = Labels::new(assets.font.clone(), &config.borrow().language);
+        StatsScene {
+            txt_num: TextNumber::new(assets.numbers.clone()),
+            labels,
+            keys,
+            attempts_tx: assets.attempts.clone(),
+            solved_tx: assets.solved.clone(),
+            throws_tx: assets.throws.clone(),
+            scores: sc,
+            config,
+            totals,
+            reset_confirm: false,
+        }
+    }
+
+    fn draw_line(&mut self, ctx: &mut Context, line: usize, first: u32, second: u32, color: Color) {
+        let y = TOP_Y + LINE_H * line as f32;
+        let tx = match line {
+            0 => &self.attempts_tx,
+            1 => &self.solved_tx,
+            _ => &self.throws_tx,
+        };
+        tx.draw(ctx, DrawParams::new().position(Vec2::new(LABEL_X, y)));
+        // totals can be long, a small gap between digits makes them easier to read
+        let tp = TextParams::new().with_width(5).with_right_align().with_spacing(DIGIT_SPACING);
+        // while the reset waits for confirmation, all the numbers that are going to be wiped are red
+        let (first_color, color) = if self.reset_confirm { (RESET_COLOR, RESET_COLOR) } else { (Color::WHITE, color) };
+        self.txt_num.draw(ctx, Vec2::new(FIRST_X, y), first, tp.clone().with_color(first_color));
+        self.txt_num.draw(ctx, Vec2::new(SECOND_X, y), second, tp.with_color(color));
+    }
+
+    // the best results of the latest daily challenges: a date and the number of throws
+    fn draw_daily(&mut self, ctx: &mut Context) {
+        let days: Vec<_> = self.scores.borrow().daily_history().take(DAILY_DAYS).copied().collect();
+        if days.is_empty() {
+            return;
+        }
+        self.labels.draw_centered(ctx, SCR_W / 2.0, DAILY_Y, "daily_history", Color::WHITE);
+        let color = if self.reset_confirm { RESET_COLOR } else { Color::WHITE };
+        let tp = TextParams::new().with_width(5).with_right_align().with_color(color);
+        for (idx, d) in days.iter().enumerate() {
+            let y = DAILY_Y + DAILY_LINE_H * (idx + 1) as f32;
+            self.labels.draw_text(ctx, Vec2::new(LABEL_X, y), &d.date(), color);
+            self.txt_num.draw(ctx, Vec2::new(FIRST_X, y), d.throws, tp.clone());
+        }
+    }
+}
+
+impl Scene for StatsScene {
+    fn update(&mut self, ctx: &mut Context) -> tetra::Result<Transition> {
+        // Reset confirmation: Space and Return wipe the progress, Escape cancels the reset
+        if self.reset_confirm {
+            if self.keys.is_pressed(ctx, Command::Confirm) {
+                self.scores.borrow_mut().reset();
+                self.totals = Totals::new(&self.scores.borrow());
+                self.reset_confirm = false;
+            } else if self.keys.is_pressed(ctx, Command::Back) {
+                self.reset_confirm = false;
+            }
+            return Ok(Transition::None);
+        }
+        if input::is_key_pressed(ctx, Key::Delete) {
+            self.reset_confirm = true;
+            return Ok(Transition::None);
+        }
+        if self.keys.is_pressed(ctx, Command::Back) || self.keys.is_pressed(ctx, Command::Confirm) {
+            return Ok(Transition::Pop);
+        }
+        Ok(Transition::None)
+    }
+
+    fn draw(&mut self, ctx: &mut Context) -> tetra::Result<Transition> {
+        graphics::clear(ctx, self.config.borrow().clear_color());
+        self.labels.draw_centered(ctx, SCR_W / 2.0, TITLE_Y, "stats_title", Color::WHITE);
+        if self.reset_confirm {
+            self.labels.draw_centered(ctx, SCR_W / 2.0, RESET_Y, "stats_reset", RESET_COLOR);
+        }
+
+        let (attempts, wins) = (self.totals.attempts, self.totals.wins);
+        let (solved, help_used) = (self.totals.solved, self.totals.help_used);
+        let (throws, beat) = (self.totals.throws, self.totals.beat_record);
+        self.draw_line(ctx, 0, attempts, wins, Color::WHITE);
+        self.draw_line(ctx, 1, solved, help_used, Color::rgb(0.0, 0.3, 0.8));
+        self.draw_line(ctx, 2, throws, beat, Color::rgb(0.0, 0.8, 0.3));
+        self.draw_daily(ctx);
+
+        Ok(Transition::None)
+    }
+}
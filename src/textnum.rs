@@ -1,12 +1,16 @@
+use tetra::graphics::mesh::{Mesh, ShapeStyle};
 use tetra::graphics::{Color, DrawParams, Rectangle, Texture};
 use tetra::math::Vec2;
 use tetra::Context;
 
-// A struct that can draw a number digit by digit using a texture with 10 digits
+// A struct that can draw a number digit by digit using a texture with 10 digits.
+// The texture is a single row of digits 0..9 of the same width. There is no colon glyph:
+// `draw_time` draws colons as two small squares
 pub struct TextNumber {
-    digits: Texture, //texture with 10 digits (0..9)
-    digit_w: f32,    // width of a digit (all digits have the same width)
-    digit_h: f32,    // height of a digit
+    digits: Texture,     //texture with 10 digits (0..9)
+    digit_w: f32,        // width of a digit (all digits have the same width)
+    digit_h: f32,        // height of a digit
+    colon: Option<Mesh>, // a dot of a colon, created at first `draw_time` call
 }
 
 // Parameters to display a number
@@ -19,67 +23,61 @@ pub struct TextParams {
     leading_zeroes: bool,
     // used only if width is set and leading_zeroes is off
     right_align: bool,
+    // used only if width is set: digits start at the start position, and the number always
+    // takes `width` digits, so the next element drawn after it is in the same column
+    left_align: bool,
+    // extra horizontal gap between consecutive digits in pixels
+    spacing: f32,
     // optional color tint
     color: Option<Color>,
 }
 
 impl TextParams {
     pub fn new() -> Self {
-        TextParams { width: 0, leading_zeroes: false, right_align: false, color: None }
+        TextParams { width: 0, leading_zeroes: false, right_align: false, left_align: false, spacing: 0.0, color: None }
     }
     pub fn with_width(self, w: u8) -> Self {
         TextParams { width: w, ..self }
     }
     pub fn with_right_align(self) -> Self {
-        TextParams { right_align: true, ..self }
+        TextParams { right_align: true, left_align: false, ..self }
+    }
+    pub fn with_left_align(self) -> Self {
+        TextParams { left_align: true, right_align: false, ..self }
     }
     pub fn with_leading_zeroes(self) -> Self {
         TextParams { leading_zeroes: true, ..self }
     }
+    pub fn with_spacing(self, px: f32) -> Self {
+        TextParams { spacing: px, ..self }
+    }
     pub fn with_color(self, c: Color) -> Self {
         TextParams { color: Some(c), ..self }
     }
 }
 
 impl TextNumber {
-    pub fn new(ctx: &mut Context, bytes: &[u8]) -> tetra::Result<TextNumber> {
-        let mut tx = TextNumber { digits: Texture::from_encoded(ctx, bytes)?, digit_w: 0.0, digit_h: 0.0 };
-        tx.digit_w = (tx.digits.width() / 10) as f32;
-        tx.digit_h = tx.digits.height() as f32;
-        Ok(tx)
+    pub fn new(digits: Texture) -> TextNumber {
+        let digit_w = (digits.width() / 10) as f32;
+        let digit_h = digits.height() as f32;
+        TextNumber { digits, digit_w, digit_h, colon: None }
     }
 
     pub fn digit_size(&self) -> Vec2<f32> {
         Vec2::new(self.digit_w, self.digit_h)
     }
 
-    pub fn draw(&mut self, ctx: &mut Context, start_pos: Vec2<f32>, n: u32, param: TextParams) {
-        let mut d: Vec<u32> = Vec::new();
-
-        // split a number into its digits
-        if n == 0 {
-            d.push(0);
-        } else {
-            let mut n = n;
-            while n > 0 {
-                let m = n % 10;
-                n /= 10;
-                d.insert(0, m);
-            }
-        }
-
-        // add extra zeroes if required
-        if param.width != 0 && param.leading_zeroes {
-            while d.len() < param.width as usize {
-                d.insert(0, 0);
-            }
-        }
+    // width of a number of `cnt` digits drawn with the parameters
+    pub fn number_width(&self, cnt: u8, param: &TextParams) -> f32 {
+        digits_width(cnt, self.digit_w, param)
+    }
 
-        // fix starting position if the number is right aligned
-        let mut p: Vec2<f32> = start_pos;
-        if param.width != 0 && param.right_align && d.len() < param.width as usize {
-            p = Vec2::new(p.x + ((param.width as usize) - d.len()) as f32 * self.digit_w, p.y);
-        }
+    // Returns the advance width: the distance from the start position to where the next element
+    // can be drawn. An aligned number with width takes at least `width` digits
+    pub fn draw(&mut self, ctx: &mut Context, start_pos: Vec2<f32>, n: u32, param: TextParams) -> f32 {
+        let (d, shift, advance) = layout(n, &param, self.digit_w);
+        let mut p = Vec2::new(start_pos.x + shift, start_pos.y);
+        let step = self.digit_w + param.spacing;
 
         // show digits one by one
         for digit in d {
@@ -89,7 +87,180 @@ impl TextNumber {
                 dp = dp.color(c);
             }
             self.digits.draw_region(ctx, clip, dp);
-            p = Vec2::new(p.x + self.digit_w, p.y);
+            p = Vec2::new(p.x + step, p.y);
+        }
+        advance
+    }
+
+    // draws time in seconds as `MM:SS`, or `H:MM:SS` if it is an hour or longer. Only the color
+    // of the parameters is used. A colon takes half of a digit width
+    pub fn draw_time(&mut self, ctx: &mut Context, start_pos: Vec2<f32>, secs: u32, param: TextParams) {
+        let dot = (self.digit_w * 0.2).max(2.0);
+        if self.colon.is_none() {
+            self.colon = Mesh::rectangle(ctx, ShapeStyle::Fill, Rectangle::new(0.0, 0.0, dot, dot)).ok();
+        }
+        let param = TextParams { width: 2, leading_zeroes: true, ..param }.with_left_align();
+        let mut parts = vec![secs / 60 % 60, secs % 60];
+        let mut first_param = param.clone();
+        if secs >= 3600 {
+            parts.insert(0, secs / 3600);
+            first_param = TextParams { width: 0, ..param.clone() };
+        }
+
+        let mut pos = start_pos;
+        for (idx, part) in parts.iter().enumerate() {
+            if idx != 0 {
+                // colon
+                if let Some(colon) = &self.colon {
+                    let color = param.color.unwrap_or(Color::WHITE);
+                    let x = pos.x + (self.digit_w * 0.5 - dot) * 0.5;
+                    for y in [self.digit_h / 3.0, self.digit_h * 2.0 / 3.0] {
+                        let dp = DrawParams::new().position(Vec2::new(x, pos.y + y - dot * 0.5)).color(color);
+                        colon.draw(ctx, dp);
+                    }
+                }
+                pos.x += self.digit_w * 0.5;
+            }
+            let tp = if idx == 0 { first_param.clone() } else { param.clone() };
+            pos.x += self.draw(ctx, pos, *part, tp);
+        }
+    }
+}
+
+fn digits_width(cnt: u8, digit_w: f32, param: &TextParams) -> f32 {
+    if cnt == 0 {
+        return 0.0;
+    }
+    f32::from(cnt) * digit_w + f32::from(cnt - 1) * param.spacing
+}
+
+// Splits a number into the digits to draw. Returns the digits, the horizontal shift of the
+// first digit from the start position, and the advance width of the number
+fn layout(n: u32, param: &TextParams, digit_w: f32) -> (Vec<u32>, f32, f32) {
+    let mut d: Vec<u32> = Vec::new();
+
+    // split a number into its digits
+    if n == 0 {
+        d.push(0);
+    } else {
+        let mut n = n;
+        while n > 0 {
+            let m = n % 10;
+            n /= 10;
+            d.insert(0, m);
+        }
+    }
+
+    // add extra zeroes if required
+    if param.width != 0 && param.leading_zeroes {
+        while d.len() < param.width as usize {
+            d.insert(0, 0);
+        }
+    }
+
+    // fix starting position if the number is right aligned
+    let mut shift = 0.0;
+    if param.width != 0 && param.right_align && d.len() < param.width as usize {
+        shift = ((param.width as usize) - d.len()) as f32 * (digit_w + param.spacing);
+    }
+
+    let mut advance = digits_width(d.len() as u8, digit_w, param);
+    if param.width != 0 && (param.left_align || param.right_align) {
+        advance = advance.max(digits_width(param.width, digit_w, param));
+    }
+    (d, shift, advance)
+}
+
+// glyphs of `BitmapText` in the order they are in its texture: left to right, top to bottom
+const GLYPHS: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789.,:!?-'/()";
+
+// A struct that draws text using a texture with glyphs of the same size arranged in a grid.
+// Lowercase letters are drawn as uppercase ones, and characters without a glyph as spaces.
+// Only color and spacing of the parameters are used
+pub struct BitmapText {
+    glyphs: Texture, // texture with all GLYPHS
+    columns: usize,  // glyphs in a row of the texture
+    glyph_w: f32,
+    glyph_h: f32,
+}
+
+impl BitmapText {
+    pub fn new(glyphs: Texture, columns: usize, rows: usize) -> BitmapText {
+        let glyph_w = (glyphs.width() as usize / columns) as f32;
+        let glyph_h = (glyphs.height() as usize / rows) as f32;
+        BitmapText { glyphs, columns, glyph_w, glyph_h }
+    }
+
+    pub fn glyph_w(&self) -> f32 {
+        self.glyph_w
+    }
+
+    pub fn glyph_h(&self) -> f32 {
+        self.glyph_h
+    }
+
+    pub fn text_width(&self, text: &str, param: &TextParams) -> f32 {
+        let cnt = text.chars().count();
+        if cnt == 0 {
+            return 0.0;
         }
+        cnt as f32 * self.glyph_w + (cnt - 1) as f32 * param.spacing
+    }
+
+    // Returns the advance width like `TextNumber::draw` does
+    pub fn draw(&mut self, ctx: &mut Context, start_pos: Vec2<f32>, text: &str, param: TextParams) -> f32 {
+        let mut p = start_pos;
+        for c in text.chars() {
+            if let Some(idx) = GLYPHS.find(c.to_ascii_uppercase()) {
+                let x = (idx % self.columns) as f32 * self.glyph_w;
+                let y = (idx / self.columns) as f32 * self.glyph_h;
+                let clip = Rectangle::new(x, y, self.glyph_w, self.glyph_h);
+                let mut dp = DrawParams::new().position(p);
+                if let Some(c) = param.color {
+                    dp = dp.color(c);
+                }
+                self.glyphs.draw_region(ctx, clip, dp);
+            }
+            p.x += self.glyph_w + param.spacing;
+        }
+        self.text_width(text, &param)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aligned_numbers_keep_their_column() {
+        // a plain number takes only its digits
+        let (d, shift, advance) = layout(7, &TextParams::new().with_width(3), 10.0);
+        assert_eq!(d, vec![7]);
+        assert_eq!((shift, advance), (0.0, 10.0));
+
+        // left aligned: digits at the start, the next element after the full width
+        let (d, shift, advance) = layout(7, &TextParams::new().with_width(3).with_left_align(), 10.0);
+        assert_eq!(d, vec![7]);
+        assert_eq!((shift, advance), (0.0, 30.0));
+
+        // right aligned: digits shifted to the end of the same width
+        let param = TextParams::new().with_width(3).with_spacing(2.0).with_right_align();
+        let (d, shift, advance) = layout(42, &param, 10.0);
+        assert_eq!(d, vec![4, 2]);
+        assert_eq!((shift, advance), (12.0, 34.0));
+
+        // the alignments exclude each other
+        let param = TextParams::new().with_width(3).with_right_align().with_left_align();
+        assert_eq!(layout(7, &param, 10.0).1, 0.0);
+
+        // a number wider than width is never cut
+        let (d, _, advance) = layout(12345, &TextParams::new().with_width(3).with_left_align(), 10.0);
+        assert_eq!(d, vec![1, 2, 3, 4, 5]);
+        assert_eq!(advance, 50.0);
+
+        let (d, shift, advance) =
+            layout(5, &TextParams::new().with_width(2).with_leading_zeroes().with_left_align(), 10.0);
+        assert_eq!(d, vec![0, 5]);
+        assert_eq!((shift, advance), (0.0, 20.0));
     }
 }
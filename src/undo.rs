@@ -0,0 +1,142 @@
+use std::collections::VecDeque;
+use std::mem::size_of;
+
+use crate::field::{BrickKind, GameState};
+
+// A block at rest: its position in whole blocks and its kind
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Block {
+    pub x: u8,
+    pub y: u8,
+    pub kind: BrickKind,
+}
+
+// The change of the board made by a throw. Only the blocks that changed are kept, so a throw
+// that clears a couple of blocks takes a few dozen bytes whatever the size of the board is
+pub struct Delta {
+    removed: Vec<Block>, // blocks that were on the board before the throw and are gone after it
+    added: Vec<Block>,   // blocks that are on the board after the throw only, e.g. fallen ones
+    pub player: Block,   // player's block before the throw
+    pub score: u32,
+    pub state: GameState,
+}
+
+impl Delta {
+    pub fn new(before: &[Block], after: &[Block], player: Block, score: u32, state: GameState) -> Delta {
+        let removed = before.iter().filter(|b| !after.contains(b)).copied().collect();
+        let added = after.iter().filter(|b| !before.contains(b)).copied().collect();
+        Delta { removed, added, player, score, state }
+    }
+
+    // the blocks before the throw made from the blocks after it
+    pub fn revert(&self, after: &[Block]) -> Vec<Block> {
+        let mut blocks: Vec<Block> = after.iter().filter(|b| !self.added.contains(b)).copied().collect();
+        blocks.extend_from_slice(&self.removed);
+        blocks
+    }
+
+    // memory the delta takes in bytes
+    pub fn size(&self) -> usize {
+        size_of::<Delta>() + (self.removed.len() + self.added.len()) * size_of::<Block>()
+    }
+}
+
+// Deltas of the last throws, the latest is the last one. When the deltas take more memory
+// than the budget, the oldest ones are discarded
+pub struct UndoStack {
+    deltas: VecDeque<Delta>,
+    bytes: usize,  // memory all deltas take
+    budget: usize, // the most memory the deltas can take in bytes
+}
+
+impl UndoStack {
+    pub fn new(budget: usize) -> UndoStack {
+        UndoStack { deltas: VecDeque::new(), bytes: 0, budget }
+    }
+
+    pub fn set_budget(&mut self, budget: usize) {
+        self.budget = budget;
+        self.evict();
+    }
+
+    pub fn push(&mut self, delta: Delta) {
+        self.bytes += delta.size();
+        self.deltas.push_back(delta);
+        self.evict();
+    }
+
+    pub fn pop(&mut self) -> Option<Delta> {
+        let delta = self.deltas.pop_back()?;
+        self.bytes -= delta.size();
+        Some(delta)
+    }
+
+    pub fn clear(&mut self) {
+        self.deltas.clear();
+        self.bytes = 0;
+    }
+
+    // a delta bigger than the whole budget is discarded as well
+    fn evict(&mut self) {
+        while self.bytes > self.budget {
+            match self.deltas.pop_front() {
+                Some(delta) => self.bytes -= delta.size(),
+                None => break,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(x: u8, y: u8, kind: BrickKind) -> Block {
+        Block { x, y, kind }
+    }
+
+    // a delta of a throw that cleared `cleared` blocks
+    fn throw_delta(score: u32, cleared: u8) -> Delta {
+        let before: Vec<Block> = (0..cleared).map(|x| block(x, 5, BrickKind::K1)).collect();
+        Delta::new(&before, &[], block(9, 5, BrickKind::K1), score, GameState::Unfinished)
+    }
+
+    #[test]
+    fn oldest_throws_are_evicted_at_budget() {
+        let size = throw_delta(0, 3).size();
+        let mut stack = UndoStack::new(size * 2);
+        stack.push(throw_delta(1, 3));
+        stack.push(throw_delta(2, 3));
+        // exactly at the budget: nothing is discarded
+        assert_eq!((stack.deltas.len(), stack.bytes), (2, size * 2));
+        stack.push(throw_delta(3, 3));
+        assert_eq!((stack.deltas.len(), stack.bytes), (2, size * 2));
+
+        // a smaller budget keeps only the latest throw
+        stack.set_budget(size * 2 - 1);
+        assert_eq!(stack.deltas.len(), 1);
+        assert_eq!(stack.pop().map(|d| d.score), Some(3));
+        assert!(stack.deltas.is_empty());
+        assert_eq!(stack.bytes, 0);
+
+        // a throw bigger than the budget cannot be undone
+        stack.push(throw_delta(4, 100));
+        assert!(stack.deltas.is_empty());
+        assert_eq!(stack.bytes, 0);
+    }
+
+    #[test]
+    fn delta_restores_board() {
+        use BrickKind::{K1, K2, K3};
+        // a K1 block is cleared, and the K2 block above it falls by one row
+        let before = vec![block(1, 4, K3), block(2, 4, K2), block(2, 5, K1), block(3, 5, K3)];
+        let after = vec![block(1, 4, K3), block(2, 5, K2), block(3, 5, K3)];
+        let delta = Delta::new(&before, &after, block(9, 5, K1), 7, GameState::Unfinished);
+        assert_eq!(delta.removed, vec![block(2, 4, K2), block(2, 5, K1)]);
+        assert_eq!(delta.added, vec![block(2, 5, K2)]);
+
+        let mut restored = delta.revert(&after);
+        restored.sort_by_key(|b| (b.x, b.y));
+        assert_eq!(restored, before);
+    }
+}
@@ -0,0 +1,124 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use log::warn;
+use tetra::graphics;
+use tetra::Context;
+
+use crate::assets::Assets;
+use crate::audio::Track;
+use crate::bindings::{Command, InputBindings};
+use crate::config::Config;
+use crate::field::GameField;
+use crate::loader::Loader;
+use crate::net::{Message, StreamClient};
+use crate::replay::Action;
+use crate::scenes::{Scene, Transition};
+use crate::scores::Scores;
+use crate::sim::BoardSim;
+
+// the number of frames a spectator stays behind the game to smooth out network lags
+const WATCH_DELAY: usize = 6;
+// if a spectator is behind the game by more frames, it fast-forwards
+const CATCH_UP: usize = 30;
+
+// Mirrors a game streamed by another instance of the game
+pub struct WatchScene {
+    field: GameField,
+    loader: Rc<Loader>,
+    client: StreamClient,
+    queue: VecDeque<Message>, // received messages that are not applied yet
+    loaded: bool,             // the first level has been received
+    keys: InputBindings,
+}
+
+impl WatchScene {
+    pub fn new(
+        assets: Rc<Assets>,
+        ld: Rc<Loader>,
+        sc: Rc<RefCell<Scores>>,
+        config: &Config,
+        client: StreamClient,
+    ) -> tetra::Result<Self> {
+        let mut field = GameField::new(&assets, ld.clone(), sc, true)?;
+        field.clear_color = config.clear_color();
+        let keys = InputBindings::new(config);
+        Ok(WatchScene { field, loader: ld, client, queue: VecDeque::new(), loaded: false, keys })
+    }
+
+    // Starts the level the player has started. Both instances must have the same level set,
+    // otherwise the moves make no sense
+    fn start_level(&mut self, lvl: usize, hash: u64) -> bool {
+        if lvl >= self.loader.level_count() || BoardSim::new(&self.loader.level(lvl)).board_hash() != hash {
+            warn!("Level {} of the streamed game does not match the local one", lvl);
+            return false;
+        }
+        self.field.load(lvl);
+        self.loaded = true;
+        true
+    }
+}
+
+impl Scene for WatchScene {
+    fn music(&self) -> Option<Track> {
+        Some(Track::Game)
+    }
+
+    fn update(&mut self, ctx: &mut Context) -> tetra::Result<Transition> {
+        if self.keys.is_pressed(ctx, Command::Back) {
+            return Ok(Transition::Pop);
+        }
+
+        match self.client.poll() {
+            Ok(msgs) => self.queue.extend(msgs),
+            Err(e) => {
+                warn!("Spectator stream closed: {}", e);
+                return Ok(Transition::Pop);
+            }
+        }
+
+        // normally one frame of the game is repeated per frame
+        let pending = self.queue.iter().filter(|m| matches!(m, Message::Step)).count();
+        let mut steps = if pending > CATCH_UP {
+            pending - WATCH_DELAY
+        } else if pending > WATCH_DELAY {
+            1
+        } else {
+            0
+        };
+        while steps > 0 {
+            match self.queue.pop_front() {
+                None => break,
+                Some(Message::Step) => {
+                    self.field.step();
+                    steps -= 1;
+                }
+                Some(Message::Level(lvl, hash)) => {
+                    if !self.start_level(lvl, hash) {
+                        return Ok(Transition::Pop);
+                    }
+                }
+                Some(Message::Move(_, act)) => match act {
+                    Action::Up => self.field.player_up(),
+                    Action::Down => self.field.player_down(),
+                    Action::Throw => {
+                        self.field.throw_brick();
+                    }
+                },
+            }
+        }
+
+        self.field.animate(ctx);
+        Ok(Transition::None)
+    }
+
+    fn draw(&mut self, ctx: &mut Context) -> tetra::Result<Transition> {
+        if !self.loaded {
+            graphics::clear(ctx, self.field.clear_color);
+            return Ok(Transition::None);
+        }
+        let _ = self.field.draw(ctx)?;
+        Ok(Transition::None)
+    }
+}